@@ -1,50 +1,706 @@
-use std::error::Error;
+//! The client ties a connection to Steam together with its handlers, and keeps it alive.
+//!
+//! [SteamClient::run] connects to a CM, waits for the channel to be encrypted, logs on and serves the connection
+//! until it drops. It then reconnects to the next CM, waiting longer after every failure in a row, as told by the
+//! [ReconnectPolicy].
 
-use steamid_parser::SteamID;
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
-use crate::{config::SteamConfiguration, connection::SteamConnection};
+use steam_language_gen::generated::enums::EResult;
+use tokio::sync::Notify;
+use tokio::time::Instant;
 
+use crate::connection::ConnectionHandle;
+use crate::connection::SteamConnection;
+use crate::errors::ClientError;
+use crate::errors::ConnectionError;
+use crate::errors::LogonError;
+use crate::events::forward_connection;
+use crate::events::EventBus;
+use crate::handlers::friend_messages::FriendMessages;
+use crate::handlers::steam_apps::SteamApps;
+use crate::handlers::steam_authentication::SteamAuthentication;
+use crate::handlers::steam_chat_rooms::SteamChatRooms;
+use crate::handlers::steam_friends::SteamFriends;
+use crate::handlers::steam_game_coordinator::SteamGameCoordinator;
+use crate::handlers::steam_unified_messages::SteamUnifiedMessages;
+use crate::handlers::steam_user::LogOnDetails;
+use crate::handlers::steam_user::SteamUser;
+use crate::server_list::CmServer;
+use crate::server_list::ServerList;
+use crate::transport::TcpTransport;
+use crate::transport::Transport;
+
+/// How long we wait for a CM to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long we wait for the channel to be encrypted and Steam to answer our logon.
+const LOGON_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// When and how often to reconnect after the connection drops.
+///
+/// The delay doubles after every failure in a row, starting at `initial_delay` and never going above `max_delay`.
+/// A connection that made it to logon resets the count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first reconnection.
+    pub initial_delay: Duration,
+    /// Upper bound for the delay.
+    pub max_delay: Duration,
+    /// How many failures in a row we put up with before giving up, or `None` to retry forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Never reconnect, [SteamClient::run] returns as soon as the first connection is gone.
+    pub fn never() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the given reconnection attempt, counting from zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    fn gives_up_after(&self, failures: u32) -> bool {
+        matches!(self.max_attempts, Some(max_attempts) if failures > max_attempts)
+    }
+}
+
+/// Builds a [SteamClient].
 #[derive(Debug)]
-pub struct SteamClient<S>
-where
-    S: AsyncRead + AsyncWrite,
-{
-    /// Could be standard tcp or websockets (default).
-    connection: SteamConnection<S>,
-    /// Configuration to be used.
-    configuration: SteamConfiguration,
-    /// Server list.
-    server_list: Vec<String>,
-    /// SteamID.
-    steam_id: SteamID,
-    /// Your API Key if you want to do some commands
-    api_key: Option<String>,
-    /// CellID it is about the region you are going to fetch Steam servers
-    cell_id: Option<String>,
-}
-
-impl<S> SteamClient<S>
-where
-    S: AsyncRead + AsyncWrite,
-{
-    /// Constructs a basic steam client
-    pub fn builder() {}
-
-    pub fn with_configuration(&mut self, cfg: SteamConfiguration) {}
-
-    pub async fn run(&self) -> Result<(), Box<dyn Error>> {
-        unimplemented!()
-    }
-}
-
-// we need to have handlers for categorized events (friends, trading, profile actions.. etc)
-// and each of these handlers need to have one callback for each action
-//
-// The example would be the user related actions: [SteamUser] with the Handler trait
-// It has a callback for the logged on event
-
-// user defined callback
-// fn on_logged_on() {
-//    println!("Successfully logged in!");
-// }
+pub struct SteamClientBuilder {
+    logon_details: LogOnDetails,
+    server_list: ServerList,
+    transport: Arc<dyn Transport>,
+    cell_id: Option<u32>,
+    reconnect_policy: ReconnectPolicy,
+}
+
+impl Default for SteamClientBuilder {
+    fn default() -> Self {
+        Self {
+            logon_details: LogOnDetails::Anonymous,
+            server_list: ServerList::default(),
+            transport: Arc::new(TcpTransport),
+            cell_id: None,
+            reconnect_policy: ReconnectPolicy::default(),
+        }
+    }
+}
+
+impl SteamClientBuilder {
+    /// Credentials used to log on, anonymous by default.
+    pub fn logon_details(mut self, logon_details: LogOnDetails) -> Self {
+        self.logon_details = logon_details;
+        self
+    }
+
+    /// CM servers to connect to, only the ones matching the transport are used.
+    ///
+    /// By default they are fetched from the Steam Directory.
+    pub fn server_list(mut self, server_list: ServerList) -> Self {
+        self.server_list = server_list;
+        self
+    }
+
+    /// How to talk to CM servers, tcp by default.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Region used to fetch nearby CM servers. Updated with the one Steam assigns us on logon.
+    pub fn cell_id(mut self, cell_id: u32) -> Self {
+        self.cell_id = Some(cell_id);
+        self
+    }
+
+    /// When to reconnect after the connection drops.
+    pub fn reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    /// Builds the client. Nothing happens until [SteamClient::run] is called.
+    pub fn build(self) -> SteamClient {
+        SteamClient {
+            inner: Arc::new(ClientInner {
+                logon_details: self.logon_details,
+                server_list: self.server_list,
+                transport: self.transport,
+                cell_id: AtomicU32::new(self.cell_id.unwrap_or(0)),
+                reconnect_policy: self.reconnect_policy,
+                events: EventBus::new(),
+                handlers: Mutex::new(None),
+                running: AtomicBool::new(false),
+                stopping: AtomicBool::new(false),
+                stopped: Notify::new(),
+            }),
+        }
+    }
+}
+
+/// Handlers bound to the current connection.
+#[derive(Debug, Clone)]
+struct Handlers {
+    connection: ConnectionHandle,
+    steam_user: SteamUser,
+    steam_friends: SteamFriends,
+    friend_messages: FriendMessages,
+    chat_rooms: SteamChatRooms,
+    unified_messages: SteamUnifiedMessages,
+    authentication: SteamAuthentication,
+    steam_apps: SteamApps,
+    game_coordinator: SteamGameCoordinator,
+}
+
+impl Handlers {
+    fn new(connection: ConnectionHandle, events: &EventBus) -> Self {
+        Self {
+            steam_user: SteamUser::new(connection.clone(), events.clone()),
+            steam_friends: SteamFriends::new(connection.clone(), events.clone()),
+            friend_messages: FriendMessages::new(connection.clone(), events.clone()),
+            chat_rooms: SteamChatRooms::new(connection.clone(), events.clone()),
+            unified_messages: SteamUnifiedMessages::new(connection.clone()),
+            authentication: SteamAuthentication::new(connection.clone()),
+            steam_apps: SteamApps::new(connection.clone()),
+            game_coordinator: SteamGameCoordinator::new(connection.clone(), events.clone()),
+            connection,
+        }
+    }
+}
+
+struct ClientInner {
+    logon_details: LogOnDetails,
+    server_list: ServerList,
+    transport: Arc<dyn Transport>,
+    cell_id: AtomicU32,
+    reconnect_policy: ReconnectPolicy,
+    /// Where handlers publish their events, outliving any single connection.
+    events: EventBus,
+    handlers: Mutex<Option<Handlers>>,
+    running: AtomicBool,
+    stopping: AtomicBool,
+    stopped: Notify,
+}
+
+/// Outcome of a single connection.
+struct Served {
+    logged_on: bool,
+    result: Result<(), ConnectionError>,
+}
+
+/// Client to the Steam network, that stays connected and logged on for as long as it runs.
+///
+/// Cheap to clone, every clone drives the same client.
+#[derive(Clone)]
+pub struct SteamClient {
+    inner: Arc<ClientInner>,
+}
+
+impl fmt::Debug for SteamClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SteamClient")
+            .field("logon_details", &self.inner.logon_details)
+            .field("transport", &self.inner.transport)
+            .field("cell_id", &self.inner.cell_id)
+            .field("reconnect_policy", &self.inner.reconnect_policy)
+            .field("running", &self.inner.running)
+            .finish()
+    }
+}
+
+impl SteamClient {
+    /// Starts building a client.
+    pub fn builder() -> SteamClientBuilder {
+        SteamClientBuilder::default()
+    }
+
+    /// CM servers this client connects to, and how well they served it.
+    pub fn server_list(&self) -> &ServerList {
+        &self.inner.server_list
+    }
+
+    /// Event bus of this client, to subscribe to typed events or add handlers for any [EMsg].
+    ///
+    /// Subscriptions survive reconnects, so they can be set up before running.
+    ///
+    /// [EMsg]: steam_language_gen::generated::enums::EMsg
+    pub fn events(&self) -> &EventBus {
+        &self.inner.events
+    }
+
+    /// [SteamUser] of the current connection, if any.
+    pub fn steam_user(&self) -> Option<SteamUser> {
+        self.current_handlers().map(|handlers| handlers.steam_user)
+    }
+
+    /// [SteamFriends] of the current connection, if any.
+    pub fn steam_friends(&self) -> Option<SteamFriends> {
+        self.current_handlers().map(|handlers| handlers.steam_friends)
+    }
+
+    /// [FriendMessages] of the current connection, if any.
+    pub fn friend_messages(&self) -> Option<FriendMessages> {
+        self.current_handlers().map(|handlers| handlers.friend_messages)
+    }
+
+    /// [SteamChatRooms] of the current connection, if any.
+    pub fn chat_rooms(&self) -> Option<SteamChatRooms> {
+        self.current_handlers().map(|handlers| handlers.chat_rooms)
+    }
+
+    /// [SteamUnifiedMessages] of the current connection, if any.
+    pub fn unified_messages(&self) -> Option<SteamUnifiedMessages> {
+        self.current_handlers().map(|handlers| handlers.unified_messages)
+    }
+
+    /// [SteamAuthentication] of the current connection, if any.
+    ///
+    /// An anonymous client is enough to trade credentials for tokens, and then log on with them.
+    pub fn authentication(&self) -> Option<SteamAuthentication> {
+        self.current_handlers().map(|handlers| handlers.authentication)
+    }
+
+    /// [SteamApps] of the current connection, if any.
+    pub fn steam_apps(&self) -> Option<SteamApps> {
+        self.current_handlers().map(|handlers| handlers.steam_apps)
+    }
+
+    /// [SteamGameCoordinator] of the current connection, if any.
+    pub fn game_coordinator(&self) -> Option<SteamGameCoordinator> {
+        self.current_handlers().map(|handlers| handlers.game_coordinator)
+    }
+
+    fn current_handlers(&self) -> Option<Handlers> {
+        self.inner.handlers.lock().unwrap().clone()
+    }
+
+    /// Connects, logs on and serves the connection, reconnecting whenever it drops.
+    ///
+    /// Only returns once [SteamClient::stop] is called, Steam refuses our credentials, or the [ReconnectPolicy]
+    /// gives up.
+    pub async fn run(&self) -> Result<(), ClientError> {
+        if self.inner.running.swap(true, Ordering::AcqRel) {
+            return Err(ClientError::AlreadyRunning);
+        }
+        self.inner.stopping.store(false, Ordering::Release);
+
+        let result = self.run_loop().await;
+
+        self.inner.running.store(false, Ordering::Release);
+        result
+    }
+
+    /// Logs off and stops [SteamClient::run].
+    pub fn stop(&self) {
+        self.inner.stopping.store(true, Ordering::Release);
+        // no permit is stored for later, a run started after this must not skip its first backoff
+        self.inner.stopped.notify_waiters();
+
+        if let Some(handlers) = self.current_handlers() {
+            // the connection may be gone already, nothing to log off from then
+            let _ = handlers.steam_user.log_off();
+            handlers.connection.close();
+        }
+    }
+
+    fn is_stopping(&self) -> bool {
+        self.inner.stopping.load(Ordering::Acquire)
+    }
+
+    async fn run_loop(&self) -> Result<(), ClientError> {
+        let policy = self.inner.reconnect_policy;
+        let mut failures = 0;
+        let mut previous = None;
+
+        loop {
+            let server = self.next_server(previous.as_ref()).await;
+            let served = match &server {
+                Some(server) => self.serve(server).await?,
+                None => Served {
+                    logged_on: false,
+                    result: Err(ConnectionError::Dropped),
+                },
+            };
+            // listened to before checking, so a stop from now on still cuts the backoff short
+            let stopped = self.inner.stopped.notified();
+            tokio::pin!(stopped);
+            stopped.as_mut().enable();
+            if self.is_stopping() {
+                return Ok(());
+            }
+            previous = server;
+
+            failures = if served.logged_on { 0 } else { failures + 1 };
+            if policy.gives_up_after(failures) {
+                return Err(ClientError::ReconnectsExhausted(failures));
+            }
+
+            let delay = policy.delay(failures.saturating_sub(1));
+            warn!(
+                "Connection to Steam lost ({:?}), reconnecting in {:?}.",
+                served.result, delay
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut stopped => {}
+            }
+            if self.is_stopping() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Best CM to connect to other than the `previous` one, fetching them if we know of none.
+    async fn next_server(&self, previous: Option<&CmServer>) -> Option<CmServer> {
+        let server_list = &self.inner.server_list;
+        let protocol = self.inner.transport.protocol();
+        if server_list.is_empty(protocol) {
+            let cell_id = match self.inner.cell_id.load(Ordering::Acquire) {
+                0 => None,
+                cell_id => Some(cell_id),
+            };
+            if let Err(err) = server_list.refresh(cell_id).await {
+                error!("Could not find any CM server: {}", err);
+                return None;
+            }
+        }
+
+        server_list.pick_after(protocol, previous)
+    }
+
+    /// Connects to `server`, logs on and serves the connection until it closes.
+    ///
+    /// Only fails when trying again would not help. The server is marked bad if we could not connect or log on
+    /// through it, and good if we did, however long the connection lasted afterwards.
+    async fn serve(&self, server: &CmServer) -> Result<Served, ClientError> {
+        debug!("Connecting to CM {}.", server);
+        let started = Instant::now();
+        let transport = &self.inner.transport;
+        let connecting = transport.connect(&server.endpoint);
+        let (connection, task) = match tokio::time::timeout(CONNECT_TIMEOUT, connecting).await {
+            Ok(Ok(stream)) => SteamConnection::new(stream, server.endpoint.clone(), transport.is_secure())
+                .with_public_key(transport.public_key())
+                .spawn(),
+            Ok(Err(err)) => {
+                self.inner.server_list.mark_bad(server);
+                return Ok(Served {
+                    logged_on: false,
+                    result: Err(err),
+                });
+            }
+            Err(_) => {
+                self.inner.server_list.mark_bad(server);
+                return Ok(Served {
+                    logged_on: false,
+                    result: Err(ConnectionError::Dropped),
+                });
+            }
+        };
+        let latency = started.elapsed();
+
+        let handlers = Handlers::new(connection.clone(), &self.inner.events);
+        let forwarder = tokio::spawn(forward_connection(
+            self.inner.events.clone(),
+            connection.subscribe(),
+            task,
+        ));
+        *self.inner.handlers.lock().unwrap() = Some(handlers.clone());
+
+        // stop may have been called while we were connecting, before it could see this connection
+        let logon = if self.is_stopping() {
+            connection.close();
+            None
+        } else {
+            let logon = handlers.steam_user.log_on(self.inner.logon_details.clone());
+            Some(tokio::time::timeout(LOGON_TIMEOUT, logon).await)
+        };
+
+        let logged_on = match logon {
+            Some(Ok(Ok(logged_on))) => {
+                info!("Logged on as {:?} through {}.", logged_on.steam_id, server);
+                self.inner.cell_id.store(logged_on.cell_id, Ordering::Release);
+                self.inner.server_list.mark_good(server, latency);
+                true
+            }
+            Some(Ok(Err(LogonError::Denied(result)))) if !is_retryable(result) => {
+                connection.close();
+                let _ = forwarder.await;
+                *self.inner.handlers.lock().unwrap() = None;
+                return Err(ClientError::LogonDenied(result));
+            }
+            Some(Ok(Err(err))) => {
+                warn!("Could not log on through {}: {}", server, err);
+                self.inner.server_list.mark_bad(server);
+                connection.close();
+                false
+            }
+            Some(Err(_)) => {
+                warn!("Timed out logging on through {}.", server);
+                self.inner.server_list.mark_bad(server);
+                connection.close();
+                false
+            }
+            None => false,
+        };
+
+        let result = forwarder.await.unwrap_or(Err(ConnectionError::Dropped));
+        *self.inner.handlers.lock().unwrap() = None;
+        Ok(Served { logged_on, result })
+    }
+}
+
+/// Logon failures caused by the CM or Steam being unwell, rather than by our credentials.
+fn is_retryable(result: EResult) -> bool {
+    matches!(
+        result,
+        EResult::TryAnotherCM
+            | EResult::ServiceUnavailable
+            | EResult::Busy
+            | EResult::Timeout
+            | EResult::NoConnection
+            | EResult::RateLimitExceeded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use futures::StreamExt;
+    use steam_language_gen::generated::enums::EMsg;
+    use steam_language_gen::generated::messages::MsgClientChatEnter;
+    use steam_language_gen::MessageHeaderWrapper;
+    use steam_language_gen::SerializableBytes;
+    use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogonResponse;
+
+    use super::*;
+    use crate::events::Disconnected;
+    use crate::fake_cm;
+    use crate::fake_cm::FakeCm;
+    use crate::handlers::steam_user::LoggedOn;
+    use crate::messages::message::ClientMessage;
+    use crate::messages::packet::PacketMessage;
+    use crate::server_list::Protocol;
+    use crate::transport::BoxMessageStream;
+    use crate::transport::MemoryListener;
+    use crate::transport::MemoryTransport;
+
+    type PlainCm = BoxMessageStream;
+
+    /// Client whose connections are in memory, and already encrypted. Every connection comes out of the listener.
+    fn in_memory_client(builder: SteamClientBuilder) -> (SteamClient, MemoryListener) {
+        let (transport, listener) = MemoryTransport::without_encryption();
+        (builder.transport(transport).build(), listener)
+    }
+
+    fn two_servers() -> ServerList {
+        ServerList::with_servers(vec![CmServer::tcp("cm1:27017"), CmServer::tcp("cm2:27017")])
+    }
+
+    async fn answer_logon(cm: &mut PlainCm, eresult: EResult) {
+        let logon = cm.next().await.unwrap().unwrap();
+        assert_eq!(logon.emsg(), EMsg::ClientLogon);
+
+        let mut response = ClientMessage::<CMsgClientLogonResponse>::new_proto(EMsg::ClientLogOnResponse);
+        if let MessageHeaderWrapper::Proto(header) = &mut response.wrapped_header {
+            header.set_steamid(76561197960287930);
+            header.set_client_sessionid(1234567);
+        }
+        response.body.set_eresult(eresult as i32);
+        response.body.set_heartbeat_seconds(9);
+        response.body.set_cell_id(4);
+        cm.send(response.to_bytes()).await.unwrap();
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_the_limit() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_attempts: None,
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(4), Duration::from_secs(10));
+        assert_eq!(policy.delay(100), Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnects_to_the_next_cm_and_logs_on_again() {
+        let builder = SteamClient::builder().server_list(two_servers());
+        let (client, mut accepted) = in_memory_client(builder);
+        let mut logged_on = client.events().subscribe::<LoggedOn>();
+        let mut disconnected = client.events().subscribe::<Disconnected>();
+
+        let run = tokio::spawn({
+            let client = client.clone();
+            async move { client.run().await }
+        });
+
+        let (endpoint, mut cm) = accepted.accept().await.unwrap();
+        assert_eq!(endpoint, "cm1:27017");
+        answer_logon(&mut cm, EResult::OK).await;
+        assert_eq!(logged_on.next().await.unwrap().cell_id, 4);
+        assert!(client.steam_user().unwrap().steam_id().is_some());
+
+        // the CM restarts
+        drop(cm);
+        assert!(disconnected.next().await.unwrap().error.is_some());
+
+        let (endpoint, mut cm) = accepted.accept().await.unwrap();
+        assert_eq!(endpoint, "cm2:27017");
+        answer_logon(&mut cm, EResult::OK).await;
+        logged_on.next().await.unwrap();
+        // the drop is not held against the first CM
+        assert_eq!(
+            client.server_list().pick(Protocol::Tcp),
+            Some(CmServer::tcp("cm1:27017"))
+        );
+
+        client.stop();
+        let logoff = cm.next().await.unwrap().unwrap();
+        assert_eq!(logoff.emsg(), EMsg::ClientLogOff);
+        assert!(run.await.unwrap().is_ok());
+        assert!(client.steam_user().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refused_credentials_stop_the_client() {
+        let builder = SteamClient::builder().server_list(ServerList::with_servers(vec![CmServer::tcp("cm1:27017")]));
+        let (client, mut accepted) = in_memory_client(builder);
+
+        let run = tokio::spawn({
+            let client = client.clone();
+            async move { client.run().await }
+        });
+
+        let (_, mut cm) = accepted.accept().await.unwrap();
+        answer_logon(&mut cm, EResult::InvalidPassword).await;
+
+        assert!(matches!(
+            run.await.unwrap(),
+            Err(ClientError::LogonDenied(EResult::InvalidPassword))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn busy_cm_is_retried_until_the_policy_gives_up() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(2),
+            ..ReconnectPolicy::default()
+        };
+        let builder = SteamClient::builder()
+            .server_list(two_servers())
+            .reconnect_policy(policy);
+        let (client, mut accepted) = in_memory_client(builder);
+
+        let start = Instant::now();
+        let run = tokio::spawn({
+            let client = client.clone();
+            async move { client.run().await }
+        });
+
+        let mut endpoints = vec![];
+        for _ in 0..3 {
+            let (endpoint, mut cm) = accepted.accept().await.unwrap();
+            answer_logon(&mut cm, EResult::TryAnotherCM).await;
+            endpoints.push(endpoint);
+        }
+
+        assert!(matches!(run.await.unwrap(), Err(ClientError::ReconnectsExhausted(3))));
+        assert_eq!(endpoints, vec!["cm1:27017", "cm2:27017", "cm1:27017"]);
+        // waited 1s and then 2s between attempts
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stopping_an_idle_client_does_not_shorten_the_next_run() {
+        let builder = SteamClient::builder().server_list(two_servers());
+        let (client, mut accepted) = in_memory_client(builder);
+        client.stop();
+
+        let start = Instant::now();
+        let run = tokio::spawn({
+            let client = client.clone();
+            async move { client.run().await }
+        });
+
+        let (_, mut cm) = accepted.accept().await.unwrap();
+        answer_logon(&mut cm, EResult::TryAnotherCM).await;
+        let (_, cm) = accepted.accept().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        client.stop();
+        drop(cm);
+        assert!(run.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn runs_only_once_at_a_time() {
+        let builder = SteamClient::builder().server_list(ServerList::with_servers(vec![CmServer::tcp("cm1:27017")]));
+        let (client, mut accepted) = in_memory_client(builder);
+
+        let run = tokio::spawn({
+            let client = client.clone();
+            async move { client.run().await }
+        });
+        let (_, _cm) = accepted.accept().await.unwrap();
+
+        assert!(matches!(client.run().await, Err(ClientError::AlreadyRunning)));
+        client.stop();
+        assert!(run.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn logs_on_through_an_encrypted_fake_cm() {
+        let (transport, fake_cm) = FakeCm::new();
+        let _fake_cm = fake_cm.replay(ClientMessage::<MsgClientChatEnter>::new()).spawn();
+        let client = SteamClient::builder()
+            .server_list(ServerList::with_servers(vec![CmServer::tcp("cm1:27017")]))
+            .transport(transport)
+            .build();
+        let mut logged_on = client.events().subscribe::<LoggedOn>();
+        let mut replayed = client
+            .events()
+            .subscribe::<PacketMessage>()
+            .filter(|message| futures::future::ready(message.emsg() == EMsg::ClientChatEnter));
+
+        let run = tokio::spawn({
+            let client = client.clone();
+            async move { client.run().await }
+        });
+
+        assert_eq!(logged_on.next().await.unwrap().cell_id, fake_cm::CELL_ID);
+        replayed.next().await.unwrap();
+
+        client.stop();
+        assert!(run.await.unwrap().is_ok());
+    }
+}
@@ -1,59 +1,79 @@
-use bytes::{BufMut, BytesMut};
+use atomic::Atomic;
+use atomic::Ordering;
+use bytes::BufMut;
+use bytes::BytesMut;
 use steam_crypto::generate_encrypt_request_handshake;
+use steam_crypto::generate_encrypt_request_handshake_with;
+use steam_crypto::SessionKeys;
 use steam_language_gen::generated::enums::EMsg;
-use steam_language_gen::generated::messages::{
-    MsgChannelEncryptRequest, MsgChannelEncryptResponse, MsgChannelEncryptResult,
-};
-use steam_language_gen::{HasJobId, SerializableBytes};
-
-use crate::connection::{BytesTx, EncryptionState};
-use crate::errors::PacketError;
+use steam_language_gen::generated::enums::EResult;
+use steam_language_gen::generated::messages::MsgChannelEncryptRequest;
+use steam_language_gen::generated::messages::MsgChannelEncryptResponse;
+use steam_language_gen::generated::messages::MsgChannelEncryptResult;
+use steam_language_gen::HasJobId;
+
+use crate::connection::DynBytes;
+use crate::connection::EncryptionState;
+use crate::errors::ConnectionError;
 use crate::messages::message::ClientMessage;
 use crate::messages::packet::PacketMessage;
 use crate::messages::MessageKind;
-use atomic::{Atomic, Ordering};
 
+/// Drives the channel encryption handshake.
+///
+/// Returns the reply that must be written back to the socket, if any. The reply is always sent in plain text, since
+/// the connection only becomes `Encrypted` after Steam acknowledges it with a [EMsg::ChannelEncryptResult].
+///
+/// The session key is encrypted with Steam's public key, unless the CM has a `public_key` of its own.
 pub(crate) fn handle_encryption_negotiation(
-    tx: BytesTx,
-    conn_encryption_state: &mut Atomic<EncryptionState>,
+    conn_encryption_state: &Atomic<EncryptionState>,
+    session_keys: &mut Option<SessionKeys>,
+    public_key: Option<&[u8]>,
     message: PacketMessage,
-) -> anyhow::Result<()> {
+) -> Result<Option<DynBytes>, ConnectionError> {
+    let current_state = conn_encryption_state.load(Ordering::Acquire);
 
-    // asddassdj
-    match message.emsg() {
-        EMsg::ChannelEncryptRequest => {
-            let encrypt_response: Box<dyn SerializableBytes> = Box::new(handle_encrypt_request(message));
+    match (message.emsg(), current_state) {
+        (EMsg::ChannelEncryptRequest, EncryptionState::Connected) => {
+            let (keys, encrypt_response) = handle_encrypt_request(message, public_key)?;
 
-            println!("req current state: {:?}", conn_encryption_state);
-            conn_encryption_state.swap(EncryptionState::Challenged, Ordering::AcqRel);
-            tx.send(encrypt_response)
-                .map_err::<PacketError, _>(|_| PacketError::Malformed)?;
-        }
-        EMsg::ChannelEncryptResult => {
-            println!("result matched current state: {:?}", conn_encryption_state);
-            conn_encryption_state.swap(EncryptionState::Encrypted, Ordering::AcqRel);
-            handle_encrypt_result(message).unwrap();
+            *session_keys = Some(keys);
+            conn_encryption_state.store(EncryptionState::Challenged, Ordering::Release);
+            Ok(Some(Box::new(encrypt_response)))
         }
-        _ => unreachable!(),
+        (EMsg::ChannelEncryptResult, EncryptionState::Challenged) => match handle_encrypt_result(message) {
+            EResult::OK => {
+                debug!("Channel encryption negotiated successfully.");
+                conn_encryption_state.store(EncryptionState::Encrypted, Ordering::Release);
+                Ok(None)
+            }
+            result => {
+                conn_encryption_state.store(EncryptionState::Disconnected, Ordering::Release);
+                Err(ConnectionError::EncryptionFailed(result))
+            }
+        },
+        (emsg, _) => Err(ConnectionError::UnexpectedHandshakeMessage(emsg)),
     }
-
-    Ok(())
 }
 
-fn handle_encrypt_result(message: PacketMessage) -> anyhow::Result<()> {
+fn handle_encrypt_result(message: PacketMessage) -> EResult {
     let incoming_message: ClientMessage<MsgChannelEncryptResult> = ClientMessage::from_packet_message(message);
-    println!("{:?}", incoming_message.body.result);
+    let result = incoming_message.body.result;
 
-    Ok(())
+    debug!("Got encryption result: {:?}", result);
+    result
 }
 
-pub(crate) fn handle_encrypt_request(message: PacketMessage) -> ClientMessage<MsgChannelEncryptResponse> {
+pub(crate) fn handle_encrypt_request(
+    message: PacketMessage,
+    public_key: Option<&[u8]>,
+) -> Result<(SessionKeys, ClientMessage<MsgChannelEncryptResponse>), ConnectionError> {
     let incoming_message: ClientMessage<MsgChannelEncryptRequest> = ClientMessage::from_packet_message(message);
 
     let connected_universe = incoming_message.body.universe;
     let protocol_version = incoming_message.body.protocol_version;
 
-    println!(
+    debug!(
         "Got encryption request. Universe: {:?} Protocol Version {:?}",
         connected_universe, protocol_version
     );
@@ -65,21 +85,21 @@ pub(crate) fn handle_encrypt_request(message: PacketMessage) -> ClientMessage<Ms
         random_challenge.put(payload);
     }
 
-    let (session_keys, encrypted_payload) = generate_encrypt_request_handshake(&*random_challenge);
+    let handshake = match public_key {
+        Some(public_key) => generate_encrypt_request_handshake_with(public_key, &random_challenge),
+        None => generate_encrypt_request_handshake(&random_challenge),
+    };
+    let (session_keys, encrypted_payload) =
+        handshake.map_err(|err| ConnectionError::InvalidPublicKey(err.to_string()))?;
 
-    // last message source is now our target.. dunno yet about our source, maybe last message target?
-    let target = incoming_message.wrapped_header.target();
-    let source = incoming_message.wrapped_header.source();
+    // last message source is now our target
+    let target = incoming_message.wrapped_header.source();
 
     let reply_message: ClientMessage<MsgChannelEncryptResponse> = ClientMessage::new()
         .set_target(target)
         .set_payload(encrypted_payload.as_ref());
 
-    println!("Incoming message: {}.", incoming_message);
-    println!(
-        "Answering with: {} Payload {:?}.",
-        reply_message,
-        &reply_message.payload()[..128]
-    );
-    reply_message
+    trace!("Incoming message: {}.", incoming_message);
+    trace!("Answering with: {}.", reply_message);
+    Ok((session_keys, reply_message))
 }
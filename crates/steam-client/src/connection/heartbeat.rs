@@ -0,0 +1,113 @@
+//! While logged on, Steam expects a [EMsg::ClientHeartBeat] every `heartbeat_seconds` it told us on logon, or it
+//! drops the connection.
+//!
+//! We ask for a reply on every heartbeat, so a CM that stays silent for several of them is considered gone.
+
+use std::time::Duration;
+
+use steam_language_gen::generated::enums::EMsg;
+use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientHeartBeat;
+use tokio::time::interval_at;
+use tokio::time::Instant;
+use tokio::time::Interval;
+use tokio::time::MissedTickBehavior;
+
+use crate::errors::ConnectionError;
+use crate::messages::message::ClientMessage;
+
+/// How many heartbeats in a row may go unanswered before we give up on the connection.
+pub(crate) const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    interval: Interval,
+    unanswered: u32,
+}
+
+impl Heartbeat {
+    /// First heartbeat is due one period from now.
+    pub fn new(period: Duration) -> Self {
+        let mut interval = interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            interval,
+            unanswered: 0,
+        }
+    }
+
+    /// Waits until the next heartbeat is due, and returns the message to be sent.
+    ///
+    /// Fails instead if the last [MAX_MISSED_HEARTBEATS] heartbeats got no answer.
+    pub async fn tick(&mut self) -> Result<ClientMessage<CMsgClientHeartBeat>, ConnectionError> {
+        self.interval.tick().await;
+
+        if self.unanswered >= MAX_MISSED_HEARTBEATS {
+            return Err(ConnectionError::HeartbeatTimeout(self.unanswered));
+        }
+        self.unanswered += 1;
+
+        let mut message = ClientMessage::<CMsgClientHeartBeat>::new_proto(EMsg::ClientHeartBeat);
+        message.body.set_send_reply(true);
+        Ok(message)
+    }
+
+    /// Anything coming from Steam proves the connection is still alive.
+    pub fn received(&mut self) {
+        self.unanswered = 0;
+    }
+}
+
+/// Waits on the heartbeat, if there is one, forever otherwise.
+pub(crate) async fn next_heartbeat(
+    heartbeat: &mut Option<Heartbeat>,
+) -> Result<ClientMessage<CMsgClientHeartBeat>, ConnectionError> {
+    match heartbeat {
+        Some(heartbeat) => heartbeat.tick().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn ticks_once_per_period() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(Duration::from_secs(9));
+
+        let message = heartbeat.tick().await.unwrap();
+        assert_eq!(message.emsg, EMsg::ClientHeartBeat);
+        assert!(message.body.send_reply());
+        assert_eq!(start.elapsed(), Duration::from_secs(9));
+
+        heartbeat.received();
+        heartbeat.tick().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(18));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_after_unanswered_heartbeats() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(Duration::from_secs(9));
+
+        for _ in 0..MAX_MISSED_HEARTBEATS {
+            heartbeat.tick().await.unwrap();
+        }
+        let result = heartbeat.tick().await;
+
+        assert!(matches!(result, Err(ConnectionError::HeartbeatTimeout(3))));
+        assert_eq!(start.elapsed(), Duration::from_secs(36));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answers_reset_the_count() {
+        let mut heartbeat = Heartbeat::new(Duration::from_secs(9));
+
+        for _ in 0..10 {
+            heartbeat.tick().await.unwrap();
+            heartbeat.received();
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Request and response correlation through job ids.
+//!
+//! Every request we want an answer to goes out with an unique `jobid_source`, and Steam replies with the same value
+//! as `jobid_target`.
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Steam/SteamClient/AsyncJob/AsyncJobManager.cs
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use tokio::sync::oneshot;
+
+use crate::messages::packet::PacketMessage;
+
+/// Job id meaning "no job" on every header kind.
+pub(crate) const NO_JOB: u64 = u64::MAX;
+
+#[derive(Debug)]
+pub(crate) struct JobManager {
+    next_job_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<PacketMessage>>>,
+    closed: AtomicBool,
+}
+
+impl Default for JobManager {
+    fn default() -> Self {
+        Self {
+            next_job_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl JobManager {
+    /// Allocates a job id and starts waiting for its reply.
+    ///
+    /// The job is forgotten as soon as the returned [PendingJob] is dropped, so callers giving up on it never leak.
+    /// Once [cancel_all](Self::cancel_all) ran, jobs fail right away instead.
+    pub fn register(self: &Arc<Self>) -> PendingJob {
+        let job_id = self.next_job_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+
+        // checked under the lock, so a job can not slip in between closing and clearing
+        let mut pending = self.pending.lock().unwrap();
+        if !self.closed.load(Ordering::Relaxed) {
+            pending.insert(job_id, sender);
+        }
+        drop(pending);
+
+        PendingJob {
+            job_id,
+            reply: receiver,
+            jobs: self.clone(),
+        }
+    }
+
+    /// Hands the message to the job waiting on it, if any. Otherwise the message is given back.
+    pub fn complete(&self, packet_message: PacketMessage) -> Option<PacketMessage> {
+        let (_, target_job_id) = packet_message.jobs_ids();
+        if target_job_id == NO_JOB {
+            return Some(packet_message);
+        }
+
+        let waiting_job = self.pending.lock().unwrap().remove(&target_job_id);
+        match waiting_job {
+            Some(sender) => {
+                // the job may have been dropped right after we took it, nothing to do then
+                let _ = sender.send(packet_message);
+                None
+            }
+            None => Some(packet_message),
+        }
+    }
+
+    /// Fails every pending job, and the ones registered later, since their replies will never arrive.
+    pub fn cancel_all(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        self.closed.store(true, Ordering::Relaxed);
+        pending.clear();
+    }
+
+    fn forget(&self, job_id: u64) {
+        self.pending.lock().unwrap().remove(&job_id);
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+}
+
+/// A job waiting for its reply.
+#[derive(Debug)]
+pub(crate) struct PendingJob {
+    pub job_id: u64,
+    pub reply: oneshot::Receiver<PacketMessage>,
+    jobs: Arc<JobManager>,
+}
+
+impl Drop for PendingJob {
+    fn drop(&mut self) {
+        self.jobs.forget(self.job_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use steam_language_gen::generated::enums::EMsg;
+    use steam_language_gen::HasJobId;
+    use steam_language_gen::MessageHeaderWrapper;
+    use steam_protobuf::protobufs::steammessages_base::CMsgProtoBufHeader;
+
+    use super::*;
+
+    fn reply_to(job_id: u64) -> PacketMessage {
+        let mut header = CMsgProtoBufHeader::new();
+        header.set_target(job_id);
+        PacketMessage::new(EMsg::ClientLogOnResponse, MessageHeaderWrapper::Proto(header), vec![])
+    }
+
+    #[test]
+    fn job_ids_are_unique() {
+        let jobs = Arc::new(JobManager::default());
+        let first = jobs.register();
+        let second = jobs.register();
+
+        assert_ne!(first.job_id, second.job_id);
+        assert_ne!(first.job_id, NO_JOB);
+    }
+
+    #[tokio::test]
+    async fn reply_resolves_matching_job() {
+        let jobs = Arc::new(JobManager::default());
+        let first = jobs.register();
+        let mut second = jobs.register();
+
+        assert!(jobs.complete(reply_to(second.job_id)).is_none());
+        assert_eq!((&mut second.reply).await.unwrap().jobs_ids().1, second.job_id);
+        assert_eq!(jobs.pending_count(), 1);
+        drop(first);
+    }
+
+    #[test]
+    fn unrelated_messages_are_given_back() {
+        let jobs = Arc::new(JobManager::default());
+        let _job = jobs.register();
+
+        assert!(jobs.complete(reply_to(NO_JOB)).is_some());
+        assert!(jobs.complete(reply_to(4242)).is_some());
+    }
+
+    #[test]
+    fn dropped_jobs_are_forgotten() {
+        let jobs = Arc::new(JobManager::default());
+        let job = jobs.register();
+        let job_id = job.job_id;
+        drop(job);
+
+        assert_eq!(jobs.pending_count(), 0);
+        assert!(jobs.complete(reply_to(job_id)).is_some());
+    }
+
+    #[tokio::test]
+    async fn cancel_all_fails_pending_jobs() {
+        let jobs = Arc::new(JobManager::default());
+        let mut job = jobs.register();
+
+        jobs.cancel_all();
+        assert!((&mut job.reply).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn jobs_registered_after_cancel_all_fail_right_away() {
+        let jobs = Arc::new(JobManager::default());
+        jobs.cancel_all();
+
+        let mut job = jobs.register();
+        assert!((&mut job.reply).await.is_err());
+        assert_eq!(jobs.pending_count(), 0);
+    }
+}
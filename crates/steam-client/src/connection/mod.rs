@@ -1,203 +1,382 @@
 //! This module handles connections to Content Manager Server
-//! First you connect into the ip using a tcp socket
-//! Then reads/writes into it
+//! First you connect to it through a [Transport]
+//! Then reads/writes whole messages into it
 //!
-//! Packets are sent at the following format: packet_len + packet_magic + data
-//! packet length: u32
-//! packet magic: VT01
+//! Everything from the encryption handshake up is the same whatever the transport is.
 //!
-//! Apparently, bytes received are in little endian
+//! [Transport]: crate::transport::Transport
 
-use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 
-use async_trait::async_trait;
-use bytes::BytesMut;
-use futures::{SinkExt, StreamExt};
+use atomic::Atomic;
+use atomic::Ordering;
+use futures::SinkExt;
+use futures::StreamExt;
 use steam_crypto::SessionKeys;
 use steam_language_gen::generated::enums::EMsg;
 use steam_language_gen::SerializableBytes;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio_util::codec::{FramedRead, FramedWrite};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 
 use crate::connection::encryption::handle_encryption_negotiation;
+use crate::connection::heartbeat::next_heartbeat;
+use crate::connection::heartbeat::Heartbeat;
+use crate::connection::jobs::JobManager;
+use crate::connection::multi::unpack_multi;
+use crate::connection::session::Session;
 use crate::errors::ConnectionError;
-use crate::messages::codec::PacketMessageCodec;
 use crate::messages::message::ClientMessage;
-use crate::{errors::PacketError, messages::packet::PacketMessage};
-use atomic::{Atomic, Ordering};
+use crate::messages::packet::PacketMessage;
+use crate::transport::BoxMessageStream;
 
 pub(crate) mod encryption;
+pub(crate) mod heartbeat;
+pub(crate) mod jobs;
+pub(crate) mod multi;
+pub(crate) mod session;
+#[cfg(test)]
+pub(crate) mod testing;
+
+/// How many decoded messages a slow subscriber may lag behind before it starts missing them.
+const PACKET_CHANNEL_CAPACITY: usize = 256;
 
-const PACKET_MAGIC_BYTES: &[u8] = br#"VT01"#;
+/// How long we wait for the reply to a job, unless told otherwise.
+pub(crate) const DEFAULT_JOB_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// This should be an abstraction over low-level socket handlers and is not to be used directly.
 /// [SteamClient] is used for binding and connecting.
 #[derive(Debug)]
-pub(crate) struct SteamConnection<S> {
-    /// Stream of data to Steam Content server. May be TCP or Websocket.
-    stream: S,
+pub(crate) struct SteamConnection {
+    /// Messages to and from the Steam Content server. May be over TCP or Websocket.
+    stream: BoxMessageStream,
     /// Address to which the connection is bound.
     endpoint: String,
     /// Current encryption state
-    state: Atomic<EncryptionState>,
+    state: Arc<Atomic<EncryptionState>>,
     /// Populated after the initial handshake with Steam
     session_keys: Option<SessionKeys>,
+    /// Key the session key is encrypted with, in PEM, when the CM is not Steam.
+    public_key: Option<Vec<u8>>,
 }
 
-impl<S> SteamConnection<S> {
+impl SteamConnection {
+    /// Wraps a freshly opened connection. Steam only asks to encrypt it if it is not secure already.
+    pub(crate) fn new(stream: BoxMessageStream, endpoint: String, is_secure: bool) -> Self {
+        let state = if is_secure {
+            EncryptionState::Encrypted
+        } else {
+            EncryptionState::Connected
+        };
+
+        SteamConnection {
+            stream,
+            endpoint,
+            state: Arc::new(Atomic::new(state)),
+            session_keys: None,
+            public_key: None,
+        }
+    }
+
+    /// Encrypts the session key with `public_key`, in PEM, instead of Steam's own.
+    pub(crate) fn with_public_key(mut self, public_key: Option<&[u8]>) -> Self {
+        self.public_key = public_key.map(<[u8]>::to_vec);
+        self
+    }
+
     pub fn change_encryption_state(&self, new_state: EncryptionState) {
         self.state.swap(new_state, Ordering::AcqRel);
     }
 }
 
-#[async_trait]
-trait Connection<S> {
-    async fn new_connection(ip_addr: &str) -> Result<SteamConnection<S>, Box<dyn Error>>;
-    async fn read_packets(&mut self) -> Result<PacketMessage, PacketError>;
-    async fn write_packets(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>>;
-}
-
-pub(crate) type PacketTx = UnboundedSender<PacketMessage>;
+pub(crate) type PacketTx = broadcast::Sender<PacketMessage>;
+pub(crate) type PacketRx = broadcast::Receiver<PacketMessage>;
 pub(crate) type MessageTx<T> = UnboundedSender<ClientMessage<T>>;
 
 pub(crate) type DynBytes = Box<dyn SerializableBytes>;
 pub(crate) type BytesTx = UnboundedSender<Box<dyn SerializableBytes + 'static>>;
 
-#[cfg(not(feature = "websockets"))]
-impl SteamConnection<TcpStream> {
-    async fn main_loop(mut self) -> Result<(), ConnectionError> {
-        let (sender, mut receiver): (UnboundedSender<DynBytes>, UnboundedReceiver<DynBytes>) =
-            mpsc::unbounded_channel();
-
-        let connection_state = &mut self.state;
-        let (stream_rx, stream_tx) = self.stream.into_split();
-
-        let mut framed_read = FramedRead::new(stream_rx, PacketMessageCodec::default());
-        let mut framed_write = FramedWrite::new(stream_tx, PacketMessageCodec::default());
+/// Cheap, cloneable handle to a running [SteamConnection].
+///
+/// Messages sent through it are queued until the channel is `Encrypted`, and every decoded [PacketMessage] that is
+/// not part of the encryption handshake nor the reply to a job is broadcast to its subscribers.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionHandle {
+    sender: BytesTx,
+    packets: PacketTx,
+    state: Arc<Atomic<EncryptionState>>,
+    session: Arc<Session>,
+    jobs: Arc<JobManager>,
+    closing: Arc<Notify>,
+}
 
-        tokio::spawn(async move {
-            if let Some(mes) = receiver.recv().await {
-                let message: Vec<u8> = mes.to_bytes();
-                framed_write.send(message).await.unwrap();
-            }
-        });
+impl ConnectionHandle {
+    /// Queues a message to be written to the socket.
+    ///
+    /// Once logged on, the message header is stamped with our SteamID and session id.
+    pub fn send<M>(&self, mut message: ClientMessage<M>) -> Result<(), ConnectionError>
+    where
+        ClientMessage<M>: SerializableBytes + 'static,
+    {
+        self.session.stamp(&mut message.wrapped_header);
+        self.sender.send(Box::new(message)).map_err(|_| ConnectionError::Dropped)
+    }
 
-        while let Some(packet_message) = framed_read.next().await {
-            let packet_message = packet_message.unwrap();
+    /// Sends a message as a new job, and waits for Steam to reply to it.
+    pub async fn send_job<M>(&self, message: ClientMessage<M>) -> Result<PacketMessage, ConnectionError>
+    where
+        ClientMessage<M>: SerializableBytes + 'static,
+    {
+        self.send_job_with_timeout(message, DEFAULT_JOB_TIMEOUT).await
+    }
 
-            match packet_message.emsg() {
-                EMsg::ChannelEncryptRequest | EMsg::ChannelEncryptResponse | EMsg::ChannelEncryptResult => {
-                    handle_encryption_negotiation(sender.clone(), connection_state, packet_message).unwrap();
-                }
-                _ => {
-                    unimplemented!()
-                }
-            };
+    /// Sends a message as a new job, and waits up to `timeout` for Steam to reply to it.
+    ///
+    /// Fails with [ConnectionError::Dropped] if the connection goes away before the reply arrives.
+    pub async fn send_job_with_timeout<M>(
+        &self,
+        message: ClientMessage<M>,
+        timeout: Duration,
+    ) -> Result<PacketMessage, ConnectionError>
+    where
+        ClientMessage<M>: SerializableBytes + 'static,
+    {
+        let mut job = self.jobs.register();
+        self.send(message.set_source(job.job_id))?;
+
+        match tokio::time::timeout(timeout, &mut job.reply).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err(ConnectionError::Dropped),
+            Err(_) => Err(ConnectionError::JobTimeout(job.job_id)),
         }
-
-        Ok(())
     }
-}
 
-#[cfg(not(feature = "websockets"))]
-#[async_trait]
-impl Connection<TcpStream> for SteamConnection<TcpStream> {
-    /// Opens a tcp stream to specified IP
-    async fn new_connection(ip_addr: &str) -> Result<SteamConnection<TcpStream>, Box<dyn Error>> {
-        trace!("Connecting to ip: {}", &ip_addr);
-
-        let stream = TcpStream::connect(ip_addr).await?;
-
-        Ok(SteamConnection {
-            stream,
-            endpoint: ip_addr.to_string(),
-            state: Atomic::new(EncryptionState::Disconnected),
-            session_keys: None,
-        })
+    /// Subscribes to every incoming [PacketMessage] from this point on.
+    pub fn subscribe(&self) -> PacketRx {
+        self.packets.subscribe()
     }
 
-    #[inline]
-    async fn read_packets(&mut self) -> Result<PacketMessage, PacketError> {
-        let mut framed_stream = FramedRead::new(&mut self.stream, PacketMessageCodec::default());
-        Ok(framed_stream.next().await.unwrap().unwrap())
+    /// Returns the current encryption state of the connection.
+    pub fn encryption_state(&self) -> EncryptionState {
+        self.state.load(Ordering::Acquire)
     }
 
-    #[inline]
-    async fn write_packets(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
-        let mut output_buffer = BytesMut::with_capacity(1024);
-
-        trace!("payload size: {} ", data.len());
+    /// Returns the session Steam assigned to us on logon.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
 
-        output_buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
-        output_buffer.extend_from_slice(PACKET_MAGIC_BYTES);
-        output_buffer.extend_from_slice(data);
-        let output_buffer = output_buffer.freeze();
+    /// Resolves once the connection actor has finished.
+    pub async fn closed(&self) {
+        self.sender.closed().await
+    }
 
-        trace!("Writing {} bytes of data to stream..", output_buffer.len());
-        trace!("Payload bytes: {:?}", output_buffer);
+    /// Returns true while the connection actor is still alive.
+    pub fn is_connected(&self) -> bool {
+        !self.sender.is_closed()
+    }
 
-        let write_result = self.stream.write(&output_buffer).await?;
-        trace!("write result: {}", write_result);
-        Ok(())
+    /// Asks the connection actor to hang up, even if other handles are still alive.
+    pub fn close(&self) {
+        self.closing.notify_one();
     }
 }
 
-#[cfg(feature = "websockets")]
-mod connection_method {
-    use tokio_tls::TlsStream;
-    use tokio_tungstenite::{connect_async, stream::Stream, WebSocketStream};
+#[cfg(test)]
+impl ConnectionHandle {
+    /// A handle that is not bound to any socket, already encrypted.
+    ///
+    /// Everything sent through it comes out of the returned receiver, while [ConnectionHandle::inject] feeds it
+    /// messages as if they were read from Steam.
+    pub(crate) fn detached() -> (Self, UnboundedReceiver<DynBytes>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (packets, _) = broadcast::channel(PACKET_CHANNEL_CAPACITY);
+
+        let handle = ConnectionHandle {
+            sender,
+            packets,
+            state: Arc::new(Atomic::new(EncryptionState::Encrypted)),
+            session: Arc::new(Session::default()),
+            jobs: Arc::new(JobManager::default()),
+            closing: Arc::new(Notify::new()),
+        };
+        (handle, receiver)
+    }
 
-    use super::*;
+    /// Dispatches a message as if it was read from the socket.
+    pub(crate) fn inject(&self, packet_message: PacketMessage) {
+        dispatch(&self.packets, &self.session, &self.jobs, packet_message);
+    }
+}
 
-    type Ws = WebSocketStream<Stream<TcpStream, TlsStream<TcpStream>>>;
+impl SteamConnection {
+    /// Spawns the connection actor, returning a handle to it and the task running its main loop.
+    pub(crate) fn spawn(self) -> (ConnectionHandle, JoinHandle<Result<(), ConnectionError>>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (packets, _) = broadcast::channel(PACKET_CHANNEL_CAPACITY);
+        let session = Arc::new(Session::default());
+        let jobs = Arc::new(JobManager::default());
+        let closing = Arc::new(Notify::new());
+
+        let handle = ConnectionHandle {
+            sender,
+            packets: packets.clone(),
+            state: self.state.clone(),
+            session: session.clone(),
+            jobs: jobs.clone(),
+            closing: closing.clone(),
+        };
+
+        let task = tokio::spawn(self.main_loop(receiver, packets, session, jobs, closing));
+        (handle, task)
+    }
 
-    #[async_trait]
-    impl Connection<Ws> for SteamConnection<Ws> {
-        async fn new_connection(ws_url: &str) -> Result<SteamConnection<Ws>, Box<dyn Error>> {
-            let formatted_ws_url = format!("wss://{}/cmsocket/", ws_url);
-            debug!("Connecting to addr: {}", formatted_ws_url);
+    /// Reads and writes to the socket until either side hangs up.
+    ///
+    /// Encryption negotiation is answered directly from here, while outgoing messages are only polled after the
+    /// channel is `Encrypted`, so nothing queued early leaks out during the handshake. Heartbeats are sent from here
+    /// as well, for as long as we are logged on.
+    async fn main_loop(
+        self,
+        mut receiver: UnboundedReceiver<DynBytes>,
+        packets: PacketTx,
+        session: Arc<Session>,
+        jobs: Arc<JobManager>,
+        closing: Arc<Notify>,
+    ) -> Result<(), ConnectionError> {
+        let SteamConnection {
+            stream: mut framed,
+            endpoint,
+            state,
+            mut session_keys,
+            public_key,
+        } = self;
+
+        let mut heartbeat: Option<Heartbeat> = None;
+
+        let result = loop {
+            let is_encrypted = matches!(state.load(Ordering::Acquire), EncryptionState::Encrypted);
+
+            tokio::select! {
+                incoming = framed.next() => {
+                    let packet_message = match incoming {
+                        Some(Ok(packet_message)) => packet_message,
+                        Some(Err(err)) => break Err(err.into()),
+                        None => break Err(ConnectionError::Dropped),
+                    };
+                    if let Some(heartbeat) = &mut heartbeat {
+                        heartbeat.received();
+                    }
+
+                    match packet_message.emsg() {
+                        EMsg::ChannelEncryptRequest | EMsg::ChannelEncryptResult => {
+                            match handle_encryption_negotiation(&state, &mut session_keys, public_key.as_deref(), packet_message) {
+                                Ok(Some(reply)) => {
+                                    if let Err(err) = framed.send(reply.to_bytes()).await {
+                                        break Err(err.into());
+                                    }
+                                }
+                                // channel is now encrypted, so is every frame from here on
+                                Ok(None) => {
+                                    if let Some(session_keys) = &session_keys {
+                                        framed.enable_encryption(session_keys.session_key());
+                                    }
+                                }
+                                Err(err) => break Err(err),
+                            }
+                        }
+                        _ => {
+                            dispatch(&packets, &session, &jobs, packet_message);
+
+                            // logon and logoff may come inside a Multi, so the session knows better than the emsg
+                            match (session.heartbeat_interval(), &heartbeat) {
+                                (Some(period), None) => {
+                                    debug!("Logged on, sending heartbeats every {:?}.", period);
+                                    heartbeat = Some(Heartbeat::new(period));
+                                }
+                                (None, Some(_)) => {
+                                    debug!("Logged off, no longer sending heartbeats.");
+                                    heartbeat = None;
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                beat = next_heartbeat(&mut heartbeat) => {
+                    let mut message = match beat {
+                        Ok(message) => message,
+                        Err(err) => break Err(err),
+                    };
+                    session.stamp(&mut message.wrapped_header);
+
+                    if let Err(err) = framed.send(message.to_bytes()).await {
+                        break Err(err.into());
+                    }
+                }
+                outgoing = receiver.recv(), if is_encrypted => {
+                    match outgoing {
+                        Some(message) => {
+                            if let Err(err) = framed.send(message.to_bytes()).await {
+                                break Err(err.into());
+                            }
+                        }
+                        // every handle was dropped, so nobody is left to talk to Steam
+                        None => break Ok(()),
+                    }
+                }
+                _ = closing.notified() => {
+                    // flush what was queued before hanging up, such as a logoff
+                    if is_encrypted {
+                        while let Ok(message) = receiver.try_recv() {
+                            if let Err(err) = framed.send(message.to_bytes()).await {
+                                warn!("Could not flush queued messages while closing: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    break Ok(());
+                }
+            }
+        };
 
-            let (stream, _) = connect_async(&formatted_ws_url).await?;
+        debug!("Connection with {} finished: {:?}", endpoint, result);
+        state.store(EncryptionState::Disconnected, Ordering::Release);
+        session.clear();
+        jobs.cancel_all();
+        result
+    }
+}
 
-            Ok(SteamConnection {
-                stream,
-                endpoint: formatted_ws_url,
-                state: EncryptionState::Disconnected,
-            })
+/// Broadcasts a decoded message to every subscriber, unpacking [EMsg::Multi] so each inner message is delivered on
+/// its own, in order. Replies to jobs only go to the job waiting on them.
+fn dispatch(packets: &PacketTx, session: &Session, jobs: &JobManager, packet_message: PacketMessage) {
+    if packet_message.emsg() == EMsg::Multi {
+        match unpack_multi(&packet_message) {
+            Ok(messages) => messages
+                .into_iter()
+                .for_each(|message| dispatch(packets, session, jobs, message)),
+            Err(err) => error!("Dropping undecodable Multi message: {}", err),
         }
-        #[inline]
-        async fn read_packets(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
-            let mut data_len: [u8; 4] = [0; 4];
-            self.stream.get_mut().read_exact(&mut data_len).await?;
-
-            let mut packet_magic: [u8; 4] = [0; 4];
-            self.stream.get_mut().read_exact(&mut packet_magic).await?;
-
-            if packet_magic != PACKET_MAGIC_BYTES {
-                log::error!("Could not find magic packet on read.");
-            }
+        return;
+    }
 
-            let mut incoming_data = BytesMut::with_capacity(1024);
-            self.stream.get_mut().read_buf(&mut incoming_data).await?;
+    session.track(&packet_message);
 
-            // sanity check
-            debug!("data length: {}", u32::from_le_bytes(data_len));
-            trace!("data: {:?}", incoming_data);
+    let packet_message = match jobs.complete(packet_message) {
+        Some(packet_message) => packet_message,
+        None => return,
+    };
 
-            Ok(incoming_data.to_vec())
-        }
-
-        #[inline]
-        async fn write_packets(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
-            unimplemented!()
-        }
-    }
+    // there may be no subscribers yet, which is fine
+    let _ = packets.send(packet_message);
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// Represents the current state of encryption of the connection.
 /// Steam is always encrypted, with the exception when the connection is starting.
 pub(crate) enum EncryptionState {
@@ -217,88 +396,289 @@ pub(crate) enum EncryptionState {
 
 #[cfg(test)]
 mod tests {
-    use env_logger::Builder;
-    use log::LevelFilter;
+    use std::time::Duration;
+
+    use arrayref::array_ref;
     use steam_language_gen::generated::enums::EMsg;
+    use steam_language_gen::generated::messages::MsgClientChatEnter;
+    use steam_language_gen::HasJobId;
+    use steam_language_gen::MessageHeaderWrapper;
     use steam_language_gen::SerializableBytes;
+    use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLoggedOff;
+    use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogonResponse;
+    use tokio::io::duplex;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::DuplexStream;
+    use tokio::time::timeout;
+    use tokio::time::Instant;
+    use tokio_util::codec::Framed;
 
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
     use crate::connection::encryption::handle_encrypt_request;
-    use crate::content_manager::dump_tcp_servers;
+    use crate::connection::heartbeat::MAX_MISSED_HEARTBEATS;
+    use crate::messages::codec::PacketMessageCodec;
+    use crate::messages::codec::PACKET_MAGIC_BYTES;
+    use crate::fake_cm;
+    use crate::fake_cm::FakeCm;
+    use crate::transport::Transport;
+
+    /// ChannelEncryptRequest, with a 16 bytes challenge as payload
+    fn get_channel_encrypt_request() -> Vec<u8> {
+        vec![
+            23, 5, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1, 0, 0, 0, 1,
+            0, 0, 0, 66, 126, 251, 245, 88, 122, 243, 123, 102, 163, 11, 54, 151, 145, 31, 54,
+        ]
+    }
+
+    /// ChannelEncryptResult, with the given EResult
+    fn get_channel_encrypt_result(result: u32) -> Vec<u8> {
+        let mut message = vec![
+            25, 5, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+        ];
+        message.extend_from_slice(&result.to_le_bytes());
+        message
+    }
+
+    type RawCm = Framed<DuplexStream, PacketMessageCodec>;
+
+    fn connection_over_duplex(is_secure: bool) -> (SteamConnection, RawCm) {
+        let (client, server) = duplex(64 * 1024);
+        let stream = Box::new(Framed::new(client, PacketMessageCodec::default()));
+        let connection = SteamConnection::new(stream, "in-memory".to_string(), is_secure);
+        (connection, Framed::new(server, PacketMessageCodec::default()))
+    }
+
+    fn in_memory_connection() -> (SteamConnection, RawCm) {
+        connection_over_duplex(false)
+    }
+
+    /// Connection that skips the handshake and never encrypts, so the server side can read what is sent.
+    fn plaintext_connection() -> (SteamConnection, RawCm) {
+        connection_over_duplex(true)
+    }
+
+    fn get_logon_response(heartbeat_seconds: i32) -> Vec<u8> {
+        let mut message = ClientMessage::<CMsgClientLogonResponse>::new_proto(EMsg::ClientLogOnResponse);
+        if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+            header.set_steamid(76561197960287930);
+            header.set_client_sessionid(1234567);
+        }
+        message.body.set_eresult(1);
+        message.body.set_heartbeat_seconds(heartbeat_seconds);
+        message.to_bytes()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeats_start_on_logon_and_stop_on_logoff() {
+        let (connection, mut server) = plaintext_connection();
+        let (_handle, _task) = connection.spawn();
+
+        server.send(get_logon_response(9)).await.unwrap();
+        let logged_on_at = Instant::now();
+
+        let heartbeat = server.next().await.unwrap().unwrap();
+        assert_eq!(heartbeat.emsg(), EMsg::ClientHeartBeat);
+        assert_eq!(logged_on_at.elapsed(), Duration::from_secs(9));
+        match heartbeat.header() {
+            MessageHeaderWrapper::Proto(header) => assert_eq!(header.client_sessionid(), 1234567),
+            header => panic!("expected a protobuf header, got {:?}", header),
+        }
+
+        let logged_off = ClientMessage::<CMsgClientLoggedOff>::new_proto(EMsg::ClientLoggedOff);
+        server.send(logged_off.to_bytes()).await.unwrap();
+
+        assert!(timeout(Duration::from_secs(60), server.next()).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_server_misses_heartbeats() {
+        let (connection, mut server) = plaintext_connection();
+        let (handle, task) = connection.spawn();
+
+        server.send(get_logon_response(9)).await.unwrap();
+
+        let result = task.await.unwrap();
+        assert!(matches!(
+            result,
+            Err(ConnectionError::HeartbeatTimeout(MAX_MISSED_HEARTBEATS))
+        ));
+        assert!(!handle.session().is_logged_on());
+    }
+
+    #[tokio::test]
+    async fn job_resolves_with_its_reply_only() {
+        let (handle, mut sent) = ConnectionHandle::detached();
+        let mut packets = handle.subscribe();
+
+        let job = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.send_job(ClientMessage::<MsgClientChatEnter>::new()).await }
+        });
+
+        let request = PacketMessage::from_raw_bytes(&sent.recv().await.unwrap().to_bytes()).unwrap();
+        let (job_id, _) = request.jobs_ids();
+
+        handle.inject(PacketMessage::from_raw_bytes(&get_logon_response(9)).unwrap());
+        let mut reply = ClientMessage::<CMsgClientLogonResponse>::new_proto(EMsg::ClientLogOnResponse);
+        reply.wrapped_header.set_target(job_id);
+        handle.inject(PacketMessage::from_raw_bytes(&reply.to_bytes()).unwrap());
+
+        let reply = job.await.unwrap().unwrap();
+        assert_eq!(reply.jobs_ids().1, job_id);
+
+        // only the message that was not a reply reaches subscribers
+        assert_eq!(packets.recv().await.unwrap().jobs_ids().1, u64::MAX);
+        assert!(packets.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn job_times_out_without_reply() {
+        let (handle, _sent) = ConnectionHandle::detached();
+
+        let result = handle
+            .send_job_with_timeout(ClientMessage::<MsgClientChatEnter>::new(), Duration::from_secs(5))
+            .await;
+
+        assert!(matches!(result, Err(ConnectionError::JobTimeout(_))));
+        assert_eq!(handle.jobs.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn jobs_are_cancelled_on_disconnect() {
+        let (connection, server) = plaintext_connection();
+        let (handle, task) = connection.spawn();
+
+        let job = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.send_job(ClientMessage::<MsgClientChatEnter>::new()).await }
+        });
+        while handle.jobs.pending_count() == 0 {
+            tokio::task::yield_now().await;
+        }
 
-    fn init() {
-        let _ = Builder::from_default_env()
-            .filter_module("steam_api", LevelFilter::Trace)
-            .is_test(true)
-            .try_init();
+        drop(server);
+        assert!(task.await.unwrap().is_err());
+        assert!(matches!(job.await.unwrap(), Err(ConnectionError::Dropped)));
     }
 
     #[tokio::test]
-    #[cfg(not(feature = "websockets"))]
-    async fn connect_to_web_server() {
-        init();
+    async fn close_flushes_queue_and_hangs_up() {
+        let (connection, mut server) = plaintext_connection();
+        let (handle, task) = connection.spawn();
+
+        handle.send(ClientMessage::<MsgClientChatEnter>::new()).unwrap();
+        handle.close();
+
+        assert!(task.await.unwrap().is_ok());
+        assert!(!handle.is_connected());
+        let packet_message = server.next().await.unwrap().unwrap();
+        assert_eq!(packet_message.emsg(), EMsg::ClientChatEnter);
+        assert!(server.next().await.is_none());
+    }
 
-        let dumped_cm_servers = dump_tcp_servers().await.unwrap();
-        let steam_connection = SteamConnection::new_connection(&dumped_cm_servers[0]).await;
-        assert!(steam_connection.is_ok());
+    #[tokio::test]
+    async fn negotiates_encryption_and_broadcasts_packets() {
+        let (connection, mut server) = in_memory_connection();
+        let (handle, _task) = connection.spawn();
+        let mut packets = handle.subscribe();
+        assert_eq!(handle.encryption_state(), EncryptionState::Connected);
+
+        // queued before the handshake, so it must only be written after it
+        let queued_message = ClientMessage::<MsgClientChatEnter>::new();
+        let plain_bytes = queued_message.to_bytes();
+        handle.send(queued_message).unwrap();
+
+        server.send(get_channel_encrypt_request()).await.unwrap();
+        let response = server.next().await.unwrap().unwrap();
+        assert_eq!(response.emsg(), EMsg::ChannelEncryptResponse);
+        assert_eq!(handle.encryption_state(), EncryptionState::Challenged);
+
+        server
+            .send(ClientMessage::<MsgClientChatEnter>::new().to_bytes())
+            .await
+            .unwrap();
+        let packet_message = packets.recv().await.unwrap();
+        assert_eq!(packet_message.emsg(), EMsg::ClientChatEnter);
+
+        server.send(get_channel_encrypt_result(1)).await.unwrap();
+        while handle.encryption_state() != EncryptionState::Encrypted {
+            tokio::task::yield_now().await;
+        }
+
+        let mut server = server.into_inner();
+        let mut frame_header = [0u8; 8];
+        server.read_exact(&mut frame_header).await.unwrap();
+        assert_eq!(&frame_header[4..], PACKET_MAGIC_BYTES);
+
+        let mut frame = vec![0u8; u32::from_le_bytes(*array_ref!(frame_header, 0, 4)) as usize];
+        server.read_exact(&mut frame).await.unwrap();
+        assert_ne!(frame, plain_bytes);
+        assert_eq!(frame.len() % 16, 0);
     }
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-    #[cfg(not(feature = "websockets"))]
-    async fn main_loop() {
-        let dumped_cm_servers = dump_tcp_servers().await.unwrap();
-        let steam_connection = SteamConnection::new_connection(&dumped_cm_servers[0]).await.unwrap();
-        steam_connection.main_loop().await.unwrap()
+    #[tokio::test]
+    async fn refused_encryption_closes_connection() {
+        let (connection, mut server) = in_memory_connection();
+        let (handle, task) = connection.spawn();
+
+        server.send(get_channel_encrypt_request()).await.unwrap();
+        server.next().await.unwrap().unwrap();
+        server.send(get_channel_encrypt_result(2)).await.unwrap();
+
+        let result = task.await.unwrap();
+        assert!(matches!(result, Err(ConnectionError::EncryptionFailed(_))));
+        assert_eq!(handle.encryption_state(), EncryptionState::Disconnected);
+        assert!(!handle.is_connected());
     }
 
     #[tokio::test]
-    #[cfg(not(feature = "websockets"))]
-    async fn test_spawn() {
-        let dumped_cm_servers = dump_tcp_servers().await.unwrap();
-        let mut steam_connection = SteamConnection::new_connection(&dumped_cm_servers[0]).await.unwrap();
+    async fn answers_the_encrypt_request_of_a_fake_cm() {
+        let (transport, mut fake_cm) = FakeCm::new();
+        let handshake = tokio::spawn(async move { fake_cm.accept().await.unwrap() });
+        let mut stream = transport.connect("cm1:27017").await.unwrap();
 
-        let packet_message = steam_connection.read_packets().await.unwrap();
+        let packet_message = stream.next().await.unwrap().unwrap();
         assert_eq!(packet_message.emsg(), EMsg::ChannelEncryptRequest);
 
-        let answer = handle_encrypt_request(packet_message).to_bytes();
-        steam_connection.write_packets(&answer).await.unwrap();
-        let data = steam_connection.read_packets().await.unwrap();
+        let (_session_keys, answer) = handle_encrypt_request(packet_message, Some(fake_cm::PUBLIC_KEY)).unwrap();
+        stream.send(answer.to_bytes()).await.unwrap();
+        let data = stream.next().await.unwrap().unwrap();
         assert_eq!(data.emsg(), EMsg::ChannelEncryptResult);
-        // steam_connection.main_loop().await.unwrap()
-    }
-
-    // #[tokio::test()]
-    // #[cfg(not(feature = "websockets"))]
-    // async fn answer_encrypt_request() {
-    // init();
-    //
-    // let cm_servers = CmServerSvList::fetch_servers(env!("STEAM_API")).await;
-    // let dumped_cm_servers = cm_servers.unwrap().dump_tcp_servers();
-    //
-    // let mut steam_connection: SteamConnection<TcpStream> =
-    // SteamConnection::new_connection(&dumped_cm_servers[0]).await.unwrap(); let data =
-    // steam_connection.read_packets().await.unwrap(); let message = EMsg::from_raw_message(&data);
-    //
-    // assert_eq!(message.unwrap(), EMsg::ChannelEncryptRequest);
-    //
-    //
-    // let answer = handle_encrypt_request(PacketMessage::from_rawdata(&data));
-    // steam_connection.write_packets(answer.as_slice()).await.unwrap();
-    // let data = steam_connection.read_packets().await.unwrap();
-    // let message = EMsg::from_raw_message(&data).unwrap();
-    // assert_eq!(message, EMsg::ChannelEncryptResult);
-    // }
-
-    #[tokio::test(threaded_scheduler)]
-    #[cfg(feature = "websockets")]
-    async fn connect_to_ws_server() {
-        init();
-
-        let get_results = CmServerSvList::fetch_servers("1").await;
-        let fetched_servers = get_results.unwrap().dump_ws_servers();
-
-        let steam_connection = SteamConnection::new_connection(&fetched_servers[0]).await;
-        assert!(steam_connection.is_ok())
+
+        // the fake CM decrypted our session key, and checked it along with the challenge
+        assert_eq!(handshake.await.unwrap().endpoint(), "cm1:27017");
+    }
+
+    #[test]
+    fn bad_public_keys_fail_the_handshake() {
+        let packet_message = PacketMessage::from_raw_bytes(&get_channel_encrypt_request()).unwrap();
+
+        assert!(matches!(
+            handle_encrypt_request(packet_message, Some(b"not a key")),
+            Err(ConnectionError::InvalidPublicKey(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn main_loop_talks_encrypted_to_a_fake_cm() {
+        let (transport, mut fake_cm) = FakeCm::new();
+        let stream = transport.connect("cm1:27017").await.unwrap();
+        let connection =
+            SteamConnection::new(stream, "cm1:27017".to_string(), false).with_public_key(transport.public_key());
+        let (handle, task) = connection.spawn();
+        let mut packets = handle.subscribe();
+
+        // queued before the handshake, so it must only be written after it
+        handle.send(ClientMessage::<MsgClientChatEnter>::new()).unwrap();
+        let mut cm = fake_cm.accept().await.unwrap();
+        cm.expect(EMsg::ClientChatEnter).await;
+        assert_eq!(handle.encryption_state(), EncryptionState::Encrypted);
+
+        cm.send(ClientMessage::<MsgClientChatEnter>::new()).await;
+        assert_eq!(packets.recv().await.unwrap().emsg(), EMsg::ClientChatEnter);
+
+        drop(cm);
+        assert!(matches!(task.await.unwrap(), Err(ConnectionError::Dropped)));
     }
 }
@@ -0,0 +1,158 @@
+//! CM servers batch messages into a single [EMsg::Multi], whose body may be gzipped.
+//!
+//! The body is a sequence of `u32` little endian lengths, each followed by a complete message, EMsg included.
+
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use steam_protobuf::protobufs::steammessages_base::CMsgMulti;
+use steam_protobuf::Message;
+
+use crate::errors::PacketError;
+use crate::messages::codec::MAX_FRAME_SIZE;
+use crate::messages::packet::PacketMessage;
+use crate::messages::MessageKind;
+
+const MESSAGE_LEN_SIZE: usize = 4;
+
+/// Unpacks every message inside a [EMsg::Multi], in the order they were sent.
+///
+/// Inner messages with an EMsg we don't know about are skipped, just like the codec does with whole frames.
+pub(crate) fn unpack_multi(message: &PacketMessage) -> Result<Vec<PacketMessage>, PacketError> {
+    let multi = CMsgMulti::parse_from_bytes(message.payload()).map_err(|_| PacketError::Malformed)?;
+
+    let body = match multi.size_unzipped() as usize {
+        0 => multi.message_body().to_vec(),
+        size_unzipped => gunzip(multi.message_body(), size_unzipped)?,
+    };
+
+    let mut messages = Vec::new();
+    let mut remaining = body.as_slice();
+
+    while !remaining.is_empty() {
+        if remaining.len() < MESSAGE_LEN_SIZE {
+            return Err(PacketError::Malformed);
+        }
+        let (message_len, rest) = remaining.split_at(MESSAGE_LEN_SIZE);
+        let message_len = u32::from_le_bytes([message_len[0], message_len[1], message_len[2], message_len[3]]) as usize;
+
+        if rest.len() < message_len {
+            return Err(PacketError::Malformed);
+        }
+        let (inner_message, rest) = rest.split_at(message_len);
+        remaining = rest;
+
+        match PacketMessage::from_raw_bytes(inner_message) {
+            Ok(packet_message) => messages.push(packet_message),
+            Err(err) => warn!("Skipping undecodable message inside Multi: {}", err),
+        }
+    }
+
+    Ok(messages)
+}
+
+fn gunzip(compressed: &[u8], size_unzipped: usize) -> Result<Vec<u8>, PacketError> {
+    if size_unzipped > MAX_FRAME_SIZE {
+        return Err(PacketError::FrameTooLarge(size_unzipped));
+    }
+
+    let mut decompressed = Vec::with_capacity(size_unzipped);
+    // never inflate past what Steam told us, one extra byte is enough to catch the mismatch
+    GzDecoder::new(compressed)
+        .take(size_unzipped as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+
+    if decompressed.len() != size_unzipped {
+        return Err(PacketError::Malformed);
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use steam_language_gen::generated::enums::EMsg;
+    use steam_language_gen::generated::messages::MsgClientChatEnter;
+    use steam_language_gen::MessageHeaderWrapper;
+    use steam_language_gen::SerializableBytes;
+    use steam_protobuf::protobufs::steammessages_base::CMsgProtoBufHeader;
+
+    use super::*;
+    use crate::messages::message::ClientMessage;
+
+    /// ChannelEncryptResult, ClientChatEnter, and an unknown EMsg in between.
+    fn inner_messages() -> Vec<Vec<u8>> {
+        vec![
+            vec![
+                25, 5, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1, 0, 0, 0,
+            ],
+            vec![0xFF, 0xFF, 0xFF, 0x7F],
+            ClientMessage::<MsgClientChatEnter>::new().to_bytes(),
+        ]
+    }
+
+    fn multi_body() -> Vec<u8> {
+        inner_messages()
+            .iter()
+            .flat_map(|message| {
+                let mut framed = (message.len() as u32).to_le_bytes().to_vec();
+                framed.extend_from_slice(message);
+                framed
+            })
+            .collect()
+    }
+
+    fn multi_packet(size_unzipped: u32, message_body: Vec<u8>) -> PacketMessage {
+        let mut multi = CMsgMulti::new();
+        multi.set_size_unzipped(size_unzipped);
+        multi.set_message_body(message_body.into());
+
+        PacketMessage::new(
+            EMsg::Multi,
+            MessageHeaderWrapper::Proto(CMsgProtoBufHeader::new()),
+            multi.write_to_bytes().unwrap(),
+        )
+    }
+
+    #[test]
+    fn unpacks_plain_multi_in_order() {
+        let messages = unpack_multi(&multi_packet(0, multi_body())).unwrap();
+
+        let emsgs: Vec<EMsg> = messages.iter().map(PacketMessage::emsg).collect();
+        assert_eq!(emsgs, vec![EMsg::ChannelEncryptResult, EMsg::ClientChatEnter]);
+    }
+
+    #[test]
+    fn unpacks_gzipped_multi() {
+        let body = multi_body();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).unwrap();
+
+        let messages = unpack_multi(&multi_packet(body.len() as u32, encoder.finish().unwrap())).unwrap();
+        assert_eq!(messages, unpack_multi(&multi_packet(0, body)).unwrap());
+    }
+
+    #[test]
+    fn wrong_unzipped_size_is_malformed() {
+        let body = multi_body();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).unwrap();
+
+        let result = unpack_multi(&multi_packet(body.len() as u32 - 1, encoder.finish().unwrap()));
+        assert!(matches!(result, Err(PacketError::Malformed)));
+    }
+
+    #[test]
+    fn truncated_body_is_malformed() {
+        let mut body = multi_body();
+        body.truncate(body.len() - 1);
+
+        assert!(matches!(
+            unpack_multi(&multi_packet(0, body)),
+            Err(PacketError::Malformed)
+        ));
+    }
+}
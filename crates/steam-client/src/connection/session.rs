@@ -0,0 +1,168 @@
+//! Once logged on, Steam expects every message to carry the SteamID and session id it handed us on
+//! [EMsg::ClientLogOnResponse].
+
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::enums::EResult;
+use steam_language_gen::MessageHeaderWrapper;
+use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogonResponse;
+use steam_protobuf::Message;
+
+use crate::messages::packet::PacketMessage;
+use crate::messages::MessageKind;
+
+/// Session assigned by Steam to this connection, shared between the connection actor and its handles.
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    steam_id: AtomicU64,
+    session_id: AtomicI32,
+    heartbeat_seconds: AtomicI32,
+}
+
+impl Session {
+    /// SteamID we are logged on as, or zero.
+    pub fn steam_id(&self) -> u64 {
+        self.steam_id.load(Ordering::Acquire)
+    }
+
+    /// Session id we are logged on with, or zero.
+    pub fn session_id(&self) -> i32 {
+        self.session_id.load(Ordering::Acquire)
+    }
+
+    /// How often Steam expects a heartbeat from us, while logged on.
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        match self.heartbeat_seconds.load(Ordering::Acquire) {
+            seconds if seconds > 0 && self.is_logged_on() => Some(Duration::from_secs(seconds as u64)),
+            _ => None,
+        }
+    }
+
+    pub fn is_logged_on(&self) -> bool {
+        self.steam_id() != 0
+    }
+
+    pub fn clear(&self) {
+        self.steam_id.store(0, Ordering::Release);
+        self.session_id.store(0, Ordering::Release);
+        self.heartbeat_seconds.store(0, Ordering::Release);
+    }
+
+    /// Keeps track of the session through logon and logoff messages, before they reach any subscriber.
+    pub fn track(&self, packet_message: &PacketMessage) {
+        match packet_message.emsg() {
+            EMsg::ClientLogOnResponse => {
+                let header = match packet_message.header() {
+                    MessageHeaderWrapper::Proto(header) => header,
+                    _ => return,
+                };
+                let response = match CMsgClientLogonResponse::parse_from_bytes(packet_message.payload()) {
+                    Ok(response) if response.eresult() == EResult::OK as i32 => response,
+                    _ => return,
+                };
+
+                self.steam_id.store(header.steamid(), Ordering::Release);
+                self.session_id.store(header.client_sessionid(), Ordering::Release);
+                self.heartbeat_seconds
+                    .store(heartbeat_seconds(&response), Ordering::Release);
+            }
+            EMsg::ClientLoggedOff => self.clear(),
+            _ => {}
+        }
+    }
+
+    /// Writes the current session into an outgoing header. Messages sent before logon are left untouched, so the
+    /// logon message itself can carry the SteamID it is logging on as.
+    pub fn stamp(&self, header: &mut MessageHeaderWrapper) {
+        if !self.is_logged_on() {
+            return;
+        }
+
+        match header {
+            MessageHeaderWrapper::Proto(header) => {
+                header.set_steamid(self.steam_id());
+                header.set_client_sessionid(self.session_id());
+            }
+            MessageHeaderWrapper::Ext(header) => {
+                header.steam_id = self.steam_id();
+                header.session_id = self.session_id();
+            }
+            MessageHeaderWrapper::Std(_) => {}
+        }
+    }
+}
+
+/// Newer CMs send `heartbeat_seconds`, older ones only the legacy field.
+pub(crate) fn heartbeat_seconds(response: &CMsgClientLogonResponse) -> i32 {
+    match response.heartbeat_seconds() {
+        0 => response.legacy_out_of_game_heartbeat_seconds(),
+        seconds => seconds,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use steam_protobuf::protobufs::steammessages_base::CMsgProtoBufHeader;
+
+    use super::*;
+
+    fn logon_response(eresult: EResult) -> PacketMessage {
+        let mut header = CMsgProtoBufHeader::new();
+        header.set_steamid(76561197960287930);
+        header.set_client_sessionid(1234567);
+
+        let mut response = CMsgClientLogonResponse::new();
+        response.set_eresult(eresult as i32);
+        response.set_heartbeat_seconds(9);
+
+        PacketMessage::new(
+            EMsg::ClientLogOnResponse,
+            MessageHeaderWrapper::Proto(header),
+            response.write_to_bytes().unwrap(),
+        )
+    }
+
+    #[test]
+    fn successful_logon_is_stamped_on_outgoing_headers() {
+        let session = Session::default();
+        session.track(&logon_response(EResult::OK));
+
+        let mut header = MessageHeaderWrapper::Proto(CMsgProtoBufHeader::new());
+        session.stamp(&mut header);
+
+        match header {
+            MessageHeaderWrapper::Proto(header) => {
+                assert_eq!(header.steamid(), 76561197960287930);
+                assert_eq!(header.client_sessionid(), 1234567);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn failed_logon_keeps_session_empty() {
+        let session = Session::default();
+        session.track(&logon_response(EResult::InvalidPassword));
+
+        assert!(!session.is_logged_on());
+        assert_eq!(session.heartbeat_interval(), None);
+    }
+
+    #[test]
+    fn logged_off_clears_session() {
+        let session = Session::default();
+        session.track(&logon_response(EResult::OK));
+        session.track(&PacketMessage::new(
+            EMsg::ClientLoggedOff,
+            MessageHeaderWrapper::Proto(CMsgProtoBufHeader::new()),
+            vec![],
+        ));
+
+        assert!(!session.is_logged_on());
+        assert_eq!(session.session_id(), 0);
+    }
+}
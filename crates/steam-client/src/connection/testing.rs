@@ -0,0 +1,63 @@
+//! Steam's side of a [ConnectionHandle::detached], for the tests of handlers.
+//!
+//! [ConnectionHandle::detached]: crate::connection::ConnectionHandle::detached
+
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::enums::EResult;
+use steam_language_gen::MessageHeaderWrapper;
+use steam_language_gen::SerializableBytes;
+use steam_protobuf::Message;
+use steam_protobuf::ProtobufSerialize;
+
+use crate::connection::DynBytes;
+use crate::messages::message::ClientMessage;
+use crate::messages::packet::PacketMessage;
+
+/// Reads back a message a handler sent.
+pub(crate) fn sent_packet(sent: DynBytes) -> PacketMessage {
+    PacketMessage::from_raw_bytes(&sent.to_bytes()).unwrap()
+}
+
+/// Reads back a service method call a handler sent, checking which method it is for.
+///
+/// Returns the job id to reply to, and the request.
+pub(crate) fn sent_call<M: Message>(sent: DynBytes, method: &str) -> (u64, M) {
+    let request = sent_packet(sent);
+    match request.header() {
+        MessageHeaderWrapper::Proto(header) => assert_eq!(header.target_job_name(), method),
+        header => panic!("expected a protobuf header, got {:?}", header),
+    }
+    (request.jobs_ids().0, request.decode().unwrap())
+}
+
+/// A protobuf message, as if Steam sent it on its own.
+pub(crate) fn proto_packet<M: Message + ProtobufSerialize>(emsg: EMsg, body: M) -> PacketMessage {
+    let mut message = ClientMessage::<M>::new_proto(emsg);
+    message.body = body;
+    PacketMessage::from_raw_bytes(&message.to_bytes()).unwrap()
+}
+
+/// A service method notification, such as an incoming friend message.
+pub(crate) fn notification_packet<M: Message + ProtobufSerialize>(method: &str, body: M) -> PacketMessage {
+    let mut message = ClientMessage::<M>::new_proto(EMsg::ServiceMethod);
+    if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+        header.set_target_job_name(method.to_string());
+    }
+    message.body = body;
+    PacketMessage::from_raw_bytes(&message.to_bytes()).unwrap()
+}
+
+/// A successful reply to the job `job_id`.
+pub(crate) fn job_reply<M: Message + ProtobufSerialize>(emsg: EMsg, job_id: u64, body: M) -> PacketMessage {
+    let mut message = ClientMessage::<M>::new_proto(emsg);
+    if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+        header.set_eresult(EResult::OK as i32);
+    }
+    message.body = body;
+    PacketMessage::from_raw_bytes(&message.set_target(job_id).to_bytes()).unwrap()
+}
+
+/// A successful reply to the service method call `job_id`.
+pub(crate) fn reply<M: Message + ProtobufSerialize>(job_id: u64, body: M) -> PacketMessage {
+    job_reply(EMsg::ServiceMethodResponse, job_id, body)
+}
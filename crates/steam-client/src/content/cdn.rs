@@ -0,0 +1,48 @@
+//! Where depot chunks are downloaded from.
+
+use std::fmt;
+
+use async_trait::async_trait;
+
+use crate::errors::DownloadError;
+
+/// Somewhere to download depot chunks from.
+#[async_trait]
+pub trait CdnClient: fmt::Debug + Send + Sync {
+    /// Downloads a chunk of a depot, still encrypted and compressed, given its id in hex.
+    async fn chunk(&self, depot_id: u32, chunk_id: &str) -> Result<Vec<u8>, DownloadError>;
+}
+
+/// A Steam content server, or anything serving chunks on the same paths, such as a local mirror.
+#[derive(Debug, Clone)]
+pub struct HttpCdn {
+    client: reqwest::Client,
+    host: String,
+}
+
+impl HttpCdn {
+    /// Content server at `host`, scheme included, such as `https://cache1-fra1.steamcontent.com`.
+    pub fn new<T: Into<String>>(host: T) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            host: host.into().trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl CdnClient for HttpCdn {
+    async fn chunk(&self, depot_id: u32, chunk_id: &str) -> Result<Vec<u8>, DownloadError> {
+        let url = format!("{}/depot/{}/chunk/{}", self.host, depot_id, chunk_id);
+        let cdn_error = |err: reqwest::Error| DownloadError::Cdn(chunk_id.to_string(), err.to_string());
+
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(cdn_error)?;
+        Ok(response.bytes().await.map_err(cdn_error)?.to_vec())
+    }
+}
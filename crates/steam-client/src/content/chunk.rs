@@ -0,0 +1,233 @@
+//! Turning a chunk, as downloaded from the CDN, back into the part of the file it holds.
+//!
+//! Chunks are encrypted with the depot key just like messages are with the session key, and compressed in one of
+//! three ways, told apart by their first bytes:
+//!
+//! * `VZa`: LZMA, between a header with the LZMA properties and a footer with the decompressed size;
+//! * `VSZa`: Zstandard, between a header and a footer with the decompressed size;
+//! * `PK`: a zip with a single deflated file, from older depots.
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Steam/CDN/DepotChunk.cs
+
+use std::io::Cursor;
+
+use lzma_rs::decompress::Options;
+use lzma_rs::decompress::UnpackedSize;
+use steam_crypto::symm::symmetric_decrypt;
+
+use crate::content::is_zip;
+use crate::content::manifest::ManifestChunk;
+use crate::content::unzip;
+use crate::errors::DownloadError;
+
+const VZIP_MAGIC: &[u8] = b"VZa";
+/// Magic, then a timestamp or CRC we do not need.
+const VZIP_HEADER_SIZE: usize = 7;
+/// CRC32 and decompressed size, then the magic reversed.
+const VZIP_FOOTER_SIZE: usize = 10;
+const VZIP_FOOTER_MAGIC: &[u8] = b"zv";
+
+const VZSTD_MAGIC: &[u8] = b"VSZa";
+/// Magic, then a CRC32 we do not need.
+const VZSTD_HEADER_SIZE: usize = 8;
+/// CRC32, decompressed size, four unknown bytes, then the magic reversed.
+const VZSTD_FOOTER_SIZE: usize = 15;
+const VZSTD_FOOTER_MAGIC: &[u8] = b"zsv";
+
+/// Decrypts, decompresses and checks a chunk downloaded from the CDN, returning its contents.
+pub(crate) fn process_chunk(chunk: &ManifestChunk, data: &[u8], depot_key: &[u8]) -> Result<Vec<u8>, DownloadError> {
+    let decrypted = symmetric_decrypt(data, depot_key, false).map_err(|_| DownloadError::Decrypt(chunk.id_hex()))?;
+
+    // the sizes on the footers come from the CDN, only the manifest is trusted to say how much to allocate
+    let size = chunk.original_size as usize;
+    let decompressed = if decrypted.starts_with(VZIP_MAGIC) {
+        decompress_vzip(&decrypted, size)
+    } else if decrypted.starts_with(VZSTD_MAGIC) {
+        decompress_vzstd(&decrypted, size)
+    } else if is_zip(&decrypted) {
+        unzip(&decrypted)
+    } else {
+        return Err(DownloadError::UnknownCompression(chunk.id_hex()));
+    };
+    let decompressed = decompressed.ok_or_else(|| DownloadError::Decompress(chunk.id_hex()))?;
+
+    if !is_valid(chunk, &decompressed) {
+        return Err(DownloadError::Checksum(chunk.id_hex()));
+    }
+    Ok(decompressed)
+}
+
+/// Whether `contents` are the ones the manifest lists for the chunk, checked both by size, Adler-32 and SHA-1.
+pub(crate) fn is_valid(chunk: &ManifestChunk, contents: &[u8]) -> bool {
+    contents.len() == chunk.original_size as usize
+        && adler_hash(contents) == chunk.checksum
+        && steam_crypto::sha1_hash(contents)[..] == chunk.id[..]
+}
+
+/// Adler-32, but seeded with zero instead of one, as Steam does.
+fn adler_hash(input: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+
+    let (a, b) = input.iter().fold((0u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % MOD_ADLER;
+        (a, (b + a) % MOD_ADLER)
+    });
+    a | (b << 16)
+}
+
+fn decompressed_size(data: &[u8], footer_size: usize) -> usize {
+    let at = data.len() - footer_size + 4;
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+}
+
+fn decompress_vzip(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    if data.len() < VZIP_HEADER_SIZE + VZIP_FOOTER_SIZE || !data.ends_with(VZIP_FOOTER_MAGIC) {
+        return None;
+    }
+    if decompressed_size(data, VZIP_FOOTER_SIZE) != size {
+        return None;
+    }
+
+    // the LZMA properties follow the header, but the size is only on the footer
+    let options = Options {
+        unpacked_size: UnpackedSize::UseProvided(Some(size as u64)),
+        ..Options::default()
+    };
+    let mut compressed = Cursor::new(&data[VZIP_HEADER_SIZE..data.len() - VZIP_FOOTER_SIZE]);
+    let mut decompressed = Vec::with_capacity(size);
+    lzma_rs::lzma_decompress_with_options(&mut compressed, &mut decompressed, &options).ok()?;
+    Some(decompressed)
+}
+
+fn decompress_vzstd(data: &[u8], size: usize) -> Option<Vec<u8>> {
+    if data.len() < VZSTD_HEADER_SIZE + VZSTD_FOOTER_SIZE || !data.ends_with(VZSTD_FOOTER_MAGIC) {
+        return None;
+    }
+    if decompressed_size(data, VZSTD_FOOTER_SIZE) != size {
+        return None;
+    }
+
+    zstd::bulk::decompress(&data[VZSTD_HEADER_SIZE..data.len() - VZSTD_FOOTER_SIZE], size).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use lzma_rs::compress;
+    use steam_crypto::symm::symmetric_encrypt_with_iv;
+
+    use super::*;
+    use crate::content::tests::zip;
+
+    pub(crate) const DEPOT_KEY: [u8; 32] = [3; 32];
+
+    /// Chunk as listed on a manifest, for `contents` at `offset`.
+    pub(crate) fn manifest_chunk(contents: &[u8], offset: u64) -> ManifestChunk {
+        ManifestChunk {
+            id: steam_crypto::sha1_hash(contents).to_vec(),
+            checksum: adler_hash(contents),
+            offset,
+            original_size: contents.len() as u32,
+            compressed_size: 0,
+        }
+    }
+
+    fn footer(contents: &[u8]) -> Vec<u8> {
+        let mut footer = steam_crypto::crc_hash(contents);
+        footer.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+        footer
+    }
+
+    fn vzip(contents: &[u8]) -> Vec<u8> {
+        let options = compress::Options {
+            unpacked_size: compress::UnpackedSize::SkipWritingToHeader,
+        };
+        let mut lzma = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut Cursor::new(contents), &mut lzma, &options).unwrap();
+
+        let mut chunk = VZIP_MAGIC.to_vec();
+        chunk.extend_from_slice(&[0; 4]);
+        chunk.extend_from_slice(&lzma);
+        chunk.extend_from_slice(&footer(contents));
+        chunk.extend_from_slice(VZIP_FOOTER_MAGIC);
+        chunk
+    }
+
+    fn vzstd(contents: &[u8]) -> Vec<u8> {
+        let mut chunk = VZSTD_MAGIC.to_vec();
+        chunk.extend_from_slice(&[0; 4]);
+        chunk.extend_from_slice(&zstd::bulk::compress(contents, 0).unwrap());
+        chunk.extend_from_slice(&footer(contents));
+        chunk.extend_from_slice(&[0; 4]);
+        chunk.extend_from_slice(VZSTD_FOOTER_MAGIC);
+        chunk
+    }
+
+    /// Encrypts with a fixed IV, so decrypting with the wrong key always fails the same way.
+    fn encrypt(data: &[u8]) -> Vec<u8> {
+        symmetric_encrypt_with_iv(data, &DEPOT_KEY, &[0; 16]).unwrap()
+    }
+
+    /// Chunk as the CDN would serve it, compressed with LZMA.
+    pub(crate) fn cdn_chunk(contents: &[u8]) -> Vec<u8> {
+        encrypt(&vzip(contents))
+    }
+
+    #[test]
+    fn adler_is_seeded_with_zero() {
+        assert_eq!(adler_hash(b""), 0);
+        // regular Adler-32 of "Wikipedia" is 0x11E60398, one less per byte on the second half
+        assert_eq!(adler_hash(b"Wikipedia"), 0x11E6_0398 - 1 - (9 << 16));
+    }
+
+    #[test]
+    fn processes_every_compression() {
+        let contents = b"\x7fELF and some more bytes, which compress well, well, well".to_vec();
+        let chunk = manifest_chunk(&contents, 0);
+
+        for compressed in &[vzip(&contents), vzstd(&contents), zip(&contents)] {
+            let downloaded = encrypt(compressed);
+            assert_eq!(process_chunk(&chunk, &downloaded, &DEPOT_KEY).unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_chunks() {
+        let contents = b"contents";
+        let chunk = manifest_chunk(contents, 0);
+
+        assert!(matches!(
+            process_chunk(&chunk, &cdn_chunk(contents), &[4; 32]),
+            Err(DownloadError::Decrypt(_))
+        ));
+        assert!(matches!(
+            process_chunk(&chunk, &encrypt(contents), &DEPOT_KEY),
+            Err(DownloadError::UnknownCompression(_))
+        ));
+        assert!(matches!(
+            process_chunk(&chunk, &encrypt(b"VZa"), &DEPOT_KEY),
+            Err(DownloadError::Decompress(_))
+        ));
+        assert!(matches!(
+            process_chunk(&manifest_chunk(b"CONTENTS", 0), &cdn_chunk(contents), &DEPOT_KEY),
+            Err(DownloadError::Checksum(_))
+        ));
+    }
+
+    #[test]
+    fn footer_sizes_must_match_the_manifest() {
+        let contents = b"contents";
+        let chunk = manifest_chunk(contents, 0);
+
+        let compressed = [(vzip(contents), VZIP_FOOTER_SIZE), (vzstd(contents), VZSTD_FOOTER_SIZE)];
+        for (mut lying, footer_size) in compressed {
+            // as if the CDN claimed the chunk decompresses to 4 GiB
+            let at = lying.len() - footer_size + 4;
+            lying[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+            assert!(matches!(
+                process_chunk(&chunk, &encrypt(&lying), &DEPOT_KEY),
+                Err(DownloadError::Decompress(_))
+            ));
+        }
+    }
+}
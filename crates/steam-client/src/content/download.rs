@@ -0,0 +1,373 @@
+//! Downloading the files of a depot, as listed on one of its manifests.
+//!
+//! Chunks already on disk are checked against the manifest before anything is downloaded, so an interrupted
+//! download picks up where it stopped, and an older build is updated by only downloading the chunks that changed.
+
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use futures::stream;
+use futures::StreamExt;
+use steam_language_gen::generated::enums::EDepotFileFlag;
+
+use crate::content::cdn::CdnClient;
+use crate::content::chunk::is_valid;
+use crate::content::chunk::process_chunk;
+use crate::content::manifest::DepotManifest;
+use crate::content::manifest::ManifestChunk;
+use crate::content::manifest::ManifestFile;
+use crate::errors::DownloadError;
+
+/// Chunks downloaded at the same time, by default.
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// What a download did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadStats {
+    /// Files written, directories not included.
+    pub files: usize,
+    /// Chunks downloaded from the CDN.
+    pub chunks_downloaded: usize,
+    /// Chunks that were already on disk.
+    pub chunks_reused: usize,
+    /// Bytes downloaded from the CDN, as they were before decryption and decompression.
+    pub bytes_downloaded: u64,
+}
+
+/// Downloads the files of a depot from a [CdnClient].
+pub struct DepotDownloader<C> {
+    cdn: C,
+    depot_key: Vec<u8>,
+    concurrency: usize,
+}
+
+impl<C: fmt::Debug> fmt::Debug for DepotDownloader<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DepotDownloader")
+            .field("cdn", &self.cdn)
+            .field("concurrency", &self.concurrency)
+            .finish()
+    }
+}
+
+impl<C: CdnClient> DepotDownloader<C> {
+    /// Downloads from `cdn`, decrypting chunks with the key of the depot.
+    pub fn new(cdn: C, depot_key: &[u8]) -> Self {
+        Self {
+            cdn,
+            depot_key: depot_key.to_vec(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// How many chunks are downloaded at the same time, at least one.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Writes every file of `manifest` under `target`, downloading only the chunks that are not there already.
+    ///
+    /// Filenames are decrypted first if needed. Symlinks are not created.
+    pub async fn download(&self, manifest: &DepotManifest, target: &Path) -> Result<DownloadStats, DownloadError> {
+        let decrypted;
+        let manifest = if manifest.filenames_encrypted {
+            let mut manifest = manifest.clone();
+            manifest.decrypt_filenames(&self.depot_key)?;
+            decrypted = manifest;
+            &decrypted
+        } else {
+            manifest
+        };
+
+        let mut stats = DownloadStats::default();
+        for file in &manifest.files {
+            let path = file_path(target, &file.name)?;
+
+            if file.is_directory() {
+                blocking(move || Ok(fs::create_dir_all(path)?)).await?;
+                continue;
+            }
+            if file.flags.contains(EDepotFileFlag::Symlink) {
+                debug!("Skipping symlink {} of depot {}.", file.name, manifest.depot_id);
+                continue;
+            }
+
+            self.download_file(manifest.depot_id, file, path, &mut stats).await?;
+        }
+        Ok(stats)
+    }
+
+    async fn download_file(
+        &self,
+        depot_id: u32,
+        file: &ManifestFile,
+        path: PathBuf,
+        stats: &mut DownloadStats,
+    ) -> Result<(), DownloadError> {
+        let chunks = file.chunks.clone();
+        let (mut output, missing) = blocking(move || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut output = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+
+            let missing: Vec<ManifestChunk> = chunks
+                .into_iter()
+                .filter(|chunk| !has_chunk(&mut output, chunk))
+                .collect();
+            Ok((output, missing))
+        })
+        .await?;
+        stats.chunks_reused += file.chunks.len() - missing.len();
+
+        let mut downloads = stream::iter(missing)
+            .map(|chunk| async move {
+                let downloaded = self.cdn.chunk(depot_id, &chunk.id_hex()).await?;
+                let depot_key = self.depot_key.clone();
+                blocking(move || {
+                    let contents = process_chunk(&chunk, &downloaded, &depot_key)?;
+                    Ok((chunk.offset, contents, downloaded.len()))
+                })
+                .await
+            })
+            .buffer_unordered(self.concurrency);
+
+        while let Some(downloaded) = downloads.next().await {
+            let (offset, contents, downloaded_len) = downloaded?;
+            output = blocking(move || {
+                output.seek(SeekFrom::Start(offset))?;
+                output.write_all(&contents)?;
+                Ok(output)
+            })
+            .await?;
+
+            stats.chunks_downloaded += 1;
+            stats.bytes_downloaded += downloaded_len as u64;
+        }
+
+        // the file may have been bigger on an older build
+        let size = file.size;
+        blocking(move || Ok(output.set_len(size)?)).await?;
+        stats.files += 1;
+        Ok(())
+    }
+}
+
+/// Runs disk work and hashing away from the runtime, so they do not hold up the connection to Steam.
+async fn blocking<T, F>(work: F) -> Result<T, DownloadError>
+where
+    F: FnOnce() -> Result<T, DownloadError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work).await.map_err(io::Error::other)?
+}
+
+/// Whether the chunk is already written to `file`, and intact.
+fn has_chunk(file: &mut File, chunk: &ManifestChunk) -> bool {
+    let mut contents = vec![0; chunk.original_size as usize];
+    let read = file
+        .seek(SeekFrom::Start(chunk.offset))
+        .and_then(|_| file.read_exact(&mut contents));
+    read.is_ok() && is_valid(chunk, &contents)
+}
+
+/// Where a file of the depot goes under `target`, refusing names that would escape it.
+fn file_path(target: &Path, name: &str) -> Result<PathBuf, DownloadError> {
+    let mut path = target.to_path_buf();
+    for component in name.split(['\\', '/']).filter(|c| !c.is_empty() && *c != ".") {
+        if component == ".." || component.contains(':') {
+            return Err(DownloadError::UnsafePath(name.to_string()));
+        }
+        path.push(component);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::content::chunk::tests::cdn_chunk;
+    use crate::content::chunk::tests::manifest_chunk;
+    use crate::content::chunk::tests::DEPOT_KEY;
+    use crate::content::manifest::tests::depot_manifest;
+
+    /// CDN serving chunks from memory, remembering which ones were asked for.
+    #[derive(Debug, Clone, Default)]
+    struct FakeCdn {
+        chunks: HashMap<String, Vec<u8>>,
+        requested: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FakeCdn {
+        fn requested(&self) -> Vec<String> {
+            std::mem::take(&mut *self.requested.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl CdnClient for FakeCdn {
+        async fn chunk(&self, depot_id: u32, chunk_id: &str) -> Result<Vec<u8>, DownloadError> {
+            assert_eq!(depot_id, 731);
+            self.requested.lock().unwrap().push(chunk_id.to_string());
+            self.chunks
+                .get(chunk_id)
+                .cloned()
+                .ok_or_else(|| DownloadError::Cdn(chunk_id.to_string(), "404 Not Found".to_string()))
+        }
+    }
+
+    /// A file made of `parts`, one chunk each, all of them served by `cdn`.
+    fn file(cdn: &mut FakeCdn, name: &str, parts: &[&[u8]]) -> ManifestFile {
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        for part in parts {
+            let chunk = manifest_chunk(part, offset);
+            cdn.chunks.insert(chunk.id_hex(), cdn_chunk(part));
+            offset += part.len() as u64;
+            chunks.push(chunk);
+        }
+
+        ManifestFile {
+            name: name.to_string(),
+            size: offset,
+            flags: EDepotFileFlag::empty(),
+            name_hash: Vec::new(),
+            content_hash: Vec::new(),
+            link_target: None,
+            chunks,
+        }
+    }
+
+    fn directory(name: &str) -> ManifestFile {
+        ManifestFile {
+            name: name.to_string(),
+            size: 0,
+            flags: EDepotFileFlag::Directory,
+            name_hash: Vec::new(),
+            content_hash: Vec::new(),
+            link_target: None,
+            chunks: Vec::new(),
+        }
+    }
+
+    fn target(name: &str) -> PathBuf {
+        let target = std::env::temp_dir().join(format!("depot_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&target);
+        target
+    }
+
+    #[tokio::test]
+    async fn downloads_and_resumes_depots() {
+        let mut cdn = FakeCdn::default();
+        let manifest = depot_manifest(vec![
+            directory("cfg"),
+            file(&mut cdn, "bin\\game.exe", &[b"MZ header ", b"and the rest"]),
+            file(&mut cdn, "readme.txt", &[b"hello"]),
+        ]);
+        let downloader = DepotDownloader::new(cdn.clone(), &DEPOT_KEY).with_concurrency(2);
+        let target = target("resume");
+
+        let stats = downloader.download(&manifest, &target).await.unwrap();
+        assert_eq!(stats.files, 2);
+        assert_eq!(stats.chunks_downloaded, 3);
+        assert_eq!(stats.chunks_reused, 0);
+        assert!(target.join("cfg").is_dir());
+        assert_eq!(
+            fs::read(target.join("bin").join("game.exe")).unwrap(),
+            b"MZ header and the rest"
+        );
+        assert_eq!(fs::read(target.join("readme.txt")).unwrap(), b"hello");
+        assert_eq!(cdn.requested().len(), 3);
+
+        // corrupt the second chunk of the executable, as if the download had stopped halfway through it
+        let mut game = OpenOptions::new()
+            .write(true)
+            .open(target.join("bin").join("game.exe"))
+            .unwrap();
+        game.seek(SeekFrom::Start(15)).unwrap();
+        game.write_all(b"XX").unwrap();
+
+        let stats = downloader.download(&manifest, &target).await.unwrap();
+        assert_eq!(stats.chunks_downloaded, 1);
+        assert_eq!(stats.chunks_reused, 2);
+        assert_eq!(cdn.requested(), vec![manifest.files[1].chunks[1].id_hex()]);
+        assert_eq!(
+            fs::read(target.join("bin").join("game.exe")).unwrap(),
+            b"MZ header and the rest"
+        );
+
+        fs::remove_dir_all(target).unwrap();
+    }
+
+    #[tokio::test]
+    async fn files_are_truncated_to_their_new_size() {
+        let mut cdn = FakeCdn::default();
+        let manifest = depot_manifest(vec![file(&mut cdn, "save.dat", &[b"short"])]);
+        let target = target("truncate");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("save.dat"), b"short, but longer on the older build").unwrap();
+
+        let stats = DepotDownloader::new(cdn, &DEPOT_KEY)
+            .download(&manifest, &target)
+            .await
+            .unwrap();
+        assert_eq!(stats.chunks_reused, 1);
+        assert_eq!(fs::read(target.join("save.dat")).unwrap(), b"short");
+
+        fs::remove_dir_all(target).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_errors() {
+        let mut cdn = FakeCdn::default();
+        let mut missing = file(&mut cdn, "missing.txt", &[b"gone"]);
+        missing.chunks[0].id = vec![0; 20];
+        let downloader = DepotDownloader::new(cdn.clone(), &DEPOT_KEY);
+        let target = target("errors");
+
+        let escaping = depot_manifest(vec![file(&mut cdn, "..\\..\\evil.dll", &[b"evil"])]);
+        assert!(matches!(
+            downloader.download(&escaping, &target).await,
+            Err(DownloadError::UnsafePath(_))
+        ));
+
+        let manifest = depot_manifest(vec![missing]);
+        assert!(matches!(
+            downloader.download(&manifest, &target).await,
+            Err(DownloadError::Cdn(_, _))
+        ));
+
+        fs::remove_dir_all(target).unwrap();
+    }
+
+    #[test]
+    fn file_paths() {
+        let target = Path::new("depot");
+        assert_eq!(
+            file_path(target, "bin\\win64/./game.exe").unwrap(),
+            target.join("bin").join("win64").join("game.exe")
+        );
+        assert!(file_path(target, "C:\\Windows").is_err());
+    }
+}
@@ -0,0 +1,470 @@
+//! Depot manifests, listing every file of a depot at one of its builds and the chunks each file is made of.
+//!
+//! The CDN serves manifests zipped. Inside is a sequence of sections, each an `u32` magic and an `u32` length, both
+//! little endian, followed by a protobuf: the payload with the files, its metadata and its signature.
+//!
+//! Manifests of most depots have their filenames encrypted with the depot key, which [DepotManifest::decrypt_filenames]
+//! reverses. Two manifests of the same depot, as saved from different builds, can be compared with
+//! [DepotManifest::diff] without ever going online.
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Types/DepotManifest.cs
+
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use steam_crypto::symm::symmetric_decrypt;
+use steam_language_gen::generated::enums::EDepotFileFlag;
+use steam_protobuf::protobufs::content_manifest::content_manifest_payload::file_mapping::ChunkData;
+use steam_protobuf::protobufs::content_manifest::content_manifest_payload::FileMapping;
+use steam_protobuf::protobufs::content_manifest::ContentManifestMetadata;
+use steam_protobuf::protobufs::content_manifest::ContentManifestPayload;
+use steam_protobuf::protobufs::content_manifest::ContentManifestSignature;
+use steam_protobuf::Message;
+
+use crate::content::is_zip;
+use crate::content::unzip;
+use crate::errors::ManifestError;
+
+const PAYLOAD_MAGIC: u32 = 0x71F6_17D0;
+const METADATA_MAGIC: u32 = 0x1F48_12BE;
+const SIGNATURE_MAGIC: u32 = 0x1B81_B817;
+const END_OF_MANIFEST_MAGIC: u32 = 0x32C4_15AB;
+/// Manifests from before protobufs, which are no longer served.
+const LEGACY_MAGIC: u32 = 0x1634_9781;
+
+/// A chunk of a file, as downloaded from the CDN.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ManifestChunk {
+    /// SHA-1 of the chunk once decrypted and decompressed, which is also how the CDN knows it.
+    pub id: Vec<u8>,
+    /// Adler-32 of the chunk once decrypted and decompressed.
+    pub checksum: u32,
+    /// Where in the file the chunk goes.
+    pub offset: u64,
+    /// Size once decrypted and decompressed, in bytes.
+    pub original_size: u32,
+    /// Size as downloaded, in bytes.
+    pub compressed_size: u32,
+}
+
+impl ManifestChunk {
+    /// [ManifestChunk::id] in lowercase hex, as used on CDN urls.
+    pub fn id_hex(&self) -> String {
+        self.id.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn from_proto(chunk: &ChunkData) -> Self {
+        Self {
+            id: chunk.sha().to_vec(),
+            checksum: chunk.crc(),
+            offset: chunk.offset(),
+            original_size: chunk.cb_original(),
+            compressed_size: chunk.cb_compressed(),
+        }
+    }
+}
+
+/// A file, directory or symlink of a depot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestFile {
+    /// Path relative to the depot root, with Windows separators. Base64 while the filenames are still encrypted.
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+    /// Whether it is a directory, executable, and so on.
+    pub flags: EDepotFileFlag,
+    /// SHA-1 of the lowercase path, which stays the same whether the filenames are encrypted or not.
+    pub name_hash: Vec<u8>,
+    /// SHA-1 of the whole file.
+    pub content_hash: Vec<u8>,
+    /// Where it points to, for symlinks.
+    pub link_target: Option<String>,
+    /// Chunks, ordered by offset.
+    pub chunks: Vec<ManifestChunk>,
+}
+
+impl ManifestFile {
+    /// Whether it is a directory, and thus has no chunks.
+    pub fn is_directory(&self) -> bool {
+        self.flags.contains(EDepotFileFlag::Directory)
+    }
+
+    fn from_proto(file: &FileMapping) -> Self {
+        let mut chunks: Vec<ManifestChunk> = file.chunks.iter().map(ManifestChunk::from_proto).collect();
+        chunks.sort_by_key(|chunk| chunk.offset);
+
+        Self {
+            name: file.filename().to_string(),
+            size: file.size(),
+            flags: EDepotFileFlag::from_bits_truncate(file.flags() as i32),
+            name_hash: file.sha_filename().to_vec(),
+            content_hash: file.sha_content().to_vec(),
+            link_target: file.linktarget.clone().filter(|target| !target.is_empty()),
+            chunks,
+        }
+    }
+}
+
+/// Files of a depot at one of its builds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepotManifest {
+    /// Depot these files are from.
+    pub depot_id: u32,
+    /// Manifest id, as found on [Depot::manifests](crate::handlers::steam_apps::Depot::manifests).
+    pub manifest_id: u64,
+    /// When it was built, as an unix timestamp.
+    pub creation_time: u32,
+    /// Whether [ManifestFile::name]s are still encrypted with the depot key.
+    pub filenames_encrypted: bool,
+    /// Size of all files once installed, in bytes.
+    pub original_size: u64,
+    /// Size of all chunks as downloaded, in bytes.
+    pub compressed_size: u64,
+    /// Files, ordered by name once decrypted.
+    pub files: Vec<ManifestFile>,
+    payload: Vec<u8>,
+    signature: Option<Vec<u8>>,
+}
+
+impl DepotManifest {
+    /// Reads a manifest, either zipped as the CDN serves it or already unzipped.
+    pub fn from_bytes(manifest: &[u8]) -> Result<Self, ManifestError> {
+        let unzipped;
+        let mut remaining = if is_zip(manifest) {
+            unzipped = unzip(manifest).ok_or(ManifestError::Zip)?;
+            unzipped.as_slice()
+        } else {
+            manifest
+        };
+
+        let mut payload = None;
+        let mut metadata = None;
+        let mut signature = None;
+
+        while !remaining.is_empty() {
+            let magic = read_u32(&mut remaining)?;
+            match magic {
+                END_OF_MANIFEST_MAGIC => break,
+                LEGACY_MAGIC => return Err(ManifestError::Legacy),
+                _ => {}
+            }
+
+            let len = read_u32(&mut remaining)? as usize;
+            if remaining.len() < len {
+                return Err(ManifestError::Malformed);
+            }
+            let (section, rest) = remaining.split_at(len);
+            remaining = rest;
+
+            match magic {
+                PAYLOAD_MAGIC => payload = Some(section),
+                METADATA_MAGIC => metadata = Some(section),
+                SIGNATURE_MAGIC => signature = Some(section),
+                unknown => return Err(ManifestError::UnknownSection(unknown)),
+            }
+        }
+
+        let payload = payload.ok_or(ManifestError::MissingSection("payload"))?;
+        let metadata = metadata.ok_or(ManifestError::MissingSection("metadata"))?;
+
+        let files = ContentManifestPayload::parse_from_bytes(payload).map_err(|_| ManifestError::Malformed)?;
+        let metadata = ContentManifestMetadata::parse_from_bytes(metadata).map_err(|_| ManifestError::Malformed)?;
+        let signature = match signature {
+            Some(signature) => ContentManifestSignature::parse_from_bytes(signature)
+                .map_err(|_| ManifestError::Malformed)?
+                .signature
+                .map(|signature| signature.to_vec()),
+            None => None,
+        };
+
+        Ok(Self {
+            depot_id: metadata.depot_id(),
+            manifest_id: metadata.gid_manifest(),
+            creation_time: metadata.creation_time(),
+            filenames_encrypted: metadata.filenames_encrypted(),
+            original_size: metadata.cb_disk_original(),
+            compressed_size: metadata.cb_disk_compressed(),
+            files: files.mappings.iter().map(ManifestFile::from_proto).collect(),
+            payload: payload.to_vec(),
+            signature,
+        })
+    }
+
+    /// Decrypts the [ManifestFile::name]s and symlink targets with the depot key, then sorts the files by name.
+    ///
+    /// Does nothing if they are not encrypted to begin with.
+    pub fn decrypt_filenames(&mut self, depot_key: &[u8]) -> Result<(), ManifestError> {
+        if !self.filenames_encrypted {
+            return Ok(());
+        }
+
+        for file in &mut self.files {
+            file.name = decrypt_filename(&file.name, depot_key)?;
+            if let Some(link_target) = &file.link_target {
+                file.link_target = Some(decrypt_filename(link_target, depot_key)?);
+            }
+        }
+
+        self.files.sort_by(|a, b| a.name.cmp(&b.name));
+        self.filenames_encrypted = false;
+        Ok(())
+    }
+
+    /// Checks the signature of the files against `public_key`, in PEM.
+    ///
+    /// Returns `false` if the manifest has no signature at all.
+    pub fn verify_signature(&self, public_key: &[u8]) -> Result<bool, ManifestError> {
+        match &self.signature {
+            Some(signature) => steam_crypto::verify_signature_with(public_key, &self.payload, signature)
+                .map_err(|_| ManifestError::InvalidPublicKey),
+            None => Ok(false),
+        }
+    }
+
+    /// Which files were added, removed or changed from this manifest to `newer`.
+    ///
+    /// Files are matched by [ManifestFile::name_hash], so either manifest can still have its filenames encrypted.
+    pub fn diff<'a>(&'a self, newer: &'a DepotManifest) -> ManifestDiff<'a> {
+        let older_files: HashMap<&[u8], &ManifestFile> = self
+            .files
+            .iter()
+            .map(|file| (file.name_hash.as_slice(), file))
+            .collect();
+        let newer_files: HashMap<&[u8], &ManifestFile> = newer
+            .files
+            .iter()
+            .map(|file| (file.name_hash.as_slice(), file))
+            .collect();
+
+        let mut diff = ManifestDiff::default();
+        for file in &newer.files {
+            match older_files.get(file.name_hash.as_slice()) {
+                None => diff.added.push(file),
+                Some(older) if older.content_hash != file.content_hash || older.flags != file.flags => {
+                    diff.changed.push((older, file))
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = self
+            .files
+            .iter()
+            .filter(|file| !newer_files.contains_key(file.name_hash.as_slice()))
+            .collect();
+        diff
+    }
+}
+
+/// What changed between two manifests of a depot, from [DepotManifest::diff].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestDiff<'a> {
+    /// Files only in the newer manifest.
+    pub added: Vec<&'a ManifestFile>,
+    /// Files only in the older manifest.
+    pub removed: Vec<&'a ManifestFile>,
+    /// Files in both whose contents or flags changed, as the older one and the newer one.
+    pub changed: Vec<(&'a ManifestFile, &'a ManifestFile)>,
+}
+
+impl ManifestDiff<'_> {
+    /// Whether both manifests hold the same files.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+fn read_u32(input: &mut &[u8]) -> Result<u32, ManifestError> {
+    if input.len() < 4 {
+        return Err(ManifestError::Malformed);
+    }
+    let (value, rest) = input.split_at(4);
+    *input = rest;
+    Ok(u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+/// Filenames are encrypted just like messages, then base64 encoded, and padded with nul bytes before that.
+fn decrypt_filename(encrypted: &str, depot_key: &[u8]) -> Result<String, ManifestError> {
+    let encoded: String = encrypted.chars().filter(|c| !c.is_whitespace()).collect();
+    let encrypted_bytes = STANDARD
+        .decode(encoded)
+        .map_err(|_| ManifestError::Filename(encrypted.to_string()))?;
+    let decrypted = symmetric_decrypt(&encrypted_bytes, depot_key, false)
+        .map_err(|_| ManifestError::Filename(encrypted.to_string()))?;
+
+    let name_len = decrypted.iter().position(|&byte| byte == 0).unwrap_or(decrypted.len());
+    String::from_utf8(decrypted[..name_len].to_vec()).map_err(|_| ManifestError::Filename(encrypted.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use steam_crypto::symm::symmetric_encrypt;
+
+    use super::*;
+    use crate::content::tests::zip;
+    use crate::fake_cm;
+
+    const DEPOT_KEY: [u8; 32] = [7; 32];
+
+    /// Manifest of `files`, as if it was already read and its filenames decrypted.
+    pub(crate) fn depot_manifest(files: Vec<ManifestFile>) -> DepotManifest {
+        DepotManifest {
+            depot_id: 731,
+            manifest_id: 1,
+            creation_time: 0,
+            filenames_encrypted: false,
+            original_size: files.iter().map(|file| file.size).sum(),
+            compressed_size: 0,
+            files,
+            payload: Vec::new(),
+            signature: None,
+        }
+    }
+
+    fn file(name: &str, name_hash: u8, content_hash: u8) -> FileMapping {
+        let mut chunk = ChunkData::new();
+        chunk.set_sha(vec![content_hash; 20].into());
+        chunk.set_crc(0xDEAD_BEEF);
+        chunk.set_offset(0);
+        chunk.set_cb_original(5);
+        chunk.set_cb_compressed(48);
+
+        let mut file = FileMapping::new();
+        file.set_filename(name.to_string());
+        file.set_size(5);
+        file.set_flags(EDepotFileFlag::Executable.bits() as u32);
+        file.set_sha_filename(vec![name_hash; 20].into());
+        file.set_sha_content(vec![content_hash; 20].into());
+        file.chunks.push(chunk);
+        file
+    }
+
+    fn encrypt_filename(name: &str) -> String {
+        STANDARD.encode(symmetric_encrypt(name.as_bytes(), &DEPOT_KEY).unwrap())
+    }
+
+    fn section(manifest: &mut Vec<u8>, magic: u32, section: &[u8]) {
+        manifest.extend_from_slice(&magic.to_le_bytes());
+        manifest.extend_from_slice(&(section.len() as u32).to_le_bytes());
+        manifest.extend_from_slice(section);
+    }
+
+    /// A manifest as the CDN would serve it, signed by the fake CM.
+    fn manifest(files: Vec<FileMapping>, filenames_encrypted: bool) -> Vec<u8> {
+        let mut payload = ContentManifestPayload::new();
+        payload.mappings = files;
+        let payload = payload.write_to_bytes().unwrap();
+
+        let mut metadata = ContentManifestMetadata::new();
+        metadata.set_depot_id(731);
+        metadata.set_gid_manifest(7_617_088_375_292_372_759);
+        metadata.set_creation_time(1_700_000_000);
+        metadata.set_filenames_encrypted(filenames_encrypted);
+        metadata.set_cb_disk_original(5);
+        metadata.set_cb_disk_compressed(48);
+
+        let mut signature = ContentManifestSignature::new();
+        signature.set_signature(steam_crypto::sign_with(fake_cm::PRIVATE_KEY, &payload).unwrap().into());
+
+        let mut manifest = Vec::new();
+        section(&mut manifest, PAYLOAD_MAGIC, &payload);
+        section(&mut manifest, METADATA_MAGIC, &metadata.write_to_bytes().unwrap());
+        section(&mut manifest, SIGNATURE_MAGIC, &signature.write_to_bytes().unwrap());
+        manifest.extend_from_slice(&END_OF_MANIFEST_MAGIC.to_le_bytes());
+        zip(&manifest)
+    }
+
+    #[test]
+    fn reads_zipped_manifests() {
+        let manifest = DepotManifest::from_bytes(&manifest(vec![file("bin\\csgo.exe", 1, 2)], false)).unwrap();
+
+        assert_eq!(manifest.depot_id, 731);
+        assert_eq!(manifest.manifest_id, 7_617_088_375_292_372_759);
+        assert_eq!(manifest.creation_time, 1_700_000_000);
+        assert_eq!(manifest.original_size, 5);
+
+        let file = &manifest.files[0];
+        assert_eq!(file.name, "bin\\csgo.exe");
+        assert!(file.flags.contains(EDepotFileFlag::Executable));
+        assert!(!file.is_directory());
+        assert_eq!(file.chunks[0].checksum, 0xDEAD_BEEF);
+        assert_eq!(file.chunks[0].id_hex(), "02".repeat(20));
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let mut manifest = DepotManifest::from_bytes(&manifest(vec![file("csgo.exe", 1, 2)], false)).unwrap();
+        assert!(manifest.verify_signature(fake_cm::PUBLIC_KEY).unwrap());
+
+        manifest.payload[0] ^= 1;
+        assert!(!manifest.verify_signature(fake_cm::PUBLIC_KEY).unwrap());
+        assert!(matches!(
+            manifest.verify_signature(b"not a key"),
+            Err(ManifestError::InvalidPublicKey)
+        ));
+    }
+
+    #[test]
+    fn decrypts_filenames() {
+        let files = vec![
+            file(&encrypt_filename("zzz.txt\0\0"), 1, 1),
+            file(&encrypt_filename("bin\\csgo.exe"), 2, 2),
+        ];
+        let mut manifest = DepotManifest::from_bytes(&manifest(files, true)).unwrap();
+        assert!(manifest.filenames_encrypted);
+
+        assert!(matches!(
+            manifest.clone().decrypt_filenames(&[8; 32]),
+            Err(ManifestError::Filename(_))
+        ));
+
+        manifest.decrypt_filenames(&DEPOT_KEY).unwrap();
+        assert!(!manifest.filenames_encrypted);
+        let names: Vec<&str> = manifest.files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, ["bin\\csgo.exe", "zzz.txt"]);
+    }
+
+    #[test]
+    fn diffs_manifests() {
+        let older = manifest(
+            vec![file("kept", 1, 1), file("changed", 2, 2), file("removed", 3, 3)],
+            false,
+        );
+        let newer = manifest(
+            vec![file("kept", 1, 1), file("changed", 2, 9), file("added", 4, 4)],
+            false,
+        );
+        let older = DepotManifest::from_bytes(&older).unwrap();
+        let newer = DepotManifest::from_bytes(&newer).unwrap();
+
+        let diff = older.diff(&newer);
+        assert_eq!(diff.added.iter().map(|file| &file.name).collect::<Vec<_>>(), ["added"]);
+        assert_eq!(
+            diff.removed.iter().map(|file| &file.name).collect::<Vec<_>>(),
+            ["removed"]
+        );
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].1.content_hash, vec![9; 20]);
+        assert!(older.diff(&older).is_empty());
+    }
+
+    #[test]
+    fn malformed_manifests() {
+        assert!(matches!(
+            DepotManifest::from_bytes(b"PK\x03\x04"),
+            Err(ManifestError::Zip)
+        ));
+        assert!(matches!(
+            DepotManifest::from_bytes(&LEGACY_MAGIC.to_le_bytes()),
+            Err(ManifestError::Legacy)
+        ));
+        assert!(matches!(
+            DepotManifest::from_bytes(&[0xD0, 0x17, 0xF6, 0x71, 0xFF, 0, 0, 0]),
+            Err(ManifestError::Malformed)
+        ));
+        assert!(matches!(
+            DepotManifest::from_bytes(&END_OF_MANIFEST_MAGIC.to_le_bytes()),
+            Err(ManifestError::MissingSection("payload"))
+        ));
+    }
+}
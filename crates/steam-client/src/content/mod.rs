@@ -0,0 +1,100 @@
+//! Game content, as Steam serves it from its CDN: depots, the manifests listing their files, and the chunks those
+//! files are made of.
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/tree/master/SteamKit2/SteamKit2/Steam/CDN
+
+use std::io::Read;
+
+use flate2::read::DeflateDecoder;
+
+pub mod cdn;
+mod chunk;
+pub mod download;
+pub mod manifest;
+
+const ZIP_LOCAL_FILE_MAGIC: &[u8] = b"PK\x03\x04";
+const ZIP_LOCAL_FILE_HEADER_SIZE: usize = 30;
+const ZIP_METHOD_STORED: u16 = 0;
+const ZIP_METHOD_DEFLATED: u16 = 8;
+
+/// Whether `data` looks like a zip archive.
+pub(crate) fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(ZIP_LOCAL_FILE_MAGIC)
+}
+
+/// Extracts the first file of a zip archive, which is all Steam ever puts in its archives.
+///
+/// Returns `None` if `data` is not a zip, or its first file is neither stored nor deflated.
+pub(crate) fn unzip(data: &[u8]) -> Option<Vec<u8>> {
+    if !is_zip(data) || data.len() < ZIP_LOCAL_FILE_HEADER_SIZE {
+        return None;
+    }
+
+    let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+
+    let method = u16_at(8);
+    let uncompressed_size = u32_at(22) as usize;
+    let contents_start = ZIP_LOCAL_FILE_HEADER_SIZE + u16_at(26) as usize + u16_at(28) as usize;
+    let contents = data.get(contents_start..)?;
+
+    match method {
+        ZIP_METHOD_STORED => contents.get(..uncompressed_size).map(<[u8]>::to_vec),
+        ZIP_METHOD_DEFLATED => {
+            // deflate streams know where they end, so the sizes on the header do not matter, and are not trusted to
+            // preallocate either
+            let mut file = Vec::new();
+            DeflateDecoder::new(contents).read_to_end(&mut file).ok()?;
+            Some(file)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    /// Zips `file` the way Steam does, as a single deflated file.
+    pub(crate) fn zip(file: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(file).unwrap();
+        let deflated = encoder.finish().unwrap();
+
+        let mut archive = ZIP_LOCAL_FILE_MAGIC.to_vec();
+        archive.extend_from_slice(&20u16.to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive.extend_from_slice(&ZIP_METHOD_DEFLATED.to_le_bytes());
+        archive.extend_from_slice(&[0; 8]);
+        archive.extend_from_slice(&(deflated.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&(file.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&1u16.to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive.push(b'z');
+        archive.extend_from_slice(&deflated);
+        archive
+    }
+
+    #[test]
+    fn unzips_deflated_and_stored_files() {
+        assert_eq!(unzip(&zip(b"manifest")), Some(b"manifest".to_vec()));
+
+        let mut stored = zip(b"");
+        stored[8] = ZIP_METHOD_STORED as u8;
+        stored.truncate(ZIP_LOCAL_FILE_HEADER_SIZE + 1);
+        stored[22..26].copy_from_slice(&3u32.to_le_bytes());
+        stored.extend_from_slice(b"abc");
+        assert_eq!(unzip(&stored), Some(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn not_a_zip() {
+        assert_eq!(unzip(b"VZa"), None);
+        assert_eq!(unzip(b"PK\x03\x04"), None);
+    }
+}
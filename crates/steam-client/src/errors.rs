@@ -1,5 +1,9 @@
 use std::io;
 
+use steam_crypto::symm::SymmetricError;
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::enums::EResult;
+use tappet::errors::SteamAPIError;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -7,6 +11,169 @@ pub enum ConnectionError {
     #[error("Connection with Steam CM server was dropped.")]
     Dropped,
 
+    #[error("Steam refused to encrypt the channel: {0:?}.")]
+    EncryptionFailed(EResult),
+
+    #[error("Received {0:?} out of order during channel encryption.")]
+    UnexpectedHandshakeMessage(EMsg),
+
+    #[error("Could not encrypt the session key with the CM public key: {0}")]
+    InvalidPublicKey(String),
+
+    #[error("Steam did not answer the last {0} heartbeats.")]
+    HeartbeatTimeout(u32),
+
+    #[error("Job {0} timed out waiting for a reply.")]
+    JobTimeout(u64),
+
+    #[error(transparent)]
+    PacketError(#[from] PacketError),
+
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum LogonError {
+    #[error("Steam refused the logon: {0:?}.")]
+    Denied(EResult),
+
+    #[error(transparent)]
+    ConnectionError(#[from] ConnectionError),
+}
+
+#[derive(Debug, Error)]
+pub enum FriendsError {
+    #[error("Steam refused to change the relationship: {0:?}.")]
+    Refused(EResult),
+
+    #[error(transparent)]
+    ConnectionError(#[from] ConnectionError),
+}
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum ClientError {
+    #[error("Client is already running.")]
+    AlreadyRunning,
+
+    #[error("Steam refused the logon: {0:?}.")]
+    LogonDenied(EResult),
+
+    #[error("Could not stay connected to Steam after {0} attempts in a row.")]
+    ReconnectsExhausted(u32),
+}
+
+#[derive(Debug, Error)]
+pub enum ServerListError {
+    #[error("Steam Directory refused to list CM servers: {0}")]
+    Directory(String),
+
+    #[error("No CM server could be found.")]
+    NoServers,
+
+    #[error(transparent)]
+    WebApi(#[from] SteamAPIError),
+
+    #[error("Could not read or write the CM server cache: {0}")]
+    Cache(#[from] io::Error),
+
+    #[error("CM server cache is corrupted: {0}")]
+    CorruptedCache(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ServiceMethodError {
+    #[error("Service method {method} failed with {result:?}: {message}")]
+    Failed {
+        method: String,
+        result: EResult,
+        message: String,
+    },
+
+    #[error("Could not decode the response to service method {0}.")]
+    MalformedResponse(String),
+
+    #[error(transparent)]
+    ConnectionError(#[from] ConnectionError),
+}
+
+#[derive(Debug, Error)]
+pub enum AuthenticationError {
+    #[error("Could not encrypt the password with the key Steam sent.")]
+    Encryption,
+
+    #[error(transparent)]
+    ServiceMethod(#[from] ServiceMethodError),
+}
+
+#[derive(Debug, Error)]
+pub enum AppsError {
+    #[error("Could not read the product info of {0}: {1}")]
+    KeyValues(u32, steam_keyvalues::Error),
+
+    #[error("Steam sent no product info for {0}, nor where to download it from")]
+    MissingKeyValues(u32),
+
+    #[error(transparent)]
+    ConnectionError(#[from] ConnectionError),
+}
+
+#[derive(Debug, Error)]
+pub enum GameCoordinatorError {
+    #[error("Could not encode GC message {0}: {1}")]
+    Encode(u32, String),
+
+    #[error(transparent)]
+    ConnectionError(#[from] ConnectionError),
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Manifest is not a zip with a single stored or deflated file.")]
+    Zip,
+
+    #[error("Manifest is truncated or one of its sections is malformed.")]
+    Malformed,
+
+    #[error("Manifest is in the binary format from before protobufs, which is not supported.")]
+    Legacy,
+
+    #[error("Unknown manifest section {0:#010x}.")]
+    UnknownSection(u32),
+
+    #[error("Manifest has no {0} section.")]
+    MissingSection(&'static str),
+
+    #[error("Could not decrypt filename {0} with the depot key.")]
+    Filename(String),
+
+    #[error("Public key is not an RSA key in PEM.")]
+    InvalidPublicKey,
+}
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("CDN could not serve chunk {0}: {1}")]
+    Cdn(String, String),
+
+    #[error("Could not decrypt chunk {0} with the depot key.")]
+    Decrypt(String),
+
+    #[error("Chunk {0} is compressed in a way we do not know.")]
+    UnknownCompression(String),
+
+    #[error("Could not decompress chunk {0}.")]
+    Decompress(String),
+
+    #[error("Chunk {0} does not match its checksums.")]
+    Checksum(String),
+
+    #[error("File {0} would be written outside of the target directory.")]
+    UnsafePath(String),
+
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+
     #[error(transparent)]
     IoError(#[from] io::Error),
 }
@@ -16,6 +183,30 @@ pub enum PacketError {
     #[error("Received a malformed packet from the socket.")]
     Malformed,
 
+    #[error("Frame does not start with the VT01 magic bytes.")]
+    InvalidMagic,
+
+    #[error("Frame of {0} bytes is bigger than any Steam message.")]
+    FrameTooLarge(usize),
+
+    #[error("Unknown EMsg {0}.")]
+    UnknownEMsg(u32),
+
+    #[error("Could not encrypt or decrypt a packet with the session key: {0}")]
+    Crypto(#[from] SymmetricError),
+
     #[error(transparent)]
     IoError(#[from] io::Error),
+
+    #[cfg(feature = "websockets")]
+    #[error("WebSocket error: {0}")]
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+}
+
+#[cfg(feature = "websockets")]
+impl From<tokio_tungstenite::tungstenite::Error> for PacketError {
+    // boxed, since it is much bigger than every other variant
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        PacketError::WebSocket(Box::new(err))
+    }
 }
@@ -0,0 +1,264 @@
+//! Typed events emitted by the client and its handlers.
+//!
+//! Every event type gets its own channel on the [EventBus], created the first time someone publishes or subscribes
+//! to it. Subscribers only see events published after they subscribed, and a subscriber that falls too far behind
+//! skips the oldest events instead of slowing everyone else down.
+//!
+//! Raw [PacketMessage]s are events as well, which is how user defined handlers for any [EMsg] are built.
+
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use steam_language_gen::generated::enums::EMsg;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+use crate::connection::PacketRx;
+use crate::errors::ConnectionError;
+use crate::messages::packet::PacketMessage;
+
+/// How many events of a single type a slow subscriber may lag behind before it starts missing them.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Marker for types that can be published on the [EventBus].
+pub trait Event: Clone + Send + Sync + 'static {}
+
+/// Stream of events of a single type.
+pub type EventStream<E> = BoxStream<'static, E>;
+
+/// The connection to Steam was closed.
+#[derive(Debug, Clone)]
+pub struct Disconnected {
+    /// What closed the connection, or `None` if we closed it ourselves.
+    pub error: Option<String>,
+}
+
+impl Event for Disconnected {}
+impl Event for PacketMessage {}
+
+/// Publish and subscribe hub for every [Event].
+#[derive(Clone, Default)]
+pub struct EventBus {
+    channels: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channels = self.channels.lock().unwrap().len();
+        f.debug_struct("EventBus").field("channels", &channels).finish()
+    }
+}
+
+impl EventBus {
+    /// Creates an empty bus.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sender<E: Event>(&self) -> broadcast::Sender<E> {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(broadcast::channel::<E>(EVENT_CHANNEL_CAPACITY).0))
+            .downcast_ref::<broadcast::Sender<E>>()
+            .expect("channels are keyed by their event type")
+            .clone()
+    }
+
+    /// Delivers an event to everyone subscribed to its type.
+    pub fn publish<E: Event>(&self, event: E) {
+        // there may be no subscribers, which is fine
+        let _ = self.sender::<E>().send(event);
+    }
+
+    pub(crate) fn receiver<E: Event>(&self) -> broadcast::Receiver<E> {
+        self.sender::<E>().subscribe()
+    }
+
+    /// Subscribes to every event of type `E` from this point on.
+    pub fn subscribe<E: Event>(&self) -> EventStream<E> {
+        futures::stream::unfold(self.receiver::<E>(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Subscriber lagged behind and missed {} events.", skipped)
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Runs `handler` for every event of type `E` from this point on, one at a time.
+    ///
+    /// The handler keeps running until the returned task is aborted.
+    pub fn on<E, F, Fut>(&self, handler: F) -> JoinHandle<()>
+    where
+        E: Event,
+        F: Fn(E) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut events = self.subscribe::<E>();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                handler(event).await;
+            }
+        })
+    }
+
+    /// Runs `handler` for every message with the given [EMsg] from this point on, one at a time.
+    ///
+    /// This is how handlers for messages the client knows nothing about are written.
+    pub fn on_message<F, Fut>(&self, emsg: EMsg, handler: F) -> JoinHandle<()>
+    where
+        F: Fn(PacketMessage) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut messages = self
+            .subscribe::<PacketMessage>()
+            .filter(move |message| futures::future::ready(message.emsg() == emsg));
+
+        tokio::spawn(async move {
+            while let Some(message) = messages.next().await {
+                handler(message).await;
+            }
+        })
+    }
+}
+
+/// Publishes every message from a connection on the bus, and then a [Disconnected] once its task ends.
+///
+/// Takes the connection already subscribed, so nothing arriving before this is first polled gets lost.
+pub(crate) async fn forward_connection(
+    bus: EventBus,
+    mut packets: PacketRx,
+    mut task: JoinHandle<Result<(), ConnectionError>>,
+) -> Result<(), ConnectionError> {
+    let result = loop {
+        tokio::select! {
+            packet_message = packets.recv() => match packet_message {
+                Ok(packet_message) => bus.publish(packet_message),
+                Err(RecvError::Lagged(skipped)) => warn!("Event bus lagged behind and missed {} messages.", skipped),
+                Err(RecvError::Closed) => break (&mut task).await,
+            },
+            result = &mut task => break result,
+        }
+    };
+
+    // whatever was read right before the end still goes out before the disconnection
+    while let Ok(packet_message) = packets.try_recv() {
+        bus.publish(packet_message);
+    }
+
+    let result = result.unwrap_or(Err(ConnectionError::Dropped));
+    bus.publish(Disconnected {
+        error: result.as_ref().err().map(ToString::to_string),
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use steam_language_gen::MessageHeaderWrapper;
+    use steam_protobuf::protobufs::steammessages_base::CMsgProtoBufHeader;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::connection::ConnectionHandle;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Ping(u32);
+    impl Event for Ping {}
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Pong(u32);
+    impl Event for Pong {}
+
+    fn packet(emsg: EMsg) -> PacketMessage {
+        PacketMessage::new(emsg, MessageHeaderWrapper::Proto(CMsgProtoBufHeader::new()), vec![])
+    }
+
+    #[tokio::test]
+    async fn subscribers_only_get_their_event_type() {
+        let bus = EventBus::new();
+        let mut pings = bus.subscribe::<Ping>();
+        let mut pongs = bus.subscribe::<Pong>();
+
+        bus.publish(Pong(1));
+        bus.publish(Ping(2));
+
+        assert_eq!(pings.next().await, Some(Ping(2)));
+        assert_eq!(pongs.next().await, Some(Pong(1)));
+    }
+
+    #[tokio::test]
+    async fn async_handlers_run_for_every_event() {
+        let bus = EventBus::new();
+        let (sender, mut handled) = mpsc::unbounded_channel();
+
+        let _handler = bus.on(move |Ping(value)| {
+            let sender = sender.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                sender.send(value).unwrap();
+            }
+        });
+
+        bus.publish(Ping(1));
+        bus.publish(Ping(2));
+
+        assert_eq!(handled.recv().await, Some(1));
+        assert_eq!(handled.recv().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn message_handlers_are_keyed_by_emsg() {
+        let bus = EventBus::new();
+        let (sender, mut handled) = mpsc::unbounded_channel();
+
+        let _handler = bus.on_message(EMsg::ClientFriendsList, move |message| {
+            let sender = sender.clone();
+            async move { sender.send(message.emsg()).unwrap() }
+        });
+
+        bus.publish(packet(EMsg::ClientPersonaState));
+        bus.publish(packet(EMsg::ClientFriendsList));
+
+        assert_eq!(handled.recv().await, Some(EMsg::ClientFriendsList));
+        assert!(handled.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn connection_is_forwarded_until_disconnected() {
+        let bus = EventBus::new();
+        let mut messages = bus.subscribe::<PacketMessage>();
+        let mut disconnected = bus.subscribe::<Disconnected>();
+
+        let (connection, _sent) = ConnectionHandle::detached();
+        let (finish, finished) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let _ = finished.await;
+            Err(ConnectionError::Dropped)
+        });
+        let forwarder = tokio::spawn(forward_connection(bus.clone(), connection.subscribe(), task));
+
+        connection.inject(packet(EMsg::ClientFriendsList));
+        assert_eq!(messages.next().await.unwrap().emsg(), EMsg::ClientFriendsList);
+
+        finish.send(()).unwrap();
+        assert!(matches!(forwarder.await.unwrap(), Err(ConnectionError::Dropped)));
+        assert!(disconnected.next().await.unwrap().error.is_some());
+    }
+}
@@ -0,0 +1,224 @@
+//! A scripted CM server that never leaves the process, to test clients without Steam.
+//!
+//! [FakeCm] listens on a [MemoryTransport] and does what a CM does when a client connects: it asks to encrypt the
+//! channel and performs the RSA/AES handshake, using a test keypair instead of Steam's. Each [FakeCmConnection] is
+//! then driven message by message, or left to accept the logon and replay canned messages on its own.
+//!
+//! Being test support, the fake CM panics as soon as a client does something it did not expect, failing the test.
+//!
+//! Built with the `fake-cm` feature.
+
+use futures::SinkExt;
+use futures::StreamExt;
+use steam_crypto::crc_hash;
+use steam_crypto::decrypt_session_key;
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::enums::EResult;
+use steam_language_gen::generated::messages::MsgChannelEncryptRequest;
+use steam_language_gen::generated::messages::MsgChannelEncryptResponse;
+use steam_language_gen::generated::messages::MsgChannelEncryptResult;
+use steam_language_gen::MessageHeaderWrapper;
+use steam_language_gen::SerializableBytes;
+use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogonResponse;
+use tokio::task::JoinHandle;
+
+use crate::messages::message::ClientMessage;
+use crate::messages::packet::PacketMessage;
+use crate::messages::MessageKind;
+use crate::transport::BoxMessageStream;
+use crate::transport::MemoryListener;
+use crate::transport::MemoryTransport;
+
+/// Private half of the test keypair, in PEM.
+pub(crate) const PRIVATE_KEY: &[u8] = include_bytes!("../assets/fake_cm_private.pem");
+
+/// Public half of the test keypair, in PEM. Clients must encrypt the session key with it.
+pub const PUBLIC_KEY: &[u8] = include_bytes!("../assets/fake_cm_public.pem");
+
+/// SteamID every client is logged on as.
+pub const STEAM_ID: u64 = 76561197960287930;
+
+/// Session id every client is given on logon.
+pub const SESSION_ID: i32 = 1234567;
+
+/// Cell id every client is assigned on logon.
+pub const CELL_ID: u32 = 4;
+
+/// Heartbeat interval every client is asked for on logon.
+pub const HEARTBEAT_SECONDS: i32 = 9;
+
+/// Challenge the client must send back along with the session key.
+const CHALLENGE: [u8; 16] = [66, 126, 251, 245, 88, 122, 243, 123, 102, 163, 11, 54, 151, 145, 31, 54];
+
+/// A scripted CM, reached through the [MemoryTransport] it is created with.
+#[derive(Debug)]
+pub struct FakeCm {
+    listener: MemoryListener,
+    canned: Vec<Vec<u8>>,
+}
+
+impl FakeCm {
+    /// Creates a fake CM, along with the transport clients reach it through.
+    pub fn new() -> (MemoryTransport, Self) {
+        let (transport, listener) = MemoryTransport::new();
+        let fake_cm = Self {
+            listener,
+            canned: vec![],
+        };
+        (transport.with_public_key(PUBLIC_KEY), fake_cm)
+    }
+
+    /// Replays `message` to every client right after it logs on, after the ones queued before it.
+    pub fn replay<M: SerializableBytes>(mut self, message: M) -> Self {
+        self.canned.push(message.to_bytes());
+        self
+    }
+
+    /// Waits for the next client and encrypts the channel with it.
+    ///
+    /// Returns `None` once every transport of this fake CM is gone.
+    pub async fn accept(&mut self) -> Option<FakeCmConnection> {
+        let (endpoint, stream) = self.listener.accept().await?;
+        let mut connection = FakeCmConnection {
+            endpoint,
+            stream,
+            canned: self.canned.clone(),
+        };
+
+        connection.encrypt().await;
+        Some(connection)
+    }
+
+    /// Serves every client in the background, logging them on and replaying canned messages to them. Anything they
+    /// send afterwards is ignored, until they hang up.
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(mut connection) = self.accept().await {
+                tokio::spawn(async move {
+                    connection.accept_logon().await;
+                    while connection.recv().await.is_some() {}
+                });
+            }
+        })
+    }
+}
+
+/// A client connected to a [FakeCm], with the channel already encrypted.
+#[derive(Debug)]
+pub struct FakeCmConnection {
+    endpoint: String,
+    stream: BoxMessageStream,
+    canned: Vec<Vec<u8>>,
+}
+
+impl FakeCmConnection {
+    /// Endpoint the client meant to connect to.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Next message from the client, leaving heartbeats out.
+    ///
+    /// Returns `None` once the client hangs up.
+    pub async fn recv(&mut self) -> Option<PacketMessage> {
+        loop {
+            match self.stream.next().await? {
+                Ok(packet_message) if packet_message.emsg() == EMsg::ClientHeartBeat => continue,
+                Ok(packet_message) => return Some(packet_message),
+                Err(err) => panic!("Fake CM could not read from the client: {}", err),
+            }
+        }
+    }
+
+    /// Next message from the client, that must be an `emsg`.
+    pub async fn expect(&mut self, emsg: EMsg) -> PacketMessage {
+        let packet_message = self
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("Client hung up while the fake CM waited for {:?}.", emsg));
+        assert_eq!(packet_message.emsg(), emsg, "Fake CM got an unexpected message.");
+        packet_message
+    }
+
+    /// Sends `message` to the client.
+    pub async fn send<M: SerializableBytes>(&mut self, message: M) {
+        self.send_bytes(message.to_bytes()).await
+    }
+
+    /// Sends `message` to the client, as the reply to the job `request` started.
+    pub async fn reply<M>(&mut self, request: &PacketMessage, message: ClientMessage<M>)
+    where
+        ClientMessage<M>: SerializableBytes,
+    {
+        let (job_id, _) = request.jobs_ids();
+        self.send(message.set_target(job_id)).await
+    }
+
+    /// Waits for the client to log on and lets it in, then replays the canned messages.
+    ///
+    /// Returns the logon message.
+    pub async fn accept_logon(&mut self) -> PacketMessage {
+        let logon = self.expect(EMsg::ClientLogon).await;
+        self.answer_logon(EResult::OK).await;
+
+        for message in std::mem::take(&mut self.canned) {
+            self.send_bytes(message).await;
+        }
+        logon
+    }
+
+    /// Waits for the client to log on and refuses it with `eresult`.
+    ///
+    /// Returns the logon message.
+    pub async fn deny_logon(&mut self, eresult: EResult) -> PacketMessage {
+        let logon = self.expect(EMsg::ClientLogon).await;
+        self.answer_logon(eresult).await;
+        logon
+    }
+
+    async fn answer_logon(&mut self, eresult: EResult) {
+        let mut response = ClientMessage::<CMsgClientLogonResponse>::new_proto(EMsg::ClientLogOnResponse);
+        if let MessageHeaderWrapper::Proto(header) = &mut response.wrapped_header {
+            header.set_steamid(STEAM_ID);
+            header.set_client_sessionid(SESSION_ID);
+        }
+        response.body.set_eresult(eresult as i32);
+        response.body.set_heartbeat_seconds(HEARTBEAT_SECONDS);
+        response.body.set_cell_id(CELL_ID);
+        self.send(response).await;
+    }
+
+    async fn send_bytes(&mut self, message: Vec<u8>) {
+        if let Err(err) = self.stream.send(message).await {
+            panic!("Fake CM could not write to the client: {}", err);
+        }
+    }
+
+    /// Plays the CM side of the channel encryption handshake.
+    async fn encrypt(&mut self) {
+        let request = ClientMessage::<MsgChannelEncryptRequest>::new().set_payload(&CHALLENGE);
+        self.send(request).await;
+
+        let response = self.expect(EMsg::ChannelEncryptResponse).await;
+        let response = ClientMessage::<MsgChannelEncryptResponse>::from_packet_message(response);
+        let payload = response.payload();
+        // the encrypted key is as long as the RSA key, and followed by its CRC and four unused bytes
+        assert!(payload.len() > 8, "ChannelEncryptResponse is too short.");
+
+        let (encrypted_key, key_hash) = payload.split_at(payload.len() - 8);
+        assert_eq!(
+            &key_hash[..4],
+            crc_hash(encrypted_key).as_slice(),
+            "Session key does not match its CRC."
+        );
+        let plain_key = decrypt_session_key(PRIVATE_KEY, encrypted_key)
+            .expect("Session key was not encrypted with the fake CM public key.");
+        let (session_key, challenge) = plain_key.split_at(32);
+        assert_eq!(challenge, CHALLENGE, "Client did not send the challenge back.");
+
+        let mut result = ClientMessage::<MsgChannelEncryptResult>::new();
+        result.body.result = EResult::OK;
+        self.send(result).await;
+        self.stream.enable_encryption(session_key);
+    }
+}
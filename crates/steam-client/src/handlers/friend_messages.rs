@@ -0,0 +1,312 @@
+//! One to one chat with friends, through the `FriendMessages` service.
+//!
+//! Messages are sent with service method calls, and incoming ones arrive as `FriendMessagesClient` notifications.
+//! Typing notifications travel the same way, as messages of the `Typing` chat entry type with no text.
+//!
+//! Reference: https://github.com/DoctorMcKay/node-steam-user/blob/master/components/chat.js
+
+use num::FromPrimitive;
+use steam_language_gen::generated::enums::EChatEntryType;
+use steam_protobuf::protobufs::steammessages_friendmessages_steamclient::CFriendMessages_GetRecentMessages_Request;
+use steam_protobuf::protobufs::steammessages_friendmessages_steamclient::CFriendMessages_GetRecentMessages_Response;
+use steam_protobuf::protobufs::steammessages_friendmessages_steamclient::CFriendMessages_IncomingMessage_Notification;
+use steam_protobuf::protobufs::steammessages_friendmessages_steamclient::CFriendMessages_SendMessage_Request;
+use steam_protobuf::protobufs::steammessages_friendmessages_steamclient::CFriendMessages_SendMessage_Response;
+use steamid_parser::SteamID;
+
+use crate::connection::ConnectionHandle;
+use crate::errors::ServiceMethodError;
+use crate::events::Event;
+use crate::events::EventBus;
+use crate::handlers::forward_packets;
+use crate::handlers::steam_unified_messages::notification;
+use crate::handlers::steam_unified_messages::SteamUnifiedMessages;
+use crate::messages::packet::PacketMessage;
+
+const SEND_MESSAGE: &str = "FriendMessages.SendMessage#1";
+const GET_RECENT_MESSAGES: &str = "FriendMessages.GetRecentMessages#1";
+const INCOMING_MESSAGE: &str = "FriendMessagesClient.IncomingMessage#1";
+
+/// A friend sent us a message.
+///
+/// Messages we sent from other sessions of the same account are echoed here as well.
+#[derive(Debug, Clone)]
+pub struct FriendMessage {
+    /// Friend on the other side of the conversation.
+    pub friend: SteamID,
+    /// Text of the message, in BBCode.
+    pub message: String,
+    /// When Steam got the message, as an unix timestamp.
+    pub timestamp: u32,
+    /// Tells apart messages sent within the same second.
+    pub ordinal: u32,
+    /// Whether we sent the message ourselves, from another session.
+    pub echo: bool,
+}
+
+/// A friend is typing a message to us.
+#[derive(Debug, Clone)]
+pub struct FriendTyping {
+    /// Friend who is typing.
+    pub friend: SteamID,
+}
+
+impl Event for FriendMessage {}
+impl Event for FriendTyping {}
+
+/// Message Steam accepted from us.
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    /// The message as Steam stored it, which may differ from what was sent, such as with links made into BBCode.
+    pub message: String,
+    /// When Steam got the message, as an unix timestamp.
+    pub timestamp: u32,
+    /// Tells apart messages sent within the same second.
+    pub ordinal: u32,
+}
+
+/// Message from the history of a conversation.
+#[derive(Debug, Clone)]
+pub struct HistoryMessage {
+    /// Who sent it, either us or the friend.
+    pub sender: SteamID,
+    /// Text of the message, in BBCode.
+    pub message: String,
+    /// When Steam got the message, as an unix timestamp.
+    pub timestamp: u32,
+    /// Tells apart messages sent within the same second.
+    pub ordinal: u32,
+}
+
+/// Chats with friends.
+///
+/// Publishes [FriendMessage] and [FriendTyping] on the [EventBus].
+#[derive(Debug, Clone)]
+pub struct FriendMessages {
+    connection: ConnectionHandle,
+    unified_messages: SteamUnifiedMessages,
+}
+
+impl FriendMessages {
+    pub(crate) fn new(connection: ConnectionHandle, events: EventBus) -> Self {
+        tokio::spawn(forward_packets(
+            "FriendMessages",
+            connection.clone(),
+            connection.subscribe(),
+            move |packet_message| handle_msg(&events, packet_message),
+        ));
+        Self {
+            unified_messages: SteamUnifiedMessages::new(connection.clone()),
+            connection,
+        }
+    }
+
+    /// Sends `message`, in BBCode, to `friend`.
+    pub async fn send_message(&self, friend: &SteamID, message: &str) -> Result<SentMessage, ServiceMethodError> {
+        let response = self.send(friend, EChatEntryType::ChatMsg, message).await?;
+        Ok(SentMessage {
+            message: response.modified_message().to_string(),
+            timestamp: response.server_timestamp(),
+            ordinal: response.ordinal(),
+        })
+    }
+
+    /// Lets `friend` know we are typing a message to them.
+    pub async fn send_typing(&self, friend: &SteamID) -> Result<(), ServiceMethodError> {
+        self.send(friend, EChatEntryType::Typing, "").await.map(|_| ())
+    }
+
+    async fn send(
+        &self,
+        friend: &SteamID,
+        entry_type: EChatEntryType,
+        message: &str,
+    ) -> Result<CFriendMessages_SendMessage_Response, ServiceMethodError> {
+        let mut request = CFriendMessages_SendMessage_Request::new();
+        request.set_steamid(friend.to_steam64());
+        request.set_chat_entry_type(entry_type as i32);
+        request.set_message(message.to_string());
+        request.set_contains_bbcode(true);
+        // so our other sessions see it as well
+        request.set_echo_to_sender(true);
+
+        self.unified_messages.call_service_method(SEND_MESSAGE, request).await
+    }
+
+    /// Up to `count` of the last messages exchanged with `friend`, newest first.
+    pub async fn recent_messages(
+        &self,
+        friend: &SteamID,
+        count: u32,
+    ) -> Result<Vec<HistoryMessage>, ServiceMethodError> {
+        let mut request = CFriendMessages_GetRecentMessages_Request::new();
+        request.set_steamid1(self.connection.session().steam_id());
+        request.set_steamid2(friend.to_steam64());
+        request.set_count(count);
+        request.set_most_recent_conversation(false);
+        request.set_bbcode_format(true);
+
+        let response: CFriendMessages_GetRecentMessages_Response = self
+            .unified_messages
+            .call_service_method(GET_RECENT_MESSAGES, request)
+            .await?;
+
+        let messages = response
+            .messages
+            .iter()
+            .map(|message| HistoryMessage {
+                sender: SteamID::from_steam3(message.accountid(), None, None),
+                message: message.message().to_string(),
+                timestamp: message.timestamp(),
+                ordinal: message.ordinal(),
+            })
+            .collect();
+        Ok(messages)
+    }
+}
+
+fn handle_msg(events: &EventBus, packet_message: PacketMessage) {
+    let incoming: CFriendMessages_IncomingMessage_Notification = match notification(&packet_message, INCOMING_MESSAGE) {
+        Some(incoming) => incoming,
+        None => return,
+    };
+    let friend = SteamID::from_steam64(incoming.steamid_friend());
+
+    match EChatEntryType::from_i32(incoming.chat_entry_type()) {
+        Some(EChatEntryType::ChatMsg) => events.publish(FriendMessage {
+            friend,
+            message: incoming.message().to_string(),
+            timestamp: incoming.rtime32_server_timestamp(),
+            ordinal: incoming.ordinal(),
+            echo: incoming.local_echo(),
+        }),
+        Some(EChatEntryType::Typing) => events.publish(FriendTyping { friend }),
+        entry_type => trace!("FriendMessages ignored a {:?} entry.", entry_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use steam_protobuf::protobufs::steammessages_friendmessages_steamclient::cfriend_messages_get_recent_messages_response::FriendMessage as RecentMessage;
+
+    use super::*;
+    use crate::connection::testing::notification_packet;
+    use crate::connection::testing::reply;
+    use crate::connection::testing::sent_call;
+
+    const FRIEND: u64 = 76561197960287930;
+
+    fn incoming(entry_type: EChatEntryType, text: &str) -> PacketMessage {
+        let mut incoming = CFriendMessages_IncomingMessage_Notification::new();
+        incoming.set_steamid_friend(FRIEND);
+        incoming.set_chat_entry_type(entry_type as i32);
+        incoming.set_message(text.to_string());
+        incoming.set_rtime32_server_timestamp(1600000000);
+        incoming.set_ordinal(1);
+
+        notification_packet(INCOMING_MESSAGE, incoming)
+    }
+
+    #[tokio::test]
+    async fn incoming_messages_are_events() {
+        let (connection, _sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let _friend_messages = FriendMessages::new(connection.clone(), events.clone());
+        let mut messages = events.subscribe::<FriendMessage>();
+        let mut typing = events.subscribe::<FriendTyping>();
+
+        connection.inject(incoming(EChatEntryType::Typing, ""));
+        connection.inject(incoming(EChatEntryType::ChatMsg, "hello"));
+
+        assert_eq!(typing.next().await.unwrap().friend.to_steam64(), FRIEND);
+        let message = messages.next().await.unwrap();
+        assert_eq!(message.friend.to_steam64(), FRIEND);
+        assert_eq!(message.message, "hello");
+        assert_eq!(message.timestamp, 1600000000);
+        assert!(!message.echo);
+    }
+
+    #[test]
+    fn other_notifications_are_not_messages() {
+        let packet_message = notification_packet(
+            "FriendMessagesClient.MessageReaction#1",
+            CFriendMessages_IncomingMessage_Notification::new(),
+        );
+
+        let decoded: Option<CFriendMessages_IncomingMessage_Notification> =
+            notification(&packet_message, INCOMING_MESSAGE);
+        assert!(decoded.is_none());
+    }
+
+    #[tokio::test]
+    async fn sends_messages_and_typing() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let friend_messages = FriendMessages::new(connection.clone(), EventBus::new());
+
+        let send = tokio::spawn({
+            let friend_messages = friend_messages.clone();
+            async move {
+                friend_messages.send_typing(&SteamID::from_steam64(FRIEND)).await?;
+                friend_messages
+                    .send_message(&SteamID::from_steam64(FRIEND), "hi there")
+                    .await
+            }
+        });
+
+        let (job_id, request): (_, CFriendMessages_SendMessage_Request) =
+            sent_call(sent.recv().await.unwrap(), SEND_MESSAGE);
+        assert_eq!(request.chat_entry_type(), EChatEntryType::Typing as i32);
+        connection.inject(reply(job_id, CFriendMessages_SendMessage_Response::new()));
+
+        let (job_id, request): (_, CFriendMessages_SendMessage_Request) =
+            sent_call(sent.recv().await.unwrap(), SEND_MESSAGE);
+        assert_eq!(request.steamid(), FRIEND);
+        assert_eq!(request.chat_entry_type(), EChatEntryType::ChatMsg as i32);
+        assert_eq!(request.message(), "hi there");
+
+        let mut response = CFriendMessages_SendMessage_Response::new();
+        response.set_modified_message("hi there".to_string());
+        response.set_server_timestamp(1600000000);
+        response.set_ordinal(2);
+        connection.inject(reply(job_id, response));
+
+        let sent_message = send.await.unwrap().unwrap();
+        assert_eq!(sent_message.message, "hi there");
+        assert_eq!(sent_message.timestamp, 1600000000);
+        assert_eq!(sent_message.ordinal, 2);
+    }
+
+    #[tokio::test]
+    async fn history_senders_are_steam_ids() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let friend_messages = FriendMessages::new(connection.clone(), EventBus::new());
+
+        let history = tokio::spawn({
+            let friend_messages = friend_messages.clone();
+            async move {
+                friend_messages
+                    .recent_messages(&SteamID::from_steam64(FRIEND), 10)
+                    .await
+            }
+        });
+
+        let (job_id, request): (_, CFriendMessages_GetRecentMessages_Request) =
+            sent_call(sent.recv().await.unwrap(), GET_RECENT_MESSAGES);
+        assert_eq!(request.steamid2(), FRIEND);
+        assert_eq!(request.count(), 10);
+
+        let mut message = RecentMessage::new();
+        message.set_accountid(22202);
+        message.set_message("old news".to_string());
+        message.set_timestamp(1500000000);
+        let mut response = CFriendMessages_GetRecentMessages_Response::new();
+        response.messages.push(message);
+        connection.inject(reply(job_id, response));
+
+        let history = history.await.unwrap().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].sender.to_steam64(), FRIEND);
+        assert_eq!(history[0].message, "old news");
+    }
+}
@@ -1,22 +1,43 @@
-//! Handle events through [PacketMessage] matching.
+//! Handlers turn [PacketMessage](crate::messages::packet::PacketMessage)s into typed events on the
+//! [EventBus](crate::events::EventBus), and expose the requests related to them.
 
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::connection::ConnectionHandle;
+use crate::connection::PacketRx;
 use crate::messages::packet::PacketMessage;
 
 // we try to keep the same nomenclature as SteamKit2
-pub mod steam_client;
+pub mod friend_messages;
+pub mod steam_apps;
+pub mod steam_authentication;
+pub mod steam_chat_rooms;
 pub mod steam_friends;
+pub mod steam_game_coordinator;
+pub mod steam_unified_messages;
+pub mod steam_user;
 
-#[derive(Debug, Copy, Clone)]
-pub enum SteamEvents {
-    SteamFriends,
-    SteamUser,
-    SteamClient,
-}
+/// Feeds every message read from `connection` to `handle_msg`, until the connection is gone.
+///
+/// `handler` is only used to tell who fell behind, when messages are missed.
+pub(crate) async fn forward_packets<F>(
+    handler: &str,
+    connection: ConnectionHandle,
+    mut packets: PacketRx,
+    mut handle_msg: F,
+) where
+    F: FnMut(PacketMessage),
+{
+    loop {
+        let packet_message = tokio::select! {
+            packet_message = packets.recv() => packet_message,
+            _ = connection.closed() => break,
+        };
 
-trait HandlerKind {
-    /// Each handler must implement a dispatch map, to connect emsgs to callbacks
-    /// Find EMsg on dispatch map, and execute related function callback
-    fn handle_msg(packet_message: PacketMessage) {}
+        match packet_message {
+            Ok(packet_message) => handle_msg(packet_message),
+            Err(RecvError::Lagged(skipped)) => warn!("{} lagged behind and missed {} messages.", handler, skipped),
+            Err(RecvError::Closed) => break,
+        }
+    }
 }
-
-// handles related to friends coming online etc
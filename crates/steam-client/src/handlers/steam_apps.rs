@@ -0,0 +1,690 @@
+//! App and package metadata through PICS, the product info service Steam clients use to keep their catalog fresh.
+//!
+//! Apps come as text KeyValues and packages as binary ones, both are kept whole in [AppInfo::key_values] and
+//! [PackageInfo::key_values], next to the fields most tools look for already typed.
+//!
+//! Some products can only be seen with an access token, which Steam hands out to accounts entitled to them through
+//! [SteamApps::access_tokens].
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Steam/Handlers/SteamApps/SteamApps.cs
+
+use std::collections::HashMap;
+
+use steam_keyvalues::KeyValue;
+use steam_language_gen::generated::enums::EMsg;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::cmsg_client_picsproduct_info_request;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::cmsg_client_picsproduct_info_response;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::CMsgClientPICSAccessTokenRequest;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::CMsgClientPICSAccessTokenResponse;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::CMsgClientPICSChangesSinceRequest;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::CMsgClientPICSChangesSinceResponse;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::CMsgClientPICSProductInfoRequest;
+use steam_protobuf::protobufs::steammessages_clientserver_appinfo::CMsgClientPICSProductInfoResponse;
+
+use crate::connection::ConnectionHandle;
+use crate::errors::AppsError;
+use crate::errors::ConnectionError;
+use crate::messages::message::ClientMessage;
+use crate::messages::packet::PacketMessage;
+
+/// App or package to ask product info for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProductRequest {
+    /// App or package id.
+    pub id: u32,
+    /// Access token from [SteamApps::access_tokens], zero for products that do not need one.
+    pub access_token: u64,
+}
+
+impl ProductRequest {
+    /// Asks for a product that does not need an access token.
+    pub fn new(id: u32) -> Self {
+        Self { id, access_token: 0 }
+    }
+
+    /// Asks for a product with its access token.
+    pub fn with_access_token(id: u32, access_token: u64) -> Self {
+        Self { id, access_token }
+    }
+}
+
+impl From<u32> for ProductRequest {
+    fn from(id: u32) -> Self {
+        Self::new(id)
+    }
+}
+
+/// An app or package that changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProductChange {
+    /// App or package id.
+    pub id: u32,
+    /// Changelist it last changed in.
+    pub change_number: u32,
+    /// Whether its product info can only be seen with an access token.
+    pub needs_token: bool,
+}
+
+/// What changed since a changelist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changes {
+    /// Latest changelist.
+    pub current_change_number: u32,
+    /// Steam thinks we are too far behind, and everything should be asked for again instead.
+    pub force_full_update: bool,
+    /// Apps that changed.
+    pub apps: Vec<ProductChange>,
+    /// Packages that changed.
+    pub packages: Vec<ProductChange>,
+}
+
+/// Access tokens Steam granted, and the products it denied them for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessTokens {
+    /// Tokens by app id.
+    pub apps: HashMap<u32, u64>,
+    /// Tokens by package id.
+    pub packages: HashMap<u32, u64>,
+    /// Apps we are not entitled to.
+    pub denied_apps: Vec<u32>,
+    /// Packages we are not entitled to.
+    pub denied_packages: Vec<u32>,
+}
+
+/// A depot of an app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depot {
+    /// Depot id.
+    pub depot_id: u32,
+    /// Name, when the depot has one.
+    pub name: Option<String>,
+    /// Size of its files once installed, in bytes.
+    pub max_size: Option<u64>,
+    /// Manifest id by branch, only of the branches that are not encrypted.
+    pub manifests: HashMap<String, u64>,
+}
+
+/// A branch of an app, such as `public` or a beta.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// Branch name.
+    pub name: String,
+    /// Build currently set live on it.
+    pub build_id: u32,
+    /// Description, usually only for betas.
+    pub description: Option<String>,
+    /// Whether it needs a password to be opted into.
+    pub password_required: bool,
+    /// When its build was last changed, as an unix timestamp.
+    pub time_updated: Option<u64>,
+}
+
+/// Product info of an app.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppInfo {
+    /// App id.
+    pub app_id: u32,
+    /// Changelist it last changed in.
+    pub change_number: u32,
+    /// We asked without an access token, so only the public part came back.
+    pub missing_token: bool,
+    /// SHA-1 of its KeyValues text, empty if unknown.
+    pub sha: Vec<u8>,
+    /// Where its KeyValues must be downloaded from, when Steam did not send them along.
+    ///
+    /// Everything below is left empty until they are passed to [AppInfo::with_key_values].
+    pub http_url: Option<String>,
+    /// Name, from `common`.
+    pub name: Option<String>,
+    /// Kind of app, such as `Game` or `Tool`, from `common`.
+    pub app_type: Option<String>,
+    /// Depots, from `depots`.
+    pub depots: Vec<Depot>,
+    /// Branches, from `depots/branches`.
+    pub branches: Vec<Branch>,
+    /// Everything Steam sent, under the `appinfo` root.
+    pub key_values: KeyValue,
+}
+
+impl AppInfo {
+    /// Reads the text KeyValues of the app, such as the ones downloaded from [http_url](Self::http_url) once
+    /// unzipped.
+    pub fn with_key_values(self, text: &str) -> Result<AppInfo, AppsError> {
+        let key_values = steam_keyvalues::from_text(text)
+            .map_err(|error| AppsError::KeyValues(self.app_id, error))?
+            .1;
+
+        let string = |path: &[&str]| {
+            key_values
+                .path(path)
+                .and_then(KeyValue::as_str)
+                .map(ToString::to_string)
+        };
+        let depots_section = key_values.get("depots");
+
+        // depots are the sections under a numeric key, the others being settings of all of them
+        let depots = depots_section
+            .into_iter()
+            .flat_map(KeyValue::children)
+            .filter_map(|(key, depot)| {
+                let depot_id = key.parse().ok()?;
+                Some(Depot {
+                    depot_id,
+                    name: depot.get("name").and_then(KeyValue::as_str).map(ToString::to_string),
+                    max_size: depot.get("maxsize").and_then(KeyValue::as_u64),
+                    manifests: depot
+                        .get("manifests")
+                        .into_iter()
+                        .flat_map(KeyValue::children)
+                        .filter_map(|(branch, manifest)| {
+                            // used to be the bare id, now it sits next to the size of the manifest
+                            let gid = manifest.get("gid").unwrap_or(manifest).as_u64()?;
+                            Some((branch.to_string(), gid))
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+
+        let branches = depots_section
+            .and_then(|depots| depots.get("branches"))
+            .into_iter()
+            .flat_map(KeyValue::children)
+            .map(|(name, branch)| Branch {
+                name: name.to_string(),
+                build_id: branch.get("buildid").and_then(KeyValue::as_u32).unwrap_or(0),
+                description: branch
+                    .get("description")
+                    .and_then(KeyValue::as_str)
+                    .map(ToString::to_string),
+                password_required: branch.get("pwdrequired").and_then(KeyValue::as_bool).unwrap_or(false),
+                time_updated: branch.get("timeupdated").and_then(KeyValue::as_u64),
+            })
+            .collect();
+
+        Ok(AppInfo {
+            http_url: None,
+            name: string(&["common", "name"]),
+            app_type: string(&["common", "type"]),
+            depots,
+            branches,
+            key_values,
+            ..self
+        })
+    }
+
+    fn sha_hex(&self) -> String {
+        self.sha.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Product info of a package.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageInfo {
+    /// Package id.
+    pub package_id: u32,
+    /// Changelist it last changed in.
+    pub change_number: u32,
+    /// We asked without an access token, so only the public part came back.
+    pub missing_token: bool,
+    /// How the package is paid for, as an `EBillingType`.
+    pub billing_type: Option<u32>,
+    /// How the license works, as an `ELicenseType`.
+    pub license_type: Option<u32>,
+    /// Whether it is available, as an `EPackageStatus`.
+    pub status: Option<u32>,
+    /// Apps it grants.
+    pub app_ids: Vec<u32>,
+    /// Depots it grants.
+    pub depot_ids: Vec<u32>,
+    /// Everything Steam sent, under the package root.
+    pub key_values: KeyValue,
+}
+
+/// Product info Steam sent back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProductInfo {
+    /// Apps that were found.
+    pub apps: Vec<AppInfo>,
+    /// Packages that were found.
+    pub packages: Vec<PackageInfo>,
+    /// Apps that do not exist.
+    pub unknown_apps: Vec<u32>,
+    /// Packages that do not exist.
+    pub unknown_packages: Vec<u32>,
+}
+
+/// Asks Steam about apps and packages.
+#[derive(Debug, Clone)]
+pub struct SteamApps {
+    connection: ConnectionHandle,
+}
+
+impl SteamApps {
+    pub(crate) fn new(connection: ConnectionHandle) -> Self {
+        Self { connection }
+    }
+
+    /// Apps and packages that changed since `since_change_number`.
+    pub async fn changes_since(
+        &self,
+        since_change_number: u32,
+        send_app_changes: bool,
+        send_package_changes: bool,
+    ) -> Result<Changes, ConnectionError> {
+        let mut message =
+            ClientMessage::<CMsgClientPICSChangesSinceRequest>::new_proto(EMsg::ClientPICSChangesSinceRequest);
+        message.body.set_since_change_number(since_change_number);
+        message.body.set_send_app_info_changes(send_app_changes);
+        message.body.set_send_package_info_changes(send_package_changes);
+
+        let reply = self.connection.send_job(message).await?;
+        let response = ClientMessage::<CMsgClientPICSChangesSinceResponse>::from_proto_packet(reply)
+            .map_err(ConnectionError::PacketError)?
+            .body;
+
+        Ok(Changes {
+            current_change_number: response.current_change_number(),
+            force_full_update: response.force_full_update(),
+            apps: response
+                .app_changes
+                .iter()
+                .map(|change| ProductChange {
+                    id: change.appid(),
+                    change_number: change.change_number(),
+                    needs_token: change.needs_token(),
+                })
+                .collect(),
+            packages: response
+                .package_changes
+                .iter()
+                .map(|change| ProductChange {
+                    id: change.packageid(),
+                    change_number: change.change_number(),
+                    needs_token: change.needs_token(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Asks for the access tokens of `app_ids` and `package_ids`.
+    pub async fn access_tokens(&self, app_ids: &[u32], package_ids: &[u32]) -> Result<AccessTokens, ConnectionError> {
+        let mut message =
+            ClientMessage::<CMsgClientPICSAccessTokenRequest>::new_proto(EMsg::ClientPICSAccessTokenRequest);
+        message.body.appids = app_ids.to_vec();
+        message.body.packageids = package_ids.to_vec();
+
+        let reply = self.connection.send_job(message).await?;
+        let response = ClientMessage::<CMsgClientPICSAccessTokenResponse>::from_proto_packet(reply)
+            .map_err(ConnectionError::PacketError)?
+            .body;
+
+        Ok(AccessTokens {
+            apps: response
+                .app_access_tokens
+                .iter()
+                .map(|token| (token.appid(), token.access_token()))
+                .collect(),
+            packages: response
+                .package_access_tokens
+                .iter()
+                .map(|token| (token.packageid(), token.access_token()))
+                .collect(),
+            denied_apps: response.app_denied_tokens,
+            denied_packages: response.package_denied_tokens,
+        })
+    }
+
+    /// Product info of `apps` and `packages`, in a single response.
+    pub async fn product_info(
+        &self,
+        apps: &[ProductRequest],
+        packages: &[ProductRequest],
+    ) -> Result<ProductInfo, AppsError> {
+        let mut message =
+            ClientMessage::<CMsgClientPICSProductInfoRequest>::new_proto(EMsg::ClientPICSProductInfoRequest);
+        message.body.apps = apps
+            .iter()
+            .map(|app| {
+                let mut request = cmsg_client_picsproduct_info_request::AppInfo::new();
+                request.set_appid(app.id);
+                request.set_access_token(app.access_token);
+                request
+            })
+            .collect();
+        message.body.packages = packages
+            .iter()
+            .map(|package| {
+                let mut request = cmsg_client_picsproduct_info_request::PackageInfo::new();
+                request.set_packageid(package.id);
+                request.set_access_token(package.access_token);
+                request
+            })
+            .collect();
+        message.body.set_meta_data_only(false);
+        // otherwise big requests are answered in many messages, while jobs only wait for one
+        message.body.set_single_response(true);
+
+        let reply = self.connection.send_job(message).await?;
+        product_info_from(reply)
+    }
+}
+
+fn product_info_from(reply: PacketMessage) -> Result<ProductInfo, AppsError> {
+    let response = ClientMessage::<CMsgClientPICSProductInfoResponse>::from_proto_packet(reply)
+        .map_err(ConnectionError::PacketError)?
+        .body;
+
+    Ok(ProductInfo {
+        apps: response
+            .apps
+            .iter()
+            .map(|app| app_info(app, response.http_host.as_deref()))
+            .collect::<Result<_, _>>()?,
+        packages: response.packages.iter().map(package_info).collect::<Result<_, _>>()?,
+        unknown_apps: response.unknown_appids,
+        unknown_packages: response.unknown_packageids,
+    })
+}
+
+fn app_info(
+    app: &cmsg_client_picsproduct_info_response::AppInfo,
+    http_host: Option<&str>,
+) -> Result<AppInfo, AppsError> {
+    let app_info = AppInfo {
+        app_id: app.appid(),
+        change_number: app.change_number(),
+        missing_token: app.missing_token(),
+        sha: app.sha().to_vec(),
+        http_url: None,
+        name: None,
+        app_type: None,
+        depots: vec![],
+        branches: vec![],
+        key_values: KeyValue::section(),
+    };
+
+    match (app.buffer(), http_host) {
+        // big apps are left for us to download, by the hash of their KeyValues
+        ([], Some(http_host)) if !app_info.sha.is_empty() => Ok(AppInfo {
+            http_url: Some(format!(
+                "http://{}/appinfo/{}/sha/{}.txt.gz",
+                http_host,
+                app_info.app_id,
+                app_info.sha_hex()
+            )),
+            ..app_info
+        }),
+        ([], _) => Err(AppsError::MissingKeyValues(app_info.app_id)),
+        (buffer, _) => app_info.with_key_values(&String::from_utf8_lossy(buffer)),
+    }
+}
+
+fn package_info(package: &cmsg_client_picsproduct_info_response::PackageInfo) -> Result<PackageInfo, AppsError> {
+    // the buffer starts with the package id, followed by the binary KeyValues
+    let key_values = match package.buffer() {
+        [] => KeyValue::section(),
+        buffer => {
+            steam_keyvalues::from_binary(buffer.get(4..).unwrap_or_default())
+                .map_err(|error| AppsError::KeyValues(package.packageid(), error))?
+                .1
+        }
+    };
+
+    let ids = |key: &str| -> Vec<u32> {
+        key_values
+            .get(key)
+            .into_iter()
+            .flat_map(KeyValue::children)
+            .filter_map(|(_, id)| id.as_u32())
+            .collect()
+    };
+
+    Ok(PackageInfo {
+        package_id: package.packageid(),
+        change_number: package.change_number(),
+        missing_token: package.missing_token(),
+        billing_type: key_values.get("billingtype").and_then(KeyValue::as_u32),
+        license_type: key_values.get("licensetype").and_then(KeyValue::as_u32),
+        status: key_values.get("status").and_then(KeyValue::as_u32),
+        app_ids: ids("appids"),
+        depot_ids: ids("depotids"),
+        key_values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use steam_protobuf::protobufs::steammessages_clientserver_appinfo::cmsg_client_picsaccess_token_response;
+    use steam_protobuf::protobufs::steammessages_clientserver_appinfo::cmsg_client_picschanges_since_response;
+
+    use super::*;
+    use crate::connection::testing::job_reply;
+    use crate::connection::testing::sent_packet;
+
+    const APP_INFO: &str = r#""appinfo"
+{
+    "appid"     "730"
+    "common"
+    {
+        "name"      "Counter-Strike 2"
+        "type"      "Game"
+    }
+    "depots"
+    {
+        "731"
+        {
+            "name"      "Counter-Strike 2 Content"
+            "maxsize"   "34359738368"
+            "manifests"
+            {
+                "public"
+                {
+                    "gid"       "7617088375292372759"
+                    "size"      "33345362339"
+                }
+            }
+        }
+        "732"
+        {
+            "manifests"
+            {
+                "public"    "8085935009938155917"
+            }
+        }
+        "branches"
+        {
+            "public"
+            {
+                "buildid"       "13386931"
+                "timeupdated"   "1700000000"
+            }
+            "beta"
+            {
+                "buildid"       "13386999"
+                "description"   "Beta"
+                "pwdrequired"   "1"
+            }
+        }
+        "baselanguages"     "english"
+    }
+}
+"#;
+
+    #[tokio::test]
+    async fn changes_are_listed() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let apps = SteamApps::new(connection.clone());
+
+        let changes = tokio::spawn(async move { apps.changes_since(100, true, true).await });
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(request.emsg(), EMsg::ClientPICSChangesSinceRequest);
+        let body: CMsgClientPICSChangesSinceRequest = request.decode().unwrap();
+        assert_eq!(body.since_change_number(), 100);
+
+        let mut change = cmsg_client_picschanges_since_response::AppChange::new();
+        change.set_appid(730);
+        change.set_change_number(120);
+        change.set_needs_token(true);
+        let mut response = CMsgClientPICSChangesSinceResponse::new();
+        response.set_current_change_number(130);
+        response.app_changes.push(change);
+        connection.inject(job_reply(
+            EMsg::ClientPICSChangesSinceResponse,
+            request.jobs_ids().0,
+            response,
+        ));
+
+        let changes = changes.await.unwrap().unwrap();
+        assert_eq!(changes.current_change_number, 130);
+        assert_eq!(
+            changes.apps,
+            vec![ProductChange {
+                id: 730,
+                change_number: 120,
+                needs_token: true
+            }]
+        );
+        assert!(changes.packages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn access_tokens_are_keyed_by_product() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let apps = SteamApps::new(connection.clone());
+
+        let tokens = tokio::spawn(async move { apps.access_tokens(&[730, 440], &[]).await });
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        let body: CMsgClientPICSAccessTokenRequest = request.decode().unwrap();
+        assert_eq!(body.appids, vec![730, 440]);
+
+        let mut token = cmsg_client_picsaccess_token_response::AppToken::new();
+        token.set_appid(730);
+        token.set_access_token(1234);
+        let mut response = CMsgClientPICSAccessTokenResponse::new();
+        response.app_access_tokens.push(token);
+        response.app_denied_tokens.push(440);
+        connection.inject(job_reply(
+            EMsg::ClientPICSAccessTokenResponse,
+            request.jobs_ids().0,
+            response,
+        ));
+
+        let tokens = tokens.await.unwrap().unwrap();
+        assert_eq!(tokens.apps.get(&730), Some(&1234));
+        assert_eq!(tokens.denied_apps, vec![440]);
+    }
+
+    #[tokio::test]
+    async fn product_info_is_typed() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let apps = SteamApps::new(connection.clone());
+
+        let product_info = tokio::spawn(async move {
+            apps.product_info(
+                &[ProductRequest::with_access_token(730, 1234)],
+                &[ProductRequest::new(7)],
+            )
+            .await
+        });
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        let body: CMsgClientPICSProductInfoRequest = request.decode().unwrap();
+        assert_eq!(body.apps[0].appid(), 730);
+        assert_eq!(body.apps[0].access_token(), 1234);
+        assert_eq!(body.packages[0].packageid(), 7);
+
+        let mut app = cmsg_client_picsproduct_info_response::AppInfo::new();
+        app.set_appid(730);
+        app.set_change_number(120);
+        app.set_buffer(format!("{}\0", APP_INFO).into_bytes().into());
+
+        let mut buffer = 7u32.to_le_bytes().to_vec();
+        buffer.extend_from_slice(b"\x007\x00\x02billingtype\x00");
+        buffer.extend_from_slice(&10i32.to_le_bytes());
+        buffer.extend_from_slice(b"\x00appids\x00\x020\x00");
+        buffer.extend_from_slice(&730i32.to_le_bytes());
+        buffer.extend_from_slice(b"\x08\x00depotids\x00\x020\x00");
+        buffer.extend_from_slice(&731i32.to_le_bytes());
+        buffer.extend_from_slice(b"\x021\x00");
+        buffer.extend_from_slice(&732i32.to_le_bytes());
+        buffer.extend_from_slice(b"\x08\x08\x08");
+        let mut package = cmsg_client_picsproduct_info_response::PackageInfo::new();
+        package.set_packageid(7);
+        package.set_buffer(buffer.into());
+
+        let mut response = CMsgClientPICSProductInfoResponse::new();
+        response.apps.push(app);
+        response.packages.push(package);
+        response.unknown_appids.push(1);
+        connection.inject(job_reply(
+            EMsg::ClientPICSProductInfoResponse,
+            request.jobs_ids().0,
+            response,
+        ));
+
+        let product_info = product_info.await.unwrap().unwrap();
+        assert_eq!(product_info.unknown_apps, vec![1]);
+
+        let app = &product_info.apps[0];
+        assert_eq!(app.app_id, 730);
+        assert_eq!(app.name.as_deref(), Some("Counter-Strike 2"));
+        assert_eq!(app.app_type.as_deref(), Some("Game"));
+        assert_eq!(app.depots.len(), 2);
+        assert_eq!(app.depots[0].depot_id, 731);
+        assert_eq!(app.depots[0].max_size, Some(34359738368));
+        assert_eq!(app.depots[0].manifests["public"], 7617088375292372759);
+        assert_eq!(app.depots[1].manifests["public"], 8085935009938155917);
+
+        assert_eq!(app.branches.len(), 2);
+        assert_eq!(app.branches[0].name, "public");
+        assert_eq!(app.branches[0].build_id, 13386931);
+        assert_eq!(app.branches[0].time_updated, Some(1700000000));
+        assert!(app.branches[1].password_required);
+        assert_eq!(app.branches[1].description.as_deref(), Some("Beta"));
+
+        let package = &product_info.packages[0];
+        assert_eq!(package.package_id, 7);
+        assert_eq!(package.billing_type, Some(10));
+        assert_eq!(package.app_ids, vec![730]);
+        assert_eq!(package.depot_ids, vec![731, 732]);
+    }
+
+    #[test]
+    fn malformed_product_info_names_the_product() {
+        let mut app = cmsg_client_picsproduct_info_response::AppInfo::new();
+        app.set_appid(730);
+        app.set_buffer(b"\"appinfo\" {".to_vec().into());
+
+        assert!(matches!(app_info(&app, None), Err(AppsError::KeyValues(730, _))));
+    }
+
+    #[test]
+    fn big_apps_are_left_to_download() {
+        let mut app = cmsg_client_picsproduct_info_response::AppInfo::new();
+        app.set_appid(730);
+        app.set_sha(vec![0xab, 0x01].into());
+
+        let app = app_info(&app, Some("media.steampowered.com")).unwrap();
+        assert_eq!(
+            app.http_url.as_deref(),
+            Some("http://media.steampowered.com/appinfo/730/sha/ab01.txt.gz")
+        );
+        assert!(app.depots.is_empty());
+
+        let app = app.with_key_values(APP_INFO).unwrap();
+        assert_eq!(app.http_url, None);
+        assert_eq!(app.name.as_deref(), Some("Counter-Strike 2"));
+        assert_eq!(app.depots.len(), 2);
+    }
+
+    #[test]
+    fn apps_without_product_info_are_an_error() {
+        let mut app = cmsg_client_picsproduct_info_response::AppInfo::new();
+        app.set_appid(730);
+
+        assert!(matches!(app_info(&app, None), Err(AppsError::MissingKeyValues(730))));
+    }
+}
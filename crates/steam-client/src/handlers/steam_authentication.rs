@@ -0,0 +1,316 @@
+//! Credentials exchange through the `Authentication` service, the same one the Steam website and the
+//! mobile app use through `IAuthenticationService`.
+//!
+//! It trades an account name and password, plus a Steam Guard code when the account has one, for an
+//! access token and a refresh token. The refresh token logs on to the CM with
+//! [LogOnDetails::refresh_token], and the pair logs on to the Steam website, such as with `steam-mobile`.
+//!
+//! Reference: https://github.com/DoctorMcKay/node-steam-session
+
+use std::time::Duration;
+
+use steam_protobuf::protobufs::enums::ESessionPersistence;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_BeginAuthSessionViaCredentials_Request;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_BeginAuthSessionViaCredentials_Response;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_DeviceDetails;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_GetPasswordRSAPublicKey_Request;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_GetPasswordRSAPublicKey_Response;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_PollAuthSessionStatus_Request;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_PollAuthSessionStatus_Response;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_UpdateAuthSessionWithSteamGuardCode_Request;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::CAuthentication_UpdateAuthSessionWithSteamGuardCode_Response;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::EAuthSessionGuardType;
+use steam_protobuf::protobufs::steammessages_auth_steamclient::EAuthTokenPlatformType;
+use steamid_parser::SteamID;
+
+use crate::connection::ConnectionHandle;
+use crate::errors::AuthenticationError;
+use crate::handlers::steam_unified_messages::SteamUnifiedMessages;
+use crate::handlers::steam_user::LogOnDetails;
+
+const GET_PASSWORD_RSA_PUBLIC_KEY: &str = "Authentication.GetPasswordRSAPublicKey#1";
+const BEGIN_AUTH_SESSION_VIA_CREDENTIALS: &str = "Authentication.BeginAuthSessionViaCredentials#1";
+const UPDATE_AUTH_SESSION_WITH_STEAM_GUARD_CODE: &str = "Authentication.UpdateAuthSessionWithSteamGuardCode#1";
+const POLL_AUTH_SESSION_STATUS: &str = "Authentication.PollAuthSessionStatus#1";
+
+/// How often to poll when Steam does not say.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Longest we wait between polls, whatever Steam says.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+const DEVICE_FRIENDLY_NAME: &str = "steam-client";
+
+/// Steam Guard code proving the logon is ours.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SteamGuardCode {
+    /// Generated by the mobile authenticator.
+    Device(String),
+    /// Sent to the account email.
+    Email(String),
+}
+
+/// Tokens Steam grants once the credentials are accepted.
+#[derive(Clone)]
+pub struct AuthTokens {
+    /// Login name the tokens were issued to.
+    pub account_name: String,
+    /// Account the tokens belong to.
+    pub steam_id: SteamID,
+    /// Short lived token, for web sessions.
+    pub access_token: String,
+    /// Long lived token, to log on again without the password.
+    pub refresh_token: String,
+}
+
+impl AuthTokens {
+    /// Logs on to the CM with these tokens.
+    pub fn logon_details(&self) -> LogOnDetails {
+        LogOnDetails::refresh_token(self.account_name.clone(), self.refresh_token.clone())
+    }
+}
+
+/// Never print tokens.
+impl std::fmt::Debug for AuthTokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthTokens")
+            .field("account_name", &self.account_name)
+            .field("steam_id", &self.steam_id)
+            .finish()
+    }
+}
+
+/// Exchanges credentials for tokens over the CM.
+#[derive(Debug, Clone)]
+pub struct SteamAuthentication {
+    unified_messages: SteamUnifiedMessages,
+}
+
+impl SteamAuthentication {
+    pub(crate) fn new(connection: ConnectionHandle) -> Self {
+        Self {
+            unified_messages: SteamUnifiedMessages::new(connection),
+        }
+    }
+
+    /// Trades `account_name` and `password` for tokens.
+    ///
+    /// Accounts protected by Steam Guard need `guard_code`. Without it, Steam waits for the logon to be
+    /// approved elsewhere, such as in the mobile app, and this only resolves once it is, or fails when
+    /// Steam gives up on the session.
+    pub async fn log_on_with_credentials(
+        &self,
+        account_name: &str,
+        password: &str,
+        guard_code: Option<SteamGuardCode>,
+    ) -> Result<AuthTokens, AuthenticationError> {
+        let mut request = CAuthentication_GetPasswordRSAPublicKey_Request::new();
+        request.set_account_name(account_name.to_string());
+        let public_key: CAuthentication_GetPasswordRSAPublicKey_Response = self
+            .unified_messages
+            .call_service_method(GET_PASSWORD_RSA_PUBLIC_KEY, request)
+            .await?;
+
+        let encrypted_password =
+            steam_crypto::encrypt_password(password, public_key.publickey_mod(), public_key.publickey_exp())
+                .map_err(|_| AuthenticationError::Encryption)?;
+
+        let mut device_details = CAuthentication_DeviceDetails::new();
+        device_details.set_device_friendly_name(DEVICE_FRIENDLY_NAME.to_string());
+        device_details.set_platform_type(EAuthTokenPlatformType::k_EAuthTokenPlatformType_SteamClient);
+
+        let mut request = CAuthentication_BeginAuthSessionViaCredentials_Request::new();
+        request.set_account_name(account_name.to_string());
+        request.set_encrypted_password(encrypted_password);
+        request.set_encryption_timestamp(public_key.timestamp());
+        request.set_remember_login(true);
+        request.set_platform_type(EAuthTokenPlatformType::k_EAuthTokenPlatformType_SteamClient);
+        request.set_persistence(ESessionPersistence::k_ESessionPersistence_Persistent);
+        request.set_website_id("Client".to_string());
+        request.device_details = Some(device_details).into();
+        let session: CAuthentication_BeginAuthSessionViaCredentials_Response = self
+            .unified_messages
+            .call_service_method(BEGIN_AUTH_SESSION_VIA_CREDENTIALS, request)
+            .await?;
+
+        if let Some(guard_code) = guard_code {
+            let (code_type, code) = match guard_code {
+                SteamGuardCode::Device(code) => (EAuthSessionGuardType::k_EAuthSessionGuardType_DeviceCode, code),
+                SteamGuardCode::Email(code) => (EAuthSessionGuardType::k_EAuthSessionGuardType_EmailCode, code),
+            };
+
+            let mut request = CAuthentication_UpdateAuthSessionWithSteamGuardCode_Request::new();
+            request.set_client_id(session.client_id());
+            request.set_steamid(session.steamid());
+            request.set_code_type(code_type);
+            request.set_code(code);
+            let _: CAuthentication_UpdateAuthSessionWithSteamGuardCode_Response = self
+                .unified_messages
+                .call_service_method(UPDATE_AUTH_SESSION_WITH_STEAM_GUARD_CODE, request)
+                .await?;
+        }
+
+        let interval = poll_interval(session.interval());
+        let mut client_id = session.client_id();
+
+        loop {
+            let mut request = CAuthentication_PollAuthSessionStatus_Request::new();
+            request.set_client_id(client_id);
+            request.set_request_id(session.request_id().to_vec().into());
+            let status: CAuthentication_PollAuthSessionStatus_Response = self
+                .unified_messages
+                .call_service_method(POLL_AUTH_SESSION_STATUS, request)
+                .await?;
+
+            if status.has_refresh_token() {
+                let account_name = match status.account_name() {
+                    "" => account_name.to_string(),
+                    name => name.to_string(),
+                };
+                return Ok(AuthTokens {
+                    account_name,
+                    steam_id: SteamID::from_steam64(session.steamid()),
+                    access_token: status.access_token().to_string(),
+                    refresh_token: status.refresh_token().to_string(),
+                });
+            }
+
+            // Steam may hand the session over to a new client id while we wait
+            if status.has_new_client_id() {
+                client_id = status.new_client_id();
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// How long to wait between polls, given the interval Steam asked for, in seconds.
+fn poll_interval(seconds: f32) -> Duration {
+    match Duration::try_from_secs_f32(seconds) {
+        Ok(interval) if !interval.is_zero() => interval.min(MAX_POLL_INTERVAL),
+        _ => DEFAULT_POLL_INTERVAL,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::testing::reply;
+    use crate::connection::testing::sent_call;
+
+    const STEAM_ID: u64 = 76561197960287930;
+
+    /// 1024 bits, only its size matters here.
+    const PUBLIC_KEY_MOD: &str = "B5FF96857EB7785B6FD3D064AE4B6645A701B666DC82BCA936F0EF9BBB74C527CE822FFAEE70A7D08EA46EFF256A74C439F15A44C552E632D6425D02A3296205617C9FBEEA92FC4315981365596AB1CB2EFE9CBACE43DA2D8E43E88E52ED21B26E0749FD1AF41C930AB13CB895836FB7786E93979B85D1D8560978306A333D9D";
+
+    #[tokio::test]
+    async fn credentials_are_traded_for_tokens() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let authentication = SteamAuthentication::new(connection.clone());
+
+        let log_on = tokio::spawn(async move {
+            authentication
+                .log_on_with_credentials("gabe", "hunter2", Some(SteamGuardCode::Device("R2D2C".to_string())))
+                .await
+        });
+
+        let (job_id, request): (_, CAuthentication_GetPasswordRSAPublicKey_Request) =
+            sent_call(sent.recv().await.unwrap(), GET_PASSWORD_RSA_PUBLIC_KEY);
+        assert_eq!(request.account_name(), "gabe");
+        let mut public_key = CAuthentication_GetPasswordRSAPublicKey_Response::new();
+        public_key.set_publickey_mod(PUBLIC_KEY_MOD.to_string());
+        public_key.set_publickey_exp("010001".to_string());
+        public_key.set_timestamp(1600000000);
+        connection.inject(reply(job_id, public_key));
+
+        let (job_id, request): (_, CAuthentication_BeginAuthSessionViaCredentials_Request) =
+            sent_call(sent.recv().await.unwrap(), BEGIN_AUTH_SESSION_VIA_CREDENTIALS);
+        assert_eq!(request.account_name(), "gabe");
+        // base64 of the 128 bytes the key encrypts to
+        assert_eq!(request.encrypted_password().len(), 172);
+        assert_ne!(request.encrypted_password(), "hunter2");
+        assert_eq!(request.encryption_timestamp(), 1600000000);
+        assert_eq!(
+            request.platform_type(),
+            EAuthTokenPlatformType::k_EAuthTokenPlatformType_SteamClient
+        );
+        let mut session = CAuthentication_BeginAuthSessionViaCredentials_Response::new();
+        session.set_client_id(1);
+        session.set_request_id(b"request".to_vec().into());
+        session.set_steamid(STEAM_ID);
+        session.set_interval(0.01);
+        connection.inject(reply(job_id, session));
+
+        let (job_id, request): (_, CAuthentication_UpdateAuthSessionWithSteamGuardCode_Request) =
+            sent_call(sent.recv().await.unwrap(), UPDATE_AUTH_SESSION_WITH_STEAM_GUARD_CODE);
+        assert_eq!(request.client_id(), 1);
+        assert_eq!(request.steamid(), STEAM_ID);
+        assert_eq!(
+            request.code_type(),
+            EAuthSessionGuardType::k_EAuthSessionGuardType_DeviceCode
+        );
+        assert_eq!(request.code(), "R2D2C");
+        connection.inject(reply(
+            job_id,
+            CAuthentication_UpdateAuthSessionWithSteamGuardCode_Response::new(),
+        ));
+
+        // still pending, and moved to a new client id
+        let (job_id, request): (_, CAuthentication_PollAuthSessionStatus_Request) =
+            sent_call(sent.recv().await.unwrap(), POLL_AUTH_SESSION_STATUS);
+        assert_eq!(request.client_id(), 1);
+        assert_eq!(request.request_id(), b"request");
+        let mut status = CAuthentication_PollAuthSessionStatus_Response::new();
+        status.set_new_client_id(2);
+        connection.inject(reply(job_id, status));
+
+        let (job_id, request): (_, CAuthentication_PollAuthSessionStatus_Request) =
+            sent_call(sent.recv().await.unwrap(), POLL_AUTH_SESSION_STATUS);
+        assert_eq!(request.client_id(), 2);
+        let mut status = CAuthentication_PollAuthSessionStatus_Response::new();
+        status.set_account_name("gabe".to_string());
+        status.set_access_token("access".to_string());
+        status.set_refresh_token("refresh".to_string());
+        connection.inject(reply(job_id, status));
+
+        let tokens = log_on.await.unwrap().unwrap();
+        assert_eq!(tokens.steam_id.to_steam64(), STEAM_ID);
+        assert_eq!(tokens.access_token, "access");
+        assert_eq!(tokens.refresh_token, "refresh");
+        match tokens.logon_details() {
+            LogOnDetails::RefreshToken {
+                account_name,
+                refresh_token,
+            } => {
+                assert_eq!(account_name, "gabe");
+                assert_eq!(refresh_token, "refresh");
+            }
+            logon_details => panic!("expected a refresh token logon, got {:?}", logon_details),
+        }
+    }
+
+    #[test]
+    fn poll_intervals_are_sane() {
+        assert_eq!(poll_interval(0.5), Duration::from_millis(500));
+        assert_eq!(poll_interval(0.0), DEFAULT_POLL_INTERVAL);
+        assert_eq!(poll_interval(-1.0), DEFAULT_POLL_INTERVAL);
+        assert_eq!(poll_interval(f32::NAN), DEFAULT_POLL_INTERVAL);
+        assert_eq!(poll_interval(f32::INFINITY), DEFAULT_POLL_INTERVAL);
+        assert_eq!(poll_interval(1e30), DEFAULT_POLL_INTERVAL);
+        assert_eq!(poll_interval(3600.0), MAX_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn tokens_are_not_printed() {
+        let tokens = AuthTokens {
+            account_name: "gabe".to_string(),
+            steam_id: SteamID::from_steam64(STEAM_ID),
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+        };
+
+        let printed = format!("{:?}", tokens);
+        assert!(!printed.contains("access"));
+        assert!(!printed.contains("refresh"));
+    }
+}
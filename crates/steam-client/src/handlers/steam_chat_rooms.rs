@@ -0,0 +1,450 @@
+//! Group chats, through the `ChatRoom` service.
+//!
+//! A chat group, such as the one of a Steam group, holds one or more chat rooms, and members join the group as a
+//! whole. Messages and member changes arrive as `ChatRoomClient` notifications for every group we are in.
+//!
+//! Reference: https://github.com/DoctorMcKay/node-steam-user/blob/master/components/chatroom.js
+
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoomGroupState;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoomState;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_GetChatRoomGroupSummary_Response;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_GetMessageHistory_Request;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_GetMessageHistory_Response;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_GetMyChatRoomGroups_Request;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_GetMyChatRoomGroups_Response;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_IncomingChatMessage_Notification;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_JoinChatRoomGroup_Request;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_JoinChatRoomGroup_Response;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_LeaveChatRoomGroup_Request;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_LeaveChatRoomGroup_Response;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_MemberStateChange_Notification;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_SendChatMessage_Request;
+use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoom_SendChatMessage_Response;
+pub use steam_protobuf::protobufs::steammessages_chat_steamclient::EChatRoomMemberStateChange;
+use steamid_parser::SteamID;
+
+use crate::connection::ConnectionHandle;
+use crate::errors::ServiceMethodError;
+use crate::events::Event;
+use crate::events::EventBus;
+use crate::handlers::forward_packets;
+use crate::handlers::friend_messages::HistoryMessage;
+use crate::handlers::friend_messages::SentMessage;
+use crate::handlers::steam_unified_messages::notification;
+use crate::handlers::steam_unified_messages::SteamUnifiedMessages;
+use crate::messages::packet::PacketMessage;
+
+const GET_MY_CHAT_ROOM_GROUPS: &str = "ChatRoom.GetMyChatRoomGroups#1";
+const JOIN_CHAT_ROOM_GROUP: &str = "ChatRoom.JoinChatRoomGroup#1";
+const LEAVE_CHAT_ROOM_GROUP: &str = "ChatRoom.LeaveChatRoomGroup#1";
+const SEND_CHAT_MESSAGE: &str = "ChatRoom.SendChatMessage#1";
+const GET_MESSAGE_HISTORY: &str = "ChatRoom.GetMessageHistory#1";
+const INCOMING_CHAT_MESSAGE: &str = "ChatRoomClient.NotifyIncomingChatMessage#1";
+const MEMBER_STATE_CHANGE: &str = "ChatRoomClient.NotifyMemberStateChange#1";
+
+/// A chat room inside a [ChatGroup].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatRoom {
+    /// Id of the room, unique within its group.
+    pub chat_id: u64,
+    /// Name of the room.
+    pub name: String,
+}
+
+impl ChatRoom {
+    fn from_state(state: &CChatRoomState) -> Self {
+        Self {
+            chat_id: state.chat_id(),
+            name: state.chat_name().to_string(),
+        }
+    }
+}
+
+/// A chat group and its rooms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatGroup {
+    /// Id of the group.
+    pub group_id: u64,
+    /// Name of the group, empty for groups that were never named.
+    pub name: String,
+    /// Who owns the group.
+    pub owner: SteamID,
+    /// Room new members land in.
+    pub default_chat_id: u64,
+    /// Every room of the group.
+    pub rooms: Vec<ChatRoom>,
+}
+
+impl ChatGroup {
+    fn from_summary(summary: &CChatRoom_GetChatRoomGroupSummary_Response) -> Self {
+        Self {
+            group_id: summary.chat_group_id(),
+            name: summary.chat_group_name().to_string(),
+            owner: SteamID::from_steam3(summary.accountid_owner(), None, None),
+            default_chat_id: summary.default_chat_id(),
+            rooms: summary.chat_rooms.iter().map(ChatRoom::from_state).collect(),
+        }
+    }
+
+    fn from_state(state: &CChatRoomGroupState) -> Self {
+        let header = state.header_state.get_or_default();
+        Self {
+            group_id: header.chat_group_id(),
+            name: header.chat_name().to_string(),
+            owner: SteamID::from_steam3(header.accountid_owner(), None, None),
+            default_chat_id: state.default_chat_id(),
+            rooms: state.chat_rooms.iter().map(ChatRoom::from_state).collect(),
+        }
+    }
+}
+
+/// Someone sent a message to a chat room we are in.
+///
+/// Messages we sent ourselves are echoed here as well.
+#[derive(Debug, Clone)]
+pub struct ChatRoomMessage {
+    /// Group of the room.
+    pub group_id: u64,
+    /// Room the message was sent to.
+    pub chat_id: u64,
+    /// Who sent it.
+    pub sender: SteamID,
+    /// Text of the message, in BBCode.
+    pub message: String,
+    /// When Steam got the message, as an unix timestamp.
+    pub timestamp: u32,
+    /// Tells apart messages sent within the same second.
+    pub ordinal: u32,
+}
+
+/// Someone joined, left or was moderated in a chat group we are in.
+#[derive(Debug, Clone)]
+pub struct ChatRoomMemberChanged {
+    /// Group the member belongs to.
+    pub group_id: u64,
+    /// Whose membership changed.
+    pub member: SteamID,
+    /// What happened to them.
+    pub change: EChatRoomMemberStateChange,
+}
+
+impl Event for ChatRoomMessage {}
+impl Event for ChatRoomMemberChanged {}
+
+/// Joins, leaves and chats in chat groups.
+///
+/// Publishes [ChatRoomMessage] and [ChatRoomMemberChanged] on the [EventBus].
+#[derive(Debug, Clone)]
+pub struct SteamChatRooms {
+    unified_messages: SteamUnifiedMessages,
+}
+
+impl SteamChatRooms {
+    pub(crate) fn new(connection: ConnectionHandle, events: EventBus) -> Self {
+        tokio::spawn(forward_packets(
+            "SteamChatRooms",
+            connection.clone(),
+            connection.subscribe(),
+            move |packet_message| handle_msg(&events, packet_message),
+        ));
+        Self {
+            unified_messages: SteamUnifiedMessages::new(connection),
+        }
+    }
+
+    /// Every chat group we are in.
+    pub async fn chat_groups(&self) -> Result<Vec<ChatGroup>, ServiceMethodError> {
+        let response: CChatRoom_GetMyChatRoomGroups_Response = self
+            .unified_messages
+            .call_service_method(GET_MY_CHAT_ROOM_GROUPS, CChatRoom_GetMyChatRoomGroups_Request::new())
+            .await?;
+
+        let groups = response
+            .chat_room_groups
+            .iter()
+            .map(|pair| ChatGroup::from_summary(pair.group_summary.get_or_default()))
+            .collect();
+        Ok(groups)
+    }
+
+    /// Joins the chat group `group_id`, with an invite code if it takes one.
+    pub async fn join_group(&self, group_id: u64, invite_code: Option<&str>) -> Result<ChatGroup, ServiceMethodError> {
+        let mut request = CChatRoom_JoinChatRoomGroup_Request::new();
+        request.set_chat_group_id(group_id);
+        if let Some(invite_code) = invite_code {
+            request.set_invite_code(invite_code.to_string());
+        }
+
+        let response: CChatRoom_JoinChatRoomGroup_Response = self
+            .unified_messages
+            .call_service_method(JOIN_CHAT_ROOM_GROUP, request)
+            .await?;
+        Ok(ChatGroup::from_state(response.state.get_or_default()))
+    }
+
+    /// Leaves the chat group `group_id`.
+    pub async fn leave_group(&self, group_id: u64) -> Result<(), ServiceMethodError> {
+        let mut request = CChatRoom_LeaveChatRoomGroup_Request::new();
+        request.set_chat_group_id(group_id);
+
+        let _: CChatRoom_LeaveChatRoomGroup_Response = self
+            .unified_messages
+            .call_service_method(LEAVE_CHAT_ROOM_GROUP, request)
+            .await?;
+        Ok(())
+    }
+
+    /// Sends `message`, in BBCode, to the room `chat_id` of the group `group_id`.
+    pub async fn send_message(
+        &self,
+        group_id: u64,
+        chat_id: u64,
+        message: &str,
+    ) -> Result<SentMessage, ServiceMethodError> {
+        let mut request = CChatRoom_SendChatMessage_Request::new();
+        request.set_chat_group_id(group_id);
+        request.set_chat_id(chat_id);
+        request.set_message(message.to_string());
+        request.set_echo_to_sender(true);
+
+        let response: CChatRoom_SendChatMessage_Response = self
+            .unified_messages
+            .call_service_method(SEND_CHAT_MESSAGE, request)
+            .await?;
+        Ok(SentMessage {
+            message: response.modified_message().to_string(),
+            timestamp: response.server_timestamp(),
+            ordinal: response.ordinal(),
+        })
+    }
+
+    /// Up to `count` of the last messages sent to the room `chat_id` of the group `group_id`, newest first.
+    pub async fn message_history(
+        &self,
+        group_id: u64,
+        chat_id: u64,
+        count: u32,
+    ) -> Result<Vec<HistoryMessage>, ServiceMethodError> {
+        let mut request = CChatRoom_GetMessageHistory_Request::new();
+        request.set_chat_group_id(group_id);
+        request.set_chat_id(chat_id);
+        request.set_max_count(count);
+
+        let response: CChatRoom_GetMessageHistory_Response = self
+            .unified_messages
+            .call_service_method(GET_MESSAGE_HISTORY, request)
+            .await?;
+
+        let messages = response
+            .messages
+            .iter()
+            // messages from Steam itself, such as someone joining, have no text
+            .filter(|message| message.server_message.is_none())
+            .map(|message| HistoryMessage {
+                sender: SteamID::from_steam3(message.sender(), None, None),
+                message: message.message().to_string(),
+                timestamp: message.server_timestamp(),
+                ordinal: message.ordinal(),
+            })
+            .collect();
+        Ok(messages)
+    }
+}
+
+fn handle_msg(events: &EventBus, packet_message: PacketMessage) {
+    if let Some(incoming) =
+        notification::<CChatRoom_IncomingChatMessage_Notification>(&packet_message, INCOMING_CHAT_MESSAGE)
+    {
+        events.publish(ChatRoomMessage {
+            group_id: incoming.chat_group_id(),
+            chat_id: incoming.chat_id(),
+            sender: SteamID::from_steam64(incoming.steamid_sender()),
+            message: incoming.message().to_string(),
+            timestamp: incoming.timestamp(),
+            ordinal: incoming.ordinal(),
+        });
+    } else if let Some(change) =
+        notification::<CChatRoom_MemberStateChange_Notification>(&packet_message, MEMBER_STATE_CHANGE)
+    {
+        events.publish(ChatRoomMemberChanged {
+            group_id: change.chat_group_id(),
+            member: SteamID::from_steam3(change.member().accountid(), None, None),
+            change: change.change(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use steam_protobuf::protobufs::steammessages_chat_steamclient::cchat_room_get_message_history_response::ChatMessage;
+    use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoomMember;
+    use steam_protobuf::protobufs::steammessages_chat_steamclient::CChatRoomSummaryPair;
+    use steam_protobuf::protobufs::steammessages_chat_steamclient::ServerMessage;
+
+    use super::*;
+    use crate::connection::testing::notification_packet;
+    use crate::connection::testing::reply;
+    use crate::connection::testing::sent_call;
+
+    const GROUP_ID: u64 = 4242;
+    const CHAT_ID: u64 = 7;
+    const MEMBER: u64 = 76561197960287930;
+    const MEMBER_ACCOUNT_ID: u32 = 22202;
+
+    fn room_state(chat_id: u64, name: &str) -> CChatRoomState {
+        let mut room = CChatRoomState::new();
+        room.set_chat_id(chat_id);
+        room.set_chat_name(name.to_string());
+        room
+    }
+
+    #[tokio::test]
+    async fn notifications_are_events() {
+        let (connection, _sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let _chat_rooms = SteamChatRooms::new(connection.clone(), events.clone());
+        let mut messages = events.subscribe::<ChatRoomMessage>();
+        let mut members = events.subscribe::<ChatRoomMemberChanged>();
+
+        let mut member = CChatRoomMember::new();
+        member.set_accountid(MEMBER_ACCOUNT_ID);
+        let mut change = CChatRoom_MemberStateChange_Notification::new();
+        change.set_chat_group_id(GROUP_ID);
+        change.set_member(member);
+        change.set_change(EChatRoomMemberStateChange::k_EChatRoomMemberStateChange_Joined);
+        connection.inject(notification_packet(MEMBER_STATE_CHANGE, change));
+
+        let mut incoming = CChatRoom_IncomingChatMessage_Notification::new();
+        incoming.set_chat_group_id(GROUP_ID);
+        incoming.set_chat_id(CHAT_ID);
+        incoming.set_steamid_sender(MEMBER);
+        incoming.set_message("hello room".to_string());
+        connection.inject(notification_packet(INCOMING_CHAT_MESSAGE, incoming));
+
+        let joined = members.next().await.unwrap();
+        assert_eq!(joined.group_id, GROUP_ID);
+        assert_eq!(joined.member.to_steam64(), MEMBER);
+        assert_eq!(
+            joined.change,
+            EChatRoomMemberStateChange::k_EChatRoomMemberStateChange_Joined
+        );
+
+        let message = messages.next().await.unwrap();
+        assert_eq!((message.group_id, message.chat_id), (GROUP_ID, CHAT_ID));
+        assert_eq!(message.sender.to_steam64(), MEMBER);
+        assert_eq!(message.message, "hello room");
+    }
+
+    #[tokio::test]
+    async fn lists_our_chat_groups() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let chat_rooms = SteamChatRooms::new(connection.clone(), EventBus::new());
+
+        let groups = tokio::spawn({
+            let chat_rooms = chat_rooms.clone();
+            async move { chat_rooms.chat_groups().await }
+        });
+
+        let (job_id, _): (_, CChatRoom_GetMyChatRoomGroups_Request) =
+            sent_call(sent.recv().await.unwrap(), GET_MY_CHAT_ROOM_GROUPS);
+
+        let mut summary = CChatRoom_GetChatRoomGroupSummary_Response::new();
+        summary.set_chat_group_id(GROUP_ID);
+        summary.set_chat_group_name("Support".to_string());
+        summary.set_accountid_owner(MEMBER_ACCOUNT_ID);
+        summary.set_default_chat_id(CHAT_ID);
+        summary.chat_rooms.push(room_state(CHAT_ID, "General"));
+        let mut pair = CChatRoomSummaryPair::new();
+        pair.group_summary = Some(summary).into();
+        let mut response = CChatRoom_GetMyChatRoomGroups_Response::new();
+        response.chat_room_groups.push(pair);
+        connection.inject(reply(job_id, response));
+
+        let groups = groups.await.unwrap().unwrap();
+        assert_eq!(
+            groups,
+            vec![ChatGroup {
+                group_id: GROUP_ID,
+                name: "Support".to_string(),
+                owner: SteamID::from_steam64(MEMBER),
+                default_chat_id: CHAT_ID,
+                rooms: vec![ChatRoom {
+                    chat_id: CHAT_ID,
+                    name: "General".to_string(),
+                }],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn joins_and_chats() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let chat_rooms = SteamChatRooms::new(connection.clone(), EventBus::new());
+
+        let chat = tokio::spawn({
+            let chat_rooms = chat_rooms.clone();
+            async move {
+                let group = chat_rooms.join_group(GROUP_ID, Some("invite")).await?;
+                chat_rooms
+                    .send_message(group.group_id, group.default_chat_id, "hi all")
+                    .await
+            }
+        });
+
+        let (job_id, request): (_, CChatRoom_JoinChatRoomGroup_Request) =
+            sent_call(sent.recv().await.unwrap(), JOIN_CHAT_ROOM_GROUP);
+        assert_eq!(request.chat_group_id(), GROUP_ID);
+        assert_eq!(request.invite_code(), "invite");
+
+        let mut state = CChatRoomGroupState::new();
+        state.mut_header_state().set_chat_group_id(GROUP_ID);
+        state.set_default_chat_id(CHAT_ID);
+        state.chat_rooms.push(room_state(CHAT_ID, "General"));
+        let mut response = CChatRoom_JoinChatRoomGroup_Response::new();
+        response.state = Some(state).into();
+        connection.inject(reply(job_id, response));
+
+        let (job_id, request): (_, CChatRoom_SendChatMessage_Request) =
+            sent_call(sent.recv().await.unwrap(), SEND_CHAT_MESSAGE);
+        assert_eq!((request.chat_group_id(), request.chat_id()), (GROUP_ID, CHAT_ID));
+        assert_eq!(request.message(), "hi all");
+
+        let mut response = CChatRoom_SendChatMessage_Response::new();
+        response.set_modified_message("hi all".to_string());
+        response.set_ordinal(3);
+        connection.inject(reply(job_id, response));
+
+        let sent_message = chat.await.unwrap().unwrap();
+        assert_eq!(sent_message.ordinal, 3);
+    }
+
+    #[tokio::test]
+    async fn history_leaves_server_messages_out() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let chat_rooms = SteamChatRooms::new(connection.clone(), EventBus::new());
+
+        let history = tokio::spawn({
+            let chat_rooms = chat_rooms.clone();
+            async move { chat_rooms.message_history(GROUP_ID, CHAT_ID, 50).await }
+        });
+
+        let (job_id, request): (_, CChatRoom_GetMessageHistory_Request) =
+            sent_call(sent.recv().await.unwrap(), GET_MESSAGE_HISTORY);
+        assert_eq!(request.max_count(), 50);
+
+        let mut message = ChatMessage::new();
+        message.set_sender(MEMBER_ACCOUNT_ID);
+        message.set_message("welcome".to_string());
+        let mut joined = ChatMessage::new();
+        joined.server_message = Some(ServerMessage::new()).into();
+        let mut response = CChatRoom_GetMessageHistory_Response::new();
+        response.messages = vec![message, joined];
+        connection.inject(reply(job_id, response));
+
+        let history = history.await.unwrap().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].sender.to_steam64(), MEMBER);
+        assert_eq!(history[0].message, "welcome");
+    }
+}
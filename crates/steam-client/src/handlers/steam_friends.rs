@@ -0,0 +1,720 @@
+//! Friends list, relationships and personas of everyone we know on Steam.
+//!
+//! Steam sends the whole friends list right after logon, and then only what changes on it. Personas come in pieces:
+//! each [CMsgClientPersonaState] carries only the fields that changed, so they are merged into what we already know.
+//!
+//! Our own persona is set from here as well: online state, name and rich presence.
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Steam/Handlers/SteamFriends/SteamFriends.cs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use num::FromPrimitive;
+use steam_keyvalues::KeyValue;
+use steam_language_gen::generated::enums::EClientPersonaStateFlag;
+use steam_language_gen::generated::enums::EFriendRelationship;
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::enums::EPersonaState;
+use steam_language_gen::generated::enums::EResult;
+use steam_language_gen::generated::messages::MsgClientSetIgnoreFriend;
+use steam_language_gen::generated::messages::MsgClientSetIgnoreFriendResponse;
+use steam_language_gen::MessageHeaderWrapper;
+use steam_protobuf::protobufs::steammessages_clientserver_2::CMsgClientRichPresenceUpload;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::cmsg_client_persona_state;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::CMsgClientAddFriend;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::CMsgClientAddFriendResponse;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::CMsgClientChangeStatus;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::CMsgClientFriendsList;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::CMsgClientPersonaState;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::CMsgClientRemoveFriend;
+use steam_protobuf::protobufs::steammessages_clientserver_friends::CMsgClientRequestFriendData;
+use steamid_parser::SteamID;
+
+use crate::connection::ConnectionHandle;
+use crate::errors::ConnectionError;
+use crate::errors::FriendsError;
+use crate::errors::PacketError;
+use crate::events::Event;
+use crate::events::EventBus;
+use crate::handlers::forward_packets;
+use crate::messages::message::ClientMessage;
+use crate::messages::packet::PacketMessage;
+use crate::utils::eresult_from_i32;
+
+/// Where Steam serves avatars from, by the hex of their hash.
+const AVATAR_URL: &str = "https://avatars.akamai.steamstatic.com";
+
+/// Name of the root of the rich presence KeyValues.
+const RICH_PRESENCE_ROOT: &str = "RP";
+
+/// Avatar hash of accounts that never set one.
+const DEFAULT_AVATAR_HASH: [u8; 20] = [0; 20];
+
+/// Someone we have a relationship with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Friend {
+    /// Their SteamID.
+    pub steam_id: SteamID,
+    /// Whether they are a friend, asked to be one, or are blocked.
+    pub relationship: EFriendRelationship,
+}
+
+/// Game someone is playing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GamePlayed {
+    /// App being played, zero for non-Steam games.
+    pub app_id: u32,
+    /// Full game id, which also identifies mods and non-Steam games.
+    pub game_id: u64,
+    /// Name of the game, only sent for non-Steam games.
+    pub name: String,
+}
+
+/// Public face of a Steam account, as much of it as Steam told us.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Persona {
+    /// Whose persona this is.
+    pub steam_id: SteamID,
+    /// Profile name.
+    pub name: String,
+    /// Online state, `Offline` until Steam tells otherwise.
+    pub state: EPersonaState,
+    /// SHA-1 of the avatar, empty if unknown.
+    pub avatar_hash: Vec<u8>,
+    /// Game being played, if any.
+    pub game_played: Option<GamePlayed>,
+    /// Last time they logged on, as an unix timestamp.
+    pub last_logon: u32,
+    /// Last time they logged off, as an unix timestamp.
+    pub last_logoff: u32,
+}
+
+impl Persona {
+    fn new(steam_id: u64) -> Self {
+        Self {
+            steam_id: SteamID::from_steam64(steam_id),
+            name: String::new(),
+            state: EPersonaState::Offline,
+            avatar_hash: vec![],
+            game_played: None,
+            last_logon: 0,
+            last_logoff: 0,
+        }
+    }
+
+    /// Whether they are online, whatever their state.
+    pub fn is_online(&self) -> bool {
+        self.state != EPersonaState::Offline
+    }
+
+    /// Link to the full size avatar, or `None` if they never set one.
+    pub fn avatar_url(&self) -> Option<String> {
+        if self.avatar_hash.is_empty() || self.avatar_hash == DEFAULT_AVATAR_HASH {
+            return None;
+        }
+
+        let hash: String = self.avatar_hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Some(format!("{}/{}_full.jpg", AVATAR_URL, hash))
+    }
+
+    /// Overwrites whatever fields Steam sent this time.
+    fn update(&mut self, friend: &cmsg_client_persona_state::Friend) {
+        if friend.has_player_name() {
+            self.name = friend.player_name().to_string();
+        }
+        if friend.has_persona_state() {
+            self.state = EPersonaState::from_u32(friend.persona_state()).unwrap_or(EPersonaState::Offline);
+        }
+        if friend.has_avatar_hash() {
+            self.avatar_hash = friend.avatar_hash().to_vec();
+        }
+        if friend.has_gameid() || friend.has_game_played_app_id() {
+            self.game_played = match (friend.game_played_app_id(), friend.gameid()) {
+                (0, 0) => None,
+                (app_id, game_id) => Some(GamePlayed {
+                    app_id,
+                    game_id,
+                    name: friend.game_name().to_string(),
+                }),
+            };
+        }
+        if friend.has_last_logon() {
+            self.last_logon = friend.last_logon();
+        }
+        if friend.has_last_logoff() {
+            self.last_logoff = friend.last_logoff();
+        }
+    }
+}
+
+/// Steam sent the friends list, either whole or only what changed on it.
+#[derive(Debug, Clone)]
+pub struct FriendsList {
+    /// Whether only the changes were sent, or the whole list, replacing the one we had.
+    pub incremental: bool,
+    /// Everyone on the list this time.
+    pub friends: Vec<Friend>,
+}
+
+/// Our relationship with someone changed after logon, such as them asking to be our friend.
+#[derive(Debug, Clone)]
+pub struct RelationshipChanged {
+    /// Whose relationship changed.
+    pub steam_id: SteamID,
+    /// What it was before, `None` if we had none.
+    pub previous: EFriendRelationship,
+    /// What it is now, `None` if they are gone from the list.
+    pub relationship: EFriendRelationship,
+}
+
+/// Steam sent news about a persona, already merged with what we knew about it.
+#[derive(Debug, Clone)]
+pub struct PersonaStateChanged {
+    /// The persona, as we now know it.
+    pub persona: Persona,
+}
+
+impl Event for FriendsList {}
+impl Event for RelationshipChanged {}
+impl Event for PersonaStateChanged {}
+
+/// What we know about everyone, keyed by their SteamID64.
+#[derive(Debug, Default)]
+struct Roster {
+    relationships: HashMap<u64, EFriendRelationship>,
+    personas: HashMap<u64, Persona>,
+}
+
+/// Keeps the friends list and personas, and manages relationships.
+///
+/// Publishes [FriendsList], [RelationshipChanged] and [PersonaStateChanged] on the [EventBus].
+#[derive(Debug, Clone)]
+pub struct SteamFriends {
+    connection: ConnectionHandle,
+    roster: Arc<Mutex<Roster>>,
+    /// Last state we set, sent again along with name changes so they do not take us offline.
+    persona_state: Arc<Mutex<EPersonaState>>,
+}
+
+impl SteamFriends {
+    pub(crate) fn new(connection: ConnectionHandle, events: EventBus) -> Self {
+        let roster = Arc::new(Mutex::new(Roster::default()));
+        let handled_roster = roster.clone();
+        tokio::spawn(forward_packets(
+            "SteamFriends",
+            connection.clone(),
+            connection.subscribe(),
+            move |packet_message| handle_msg(&events, &handled_roster, packet_message),
+        ));
+        Self {
+            connection,
+            roster,
+            persona_state: Arc::new(Mutex::new(EPersonaState::Online)),
+        }
+    }
+
+    /// Everyone we have a relationship with, including pending requests and blocked accounts.
+    pub fn friends(&self) -> Vec<Friend> {
+        let roster = self.roster.lock().unwrap();
+        roster
+            .relationships
+            .iter()
+            .map(|(&steam_id, &relationship)| Friend {
+                steam_id: SteamID::from_steam64(steam_id),
+                relationship,
+            })
+            .collect()
+    }
+
+    /// Our relationship with `steam_id`, `None` if we have none.
+    pub fn relationship(&self, steam_id: &SteamID) -> EFriendRelationship {
+        let roster = self.roster.lock().unwrap();
+        roster
+            .relationships
+            .get(&steam_id.to_steam64())
+            .copied()
+            .unwrap_or(EFriendRelationship::None)
+    }
+
+    /// What we know about the persona of `steam_id`, if Steam told us anything.
+    ///
+    /// Steam sends the personas of friends on its own, others must be asked for with
+    /// [SteamFriends::request_persona_info].
+    pub fn persona(&self, steam_id: &SteamID) -> Option<Persona> {
+        let roster = self.roster.lock().unwrap();
+        roster.personas.get(&steam_id.to_steam64()).cloned()
+    }
+
+    /// Sends a friend request to `steam_id`, or accepts theirs, and waits for Steam to answer.
+    pub async fn add_friend(&self, steam_id: &SteamID) -> Result<(), FriendsError> {
+        let mut message = ClientMessage::<CMsgClientAddFriend>::new_proto(EMsg::ClientAddFriend);
+        message.body.set_steamid_to_add(steam_id.to_steam64());
+
+        let reply = self.connection.send_job(message).await?;
+        let response = ClientMessage::<CMsgClientAddFriendResponse>::from_proto_packet(reply)
+            .map_err(ConnectionError::PacketError)?;
+
+        match eresult_from_i32(response.body.eresult()) {
+            EResult::OK => Ok(()),
+            result => Err(FriendsError::Refused(result)),
+        }
+    }
+
+    /// Removes `steam_id` from the friends list, which also declines or cancels a friend request.
+    pub fn remove_friend(&self, steam_id: &SteamID) -> Result<(), ConnectionError> {
+        let mut message = ClientMessage::<CMsgClientRemoveFriend>::new_proto(EMsg::ClientRemoveFriend);
+        message.body.set_friendid(steam_id.to_steam64());
+        self.connection.send(message)
+    }
+
+    /// Blocks all communication from `steam_id`, and waits for Steam to answer.
+    pub async fn block(&self, steam_id: &SteamID) -> Result<(), FriendsError> {
+        self.set_ignored(steam_id, true).await
+    }
+
+    /// Lifts a block on `steam_id`, and waits for Steam to answer.
+    pub async fn unblock(&self, steam_id: &SteamID) -> Result<(), FriendsError> {
+        self.set_ignored(steam_id, false).await
+    }
+
+    async fn set_ignored(&self, steam_id: &SteamID, ignore: bool) -> Result<(), FriendsError> {
+        let mut message = ClientMessage::<MsgClientSetIgnoreFriend>::new();
+        message.body.my_steam_id = self.connection.session().steam_id();
+        message.body.steam_id_friend = steam_id.to_steam64();
+        message.body.ignore = ignore as u8;
+
+        let reply = self.connection.send_job(message).await?;
+        if reply.emsg() != EMsg::ClientSetIgnoreFriendResponse {
+            return Err(ConnectionError::PacketError(PacketError::Malformed).into());
+        }
+
+        let response = ClientMessage::<MsgClientSetIgnoreFriendResponse>::from_packet_message(reply);
+        match response.body.result {
+            EResult::OK => Ok(()),
+            result => Err(FriendsError::Refused(result)),
+        }
+    }
+
+    /// Asks Steam for the personas of `steam_ids`, which come back as [PersonaStateChanged] events.
+    pub fn request_persona_info(&self, steam_ids: &[SteamID]) -> Result<(), ConnectionError> {
+        let requested = EClientPersonaStateFlag::Status
+            | EClientPersonaStateFlag::PlayerName
+            | EClientPersonaStateFlag::Presence
+            | EClientPersonaStateFlag::LastSeen
+            | EClientPersonaStateFlag::GameExtraInfo;
+
+        let mut message = ClientMessage::<CMsgClientRequestFriendData>::new_proto(EMsg::ClientRequestFriendData);
+        message.body.set_persona_state_requested(requested.bits() as u32);
+        message.body.friends = steam_ids.iter().map(SteamID::to_steam64).collect();
+        self.connection.send(message)
+    }
+
+    /// Changes our online state, such as going `Away`, or `Invisible` to appear offline while still logged on.
+    pub fn set_persona_state(&self, state: EPersonaState) -> Result<(), ConnectionError> {
+        let mut message = ClientMessage::<CMsgClientChangeStatus>::new_proto(EMsg::ClientChangeStatus);
+        message.body.set_persona_state(state as u32);
+        message.body.set_persona_set_by_user(true);
+        self.connection.send(message)?;
+        *self.persona_state.lock().unwrap() = state;
+        Ok(())
+    }
+
+    /// Changes our profile name, keeping the state last set through [SteamFriends::set_persona_state], `Online` if
+    /// none was.
+    pub fn set_persona_name(&self, name: &str) -> Result<(), ConnectionError> {
+        let state = *self.persona_state.lock().unwrap();
+        let mut message = ClientMessage::<CMsgClientChangeStatus>::new_proto(EMsg::ClientChangeStatus);
+        message.body.set_persona_state(state as u32);
+        message.body.set_player_name(name.to_string());
+        self.connection.send(message)
+    }
+
+    /// Replaces our rich presence for `app_id` with `rich_presence`, such as `("status", "In a match")`.
+    ///
+    /// Steam only shows it while we are playing `app_id`. An empty list clears it.
+    pub fn set_rich_presence(&self, app_id: u32, rich_presence: &[(&str, &str)]) -> Result<(), ConnectionError> {
+        let mut message = ClientMessage::<CMsgClientRichPresenceUpload>::new_proto(EMsg::ClientRichPresenceUpload);
+        if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+            header.set_routing_appid(app_id);
+        }
+        message
+            .body
+            .set_rich_presence_kv(rich_presence_kv(rich_presence).into());
+        self.connection.send(message)
+    }
+}
+
+/// Encodes rich presence as binary KeyValues, every pair being a string under the `RP` root.
+fn rich_presence_kv(rich_presence: &[(&str, &str)]) -> Vec<u8> {
+    let mut root = KeyValue::section();
+    for (key, value) in rich_presence {
+        root.push(*key, KeyValue::from(*value));
+    }
+    steam_keyvalues::to_binary(RICH_PRESENCE_ROOT, &root).expect("the root is a section")
+}
+
+fn handle_msg(events: &EventBus, roster: &Mutex<Roster>, packet_message: PacketMessage) {
+    match packet_message.emsg() {
+        EMsg::ClientFriendsList => {
+            let message = match ClientMessage::<CMsgClientFriendsList>::from_proto_packet(packet_message) {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+            handle_friends_list(events, roster, message.body);
+        }
+        EMsg::ClientPersonaState => {
+            let message = match ClientMessage::<CMsgClientPersonaState>::from_proto_packet(packet_message) {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+            handle_persona_state(events, roster, message.body);
+        }
+        _ => {}
+    }
+}
+
+fn handle_friends_list(events: &EventBus, roster: &Mutex<Roster>, list: CMsgClientFriendsList) {
+    let incremental = list.bincremental();
+    let friends: Vec<Friend> = list
+        .friends
+        .iter()
+        .map(|friend| Friend {
+            steam_id: SteamID::from_steam64(friend.ulfriendid()),
+            relationship: EFriendRelationship::from_u32(friend.efriendrelationship())
+                .unwrap_or(EFriendRelationship::None),
+        })
+        .collect();
+
+    let mut changes = vec![];
+    {
+        let mut roster = roster.lock().unwrap();
+        if !incremental {
+            roster.relationships.clear();
+        }
+
+        for friend in &friends {
+            let steam_id = friend.steam_id.to_steam64();
+            let previous = match friend.relationship {
+                EFriendRelationship::None => roster.relationships.remove(&steam_id),
+                relationship => roster.relationships.insert(steam_id, relationship),
+            }
+            .unwrap_or(EFriendRelationship::None);
+
+            if incremental && previous != friend.relationship {
+                changes.push(RelationshipChanged {
+                    steam_id: friend.steam_id.clone(),
+                    previous,
+                    relationship: friend.relationship,
+                });
+            }
+        }
+    }
+
+    debug!(
+        "SteamFriends got {} friends, incremental: {}.",
+        friends.len(),
+        incremental
+    );
+    events.publish(FriendsList { incremental, friends });
+    for change in changes {
+        events.publish(change);
+    }
+}
+
+fn handle_persona_state(events: &EventBus, roster: &Mutex<Roster>, state: CMsgClientPersonaState) {
+    for friend in &state.friends {
+        let persona = {
+            let mut roster = roster.lock().unwrap();
+            let persona = roster
+                .personas
+                .entry(friend.friendid())
+                .or_insert_with(|| Persona::new(friend.friendid()));
+            persona.update(friend);
+            persona.clone()
+        };
+
+        trace!("SteamFriends persona: {:?}", persona);
+        events.publish(PersonaStateChanged { persona });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use steam_language_gen::generated::headers::ExtendedMessageHeader;
+    use steam_language_gen::HasJobId;
+    use steam_language_gen::SerializableBytes;
+    use steam_protobuf::protobufs::steammessages_clientserver_friends::cmsg_client_friends_list;
+
+    use super::*;
+    use crate::connection::testing::job_reply;
+    use crate::connection::testing::proto_packet;
+    use crate::connection::testing::sent_packet;
+
+    const FRIEND: u64 = 76561197960287930;
+    const STRANGER: u64 = 76561197960265731;
+
+    fn friends_list(incremental: bool, friends: &[(u64, EFriendRelationship)]) -> PacketMessage {
+        let mut list = CMsgClientFriendsList::new();
+        list.set_bincremental(incremental);
+        list.friends = friends
+            .iter()
+            .map(|&(steam_id, relationship)| {
+                let mut friend = cmsg_client_friends_list::Friend::new();
+                friend.set_ulfriendid(steam_id);
+                friend.set_efriendrelationship(relationship as u32);
+                friend
+            })
+            .collect();
+        proto_packet(EMsg::ClientFriendsList, list)
+    }
+
+    fn persona_state(friend: cmsg_client_persona_state::Friend) -> PacketMessage {
+        let mut state = CMsgClientPersonaState::new();
+        state.friends.push(friend);
+        proto_packet(EMsg::ClientPersonaState, state)
+    }
+
+    #[tokio::test]
+    async fn whole_list_replaces_the_roster() {
+        let (connection, _sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let steam_friends = SteamFriends::new(connection.clone(), events.clone());
+        let mut lists = events.subscribe::<FriendsList>();
+
+        connection.inject(friends_list(
+            false,
+            &[
+                (FRIEND, EFriendRelationship::Friend),
+                (STRANGER, EFriendRelationship::Blocked),
+            ],
+        ));
+        assert_eq!(lists.next().await.unwrap().friends.len(), 2);
+        assert_eq!(steam_friends.friends().len(), 2);
+
+        connection.inject(friends_list(false, &[(FRIEND, EFriendRelationship::Friend)]));
+        assert!(!lists.next().await.unwrap().incremental);
+        assert_eq!(
+            steam_friends.friends(),
+            vec![Friend {
+                steam_id: SteamID::from_steam64(FRIEND),
+                relationship: EFriendRelationship::Friend,
+            }]
+        );
+        assert_eq!(
+            steam_friends.relationship(&SteamID::from_steam64(STRANGER)),
+            EFriendRelationship::None
+        );
+    }
+
+    #[tokio::test]
+    async fn friend_requests_are_relationship_changes() {
+        let (connection, _sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let steam_friends = SteamFriends::new(connection.clone(), events.clone());
+        let mut changes = events.subscribe::<RelationshipChanged>();
+
+        connection.inject(friends_list(false, &[(FRIEND, EFriendRelationship::Friend)]));
+        connection.inject(friends_list(true, &[(STRANGER, EFriendRelationship::RequestRecipient)]));
+        connection.inject(friends_list(true, &[(FRIEND, EFriendRelationship::None)]));
+
+        // the whole list after logon is not a change
+        let request = changes.next().await.unwrap();
+        assert_eq!(request.steam_id.to_steam64(), STRANGER);
+        assert_eq!(request.previous, EFriendRelationship::None);
+        assert_eq!(request.relationship, EFriendRelationship::RequestRecipient);
+
+        let removed = changes.next().await.unwrap();
+        assert_eq!(removed.steam_id.to_steam64(), FRIEND);
+        assert_eq!(removed.previous, EFriendRelationship::Friend);
+        assert_eq!(removed.relationship, EFriendRelationship::None);
+
+        assert_eq!(
+            steam_friends.friends(),
+            vec![Friend {
+                steam_id: SteamID::from_steam64(STRANGER),
+                relationship: EFriendRelationship::RequestRecipient,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn persona_updates_are_merged() {
+        let (connection, _sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let steam_friends = SteamFriends::new(connection.clone(), events.clone());
+        let mut personas = events.subscribe::<PersonaStateChanged>();
+
+        let mut friend = cmsg_client_persona_state::Friend::new();
+        friend.set_friendid(FRIEND);
+        friend.set_player_name("gabe".to_string());
+        friend.set_persona_state(EPersonaState::Online as u32);
+        friend.set_avatar_hash(vec![0xab; 20].into());
+        friend.set_game_played_app_id(440);
+        friend.set_gameid(440);
+        connection.inject(persona_state(friend));
+        assert_eq!(personas.next().await.unwrap().persona.name, "gabe");
+
+        // only the state changed
+        let mut friend = cmsg_client_persona_state::Friend::new();
+        friend.set_friendid(FRIEND);
+        friend.set_persona_state(EPersonaState::Away as u32);
+        connection.inject(persona_state(friend));
+        let persona = personas.next().await.unwrap().persona;
+
+        assert_eq!(persona.name, "gabe");
+        assert_eq!(persona.state, EPersonaState::Away);
+        assert!(persona.is_online());
+        assert_eq!(persona.game_played.as_ref().unwrap().app_id, 440);
+        assert_eq!(
+            persona.avatar_url().unwrap(),
+            format!("{}/{}_full.jpg", AVATAR_URL, "ab".repeat(20))
+        );
+        assert_eq!(
+            steam_friends.persona(&SteamID::from_steam64(FRIEND)).unwrap().state,
+            EPersonaState::Away
+        );
+        assert!(steam_friends.persona(&SteamID::from_steam64(STRANGER)).is_none());
+    }
+
+    #[test]
+    fn default_avatar_has_no_url() {
+        let mut persona = Persona::new(FRIEND);
+        assert_eq!(persona.avatar_url(), None);
+
+        persona.avatar_hash = DEFAULT_AVATAR_HASH.to_vec();
+        assert_eq!(persona.avatar_url(), None);
+    }
+
+    #[tokio::test]
+    async fn refused_friend_request_is_an_error() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let steam_friends = SteamFriends::new(connection.clone(), EventBus::new());
+
+        let add = tokio::spawn({
+            let steam_friends = steam_friends.clone();
+            async move { steam_friends.add_friend(&SteamID::from_steam64(STRANGER)).await }
+        });
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(request.emsg(), EMsg::ClientAddFriend);
+        let body = ClientMessage::<CMsgClientAddFriend>::from_proto_packet(request.clone()).unwrap();
+        assert_eq!(body.body.steamid_to_add(), STRANGER);
+
+        let mut response = CMsgClientAddFriendResponse::new();
+        response.set_eresult(EResult::Blocked as i32);
+        connection.inject(job_reply(EMsg::ClientAddFriendResponse, request.jobs_ids().0, response));
+
+        assert!(matches!(
+            add.await.unwrap(),
+            Err(FriendsError::Refused(EResult::Blocked))
+        ));
+    }
+
+    #[tokio::test]
+    async fn blocks_through_set_ignore_friend() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let steam_friends = SteamFriends::new(connection.clone(), EventBus::new());
+
+        let block = tokio::spawn({
+            let steam_friends = steam_friends.clone();
+            async move { steam_friends.block(&SteamID::from_steam64(STRANGER)).await }
+        });
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(request.emsg(), EMsg::ClientSetIgnoreFriend);
+        let body = ClientMessage::<MsgClientSetIgnoreFriend>::from_packet_message(request.clone()).body;
+        assert_eq!({ body.steam_id_friend }, STRANGER);
+        assert_eq!({ body.ignore }, 1);
+
+        let mut header = ExtendedMessageHeader::new();
+        header.set_target(request.jobs_ids().0);
+        let mut response = MsgClientSetIgnoreFriendResponse::new();
+        response.friend_id = STRANGER;
+        response.result = EResult::OK;
+        connection.inject(PacketMessage::new(
+            EMsg::ClientSetIgnoreFriendResponse,
+            MessageHeaderWrapper::Ext(header),
+            response.to_bytes(),
+        ));
+
+        block.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_personas_of_strangers() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let steam_friends = SteamFriends::new(connection, EventBus::new());
+
+        steam_friends
+            .request_persona_info(&[SteamID::from_steam64(STRANGER)])
+            .unwrap();
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(request.emsg(), EMsg::ClientRequestFriendData);
+        let body = ClientMessage::<CMsgClientRequestFriendData>::from_proto_packet(request).unwrap();
+        assert_eq!(body.body.friends, vec![STRANGER]);
+        assert_ne!(
+            body.body.persona_state_requested() & EClientPersonaStateFlag::PlayerName.bits() as u32,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn changes_our_persona() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let steam_friends = SteamFriends::new(connection, EventBus::new());
+
+        steam_friends.set_persona_state(EPersonaState::Away).unwrap();
+        steam_friends.set_persona_name("support bot").unwrap();
+
+        let status = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(status.emsg(), EMsg::ClientChangeStatus);
+        let status = status.decode::<CMsgClientChangeStatus>().unwrap();
+        assert_eq!(status.persona_state(), EPersonaState::Away as u32);
+        assert!(!status.has_player_name());
+
+        let name = sent_packet(sent.recv().await.unwrap());
+        let name = name.decode::<CMsgClientChangeStatus>().unwrap();
+        assert_eq!(name.player_name(), "support bot");
+        assert_eq!(name.persona_state(), EPersonaState::Away as u32);
+
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let fresh = SteamFriends::new(connection, EventBus::new());
+        fresh.set_persona_name("fresh bot").unwrap();
+        let name = sent_packet(sent.recv().await.unwrap());
+        let name = name.decode::<CMsgClientChangeStatus>().unwrap();
+        assert_eq!(name.persona_state(), EPersonaState::Online as u32);
+    }
+
+    #[test]
+    fn rich_presence_is_binary_key_values() {
+        assert_eq!(rich_presence_kv(&[]), b"\0RP\0\x08\x08".to_vec());
+        assert_eq!(
+            rich_presence_kv(&[("status", "Idle")]),
+            b"\0RP\0\x01status\0Idle\0\x08\x08".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn rich_presence_is_routed_to_its_app() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let steam_friends = SteamFriends::new(connection, EventBus::new());
+
+        steam_friends.set_rich_presence(440, &[("status", "Idle")]).unwrap();
+
+        let upload = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(upload.emsg(), EMsg::ClientRichPresenceUpload);
+        match upload.header() {
+            MessageHeaderWrapper::Proto(header) => assert_eq!(header.routing_appid(), 440),
+            header => panic!("expected a protobuf header, got {:?}", header),
+        }
+        let upload = upload.decode::<CMsgClientRichPresenceUpload>().unwrap();
+        assert_eq!(
+            upload.rich_presence_kv(),
+            rich_presence_kv(&[("status", "Idle")]).as_slice()
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Messages to and from Game Coordinators, the servers behind each game's items, matchmaking and the like.
+//!
+//! Steam only relays them: each GC message travels inside a [CMsgGCClient], as `ClientToGC` or `ClientFromGC`, next
+//! to the app id of its GC. GCs use message types of their own, defined by each game's protobufs, and flag protobuf
+//! messages the same way Steam does with its [EMsg]s.
+//!
+//! A GC only talks to clients playing its game, so [SteamUser::games_played] must be called before sending anything.
+//!
+//! [SteamUser::games_played]: crate::handlers::steam_user::SteamUser::games_played
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Steam/Handlers/SteamGameCoordinator/SteamGameCoordinator.cs
+
+use bytes::Bytes;
+use futures::StreamExt;
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::PROTOMASK;
+use steam_language_gen::MessageHeaderWrapper;
+use steam_protobuf::protobufs::steammessages_base::CMsgProtoBufHeader;
+use steam_protobuf::protobufs::steammessages_clientserver_2::CMsgGCClient;
+use steam_protobuf::Message;
+
+use crate::connection::ConnectionHandle;
+use crate::errors::ConnectionError;
+use crate::errors::GameCoordinatorError;
+use crate::errors::PacketError;
+use crate::events::Event;
+use crate::events::EventBus;
+use crate::events::EventStream;
+use crate::handlers::forward_packets;
+use crate::messages::message::ClientMessage;
+use crate::messages::packet::PacketMessage;
+
+/// Version of the header of messages that are not protobufs.
+const GC_HEADER_VERSION: u16 = 1;
+/// Header version, then target and source job ids.
+const GC_HEADER_SIZE: usize = 18;
+/// Message type, then the length of the protobuf header.
+const GC_PROTO_HEADER_SIZE: usize = 8;
+const NO_JOB: u64 = u64::MAX;
+
+/// A message from the GC of an app.
+#[derive(Debug, Clone)]
+pub struct GcMessage {
+    /// App whose GC sent the message.
+    pub app_id: u32,
+    /// Message type, as defined by the game, without the protobuf flag.
+    pub msg_type: u32,
+    /// Whether the body is a protobuf, or a struct of the game's own.
+    pub is_proto: bool,
+    /// Job the GC expects a reply on, if any.
+    pub source_job_id: u64,
+    /// Job of ours this message replies to, if any.
+    pub target_job_id: u64,
+    /// The message itself, after its header.
+    pub body: Bytes,
+}
+
+impl Event for GcMessage {}
+
+impl GcMessage {
+    /// Decodes the body of a protobuf message.
+    pub fn decode<M: Message>(&self) -> Result<M, PacketError> {
+        M::parse_from_bytes(&self.body).map_err(|_| PacketError::Malformed)
+    }
+
+    /// Reads a message out of the payload of a [CMsgGCClient].
+    fn from_gc_client(gc_client: &CMsgGCClient) -> Result<Self, PacketError> {
+        let is_proto = gc_client.msgtype() & PROTOMASK != 0;
+        let msg_type = gc_client.msgtype() & !PROTOMASK;
+        let payload = gc_client.payload.clone().unwrap_or_default();
+
+        let (source_job_id, target_job_id, body_start) = if is_proto {
+            if payload.len() < GC_PROTO_HEADER_SIZE {
+                return Err(PacketError::Malformed);
+            }
+            let header_len = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]) as usize;
+            let header = payload
+                .get(GC_PROTO_HEADER_SIZE..GC_PROTO_HEADER_SIZE + header_len)
+                .ok_or(PacketError::Malformed)?;
+            let header = CMsgProtoBufHeader::parse_from_bytes(header).map_err(|_| PacketError::Malformed)?;
+            (
+                header.jobid_source(),
+                header.jobid_target(),
+                GC_PROTO_HEADER_SIZE + header_len,
+            )
+        } else {
+            if payload.len() < GC_HEADER_SIZE {
+                return Err(PacketError::Malformed);
+            }
+            let job_id = |at: usize| {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&payload[at..at + 8]);
+                u64::from_le_bytes(bytes)
+            };
+            (job_id(10), job_id(2), GC_HEADER_SIZE)
+        };
+
+        Ok(Self {
+            app_id: gc_client.appid(),
+            msg_type,
+            is_proto,
+            source_job_id,
+            target_job_id,
+            body: payload.slice(body_start..),
+        })
+    }
+}
+
+/// Talks to the Game Coordinators of any app.
+///
+/// Publishes [GcMessage] on the [EventBus].
+#[derive(Debug, Clone)]
+pub struct SteamGameCoordinator {
+    connection: ConnectionHandle,
+    events: EventBus,
+}
+
+impl SteamGameCoordinator {
+    pub(crate) fn new(connection: ConnectionHandle, events: EventBus) -> Self {
+        let published = events.clone();
+        tokio::spawn(forward_packets(
+            "SteamGameCoordinator",
+            connection.clone(),
+            connection.subscribe(),
+            move |packet_message| handle_msg(&published, packet_message),
+        ));
+        Self { connection, events }
+    }
+
+    /// Sends a protobuf message of type `msg_type` to the GC of `app_id`.
+    pub fn send<M: Message>(&self, app_id: u32, msg_type: u32, body: &M) -> Result<(), GameCoordinatorError> {
+        let body = body
+            .write_to_bytes()
+            .map_err(|err| GameCoordinatorError::Encode(msg_type, err.to_string()))?;
+        let header = CMsgProtoBufHeader::new()
+            .write_to_bytes()
+            .map_err(|err| GameCoordinatorError::Encode(msg_type, err.to_string()))?;
+
+        let msg_type = msg_type | PROTOMASK;
+        let mut payload = Vec::with_capacity(GC_PROTO_HEADER_SIZE + header.len() + body.len());
+        payload.extend_from_slice(&msg_type.to_le_bytes());
+        payload.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&header);
+        payload.extend_from_slice(&body);
+
+        self.send_payload(app_id, msg_type, payload)?;
+        Ok(())
+    }
+
+    /// Sends a message of type `msg_type` that is not a protobuf, such as the older messages of some games, to the
+    /// GC of `app_id`. The header is added here, `body` is only the struct after it.
+    pub fn send_raw(&self, app_id: u32, msg_type: u32, body: &[u8]) -> Result<(), GameCoordinatorError> {
+        let mut payload = Vec::with_capacity(GC_HEADER_SIZE + body.len());
+        payload.extend_from_slice(&GC_HEADER_VERSION.to_le_bytes());
+        payload.extend_from_slice(&NO_JOB.to_le_bytes());
+        payload.extend_from_slice(&NO_JOB.to_le_bytes());
+        payload.extend_from_slice(body);
+
+        self.send_payload(app_id, msg_type, payload)?;
+        Ok(())
+    }
+
+    fn send_payload(&self, app_id: u32, msg_type: u32, payload: Vec<u8>) -> Result<(), ConnectionError> {
+        let mut message = ClientMessage::<CMsgGCClient>::new_proto(EMsg::ClientToGC);
+        if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+            header.set_routing_appid(app_id);
+        }
+        message.body.set_appid(app_id);
+        message.body.set_msgtype(msg_type);
+        message.body.set_payload(payload.into());
+        self.connection.send(message)
+    }
+
+    /// Subscribes to every message the GC of `app_id` sends from this point on.
+    pub fn subscribe_app(&self, app_id: u32) -> EventStream<GcMessage> {
+        self.events
+            .subscribe::<GcMessage>()
+            .filter(move |message| futures::future::ready(message.app_id == app_id))
+            .boxed()
+    }
+
+    /// Subscribes to the protobuf messages of type `msg_type` the GC of `app_id` sends from this point on, decoded as
+    /// `M`, one of the game's own protobufs.
+    ///
+    /// Messages that do not decode as `M` are skipped.
+    pub fn subscribe<M: Message>(&self, app_id: u32, msg_type: u32) -> EventStream<M> {
+        self.subscribe_app(app_id)
+            .filter_map(move |message| {
+                let decoded = if message.msg_type == msg_type && message.is_proto {
+                    message
+                        .decode::<M>()
+                        .map_err(|_| warn!("Could not decode GC message {} of app {}.", msg_type, app_id))
+                        .ok()
+                } else {
+                    None
+                };
+                futures::future::ready(decoded)
+            })
+            .boxed()
+    }
+}
+
+fn handle_msg(events: &EventBus, packet_message: PacketMessage) {
+    if packet_message.emsg() != EMsg::ClientFromGC {
+        return;
+    }
+
+    let message = packet_message
+        .decode::<CMsgGCClient>()
+        .and_then(|gc_client| GcMessage::from_gc_client(&gc_client));
+    match message {
+        Ok(message) => events.publish(message),
+        Err(err) => warn!("Dropped a malformed message from a GC: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use steam_protobuf::protobufs::steammessages_clientserver_2::CMsgClientRichPresenceUpload;
+
+    use super::*;
+    use crate::connection::testing::proto_packet;
+    use crate::connection::testing::sent_packet;
+    use crate::connection::DynBytes;
+
+    const CS2: u32 = 730;
+    /// `k_EMsgGCCStrike15_v2_Client2GCEconPreviewDataBlockRequest`
+    const PREVIEW_REQUEST: u32 = 9156;
+
+    fn from_gc(app_id: u32, msg_type: u32, payload: Vec<u8>) -> PacketMessage {
+        let mut gc_client = CMsgGCClient::new();
+        gc_client.set_appid(app_id);
+        gc_client.set_msgtype(msg_type);
+        gc_client.set_payload(payload.into());
+        proto_packet(EMsg::ClientFromGC, gc_client)
+    }
+
+    /// Payload of a protobuf message from a GC, replying to job 42.
+    fn proto_payload(msg_type: u32, text: &str) -> Vec<u8> {
+        let mut header = CMsgProtoBufHeader::new();
+        header.set_jobid_target(42);
+        let header = header.write_to_bytes().unwrap();
+
+        let mut payload = (msg_type | PROTOMASK).to_le_bytes().to_vec();
+        payload.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&header);
+        payload.extend_from_slice(&body(text).write_to_bytes().unwrap());
+        payload
+    }
+
+    /// Reads a `ClientToGC` back into what the GC would see.
+    fn to_gc(sent: DynBytes) -> GcMessage {
+        let packet_message = sent_packet(sent);
+        assert_eq!(packet_message.emsg(), EMsg::ClientToGC);
+        match packet_message.header() {
+            MessageHeaderWrapper::Proto(header) => assert_eq!(header.routing_appid(), CS2),
+            header => panic!("expected a protobuf header, got {:?}", header),
+        }
+        GcMessage::from_gc_client(&packet_message.decode().unwrap()).unwrap()
+    }
+
+    // any protobuf will do as a stand in for a game's own
+    fn body(text: &str) -> CMsgClientRichPresenceUpload {
+        let mut body = CMsgClientRichPresenceUpload::new();
+        body.set_rich_presence_kv(text.as_bytes().to_vec().into());
+        body
+    }
+
+    #[tokio::test]
+    async fn sends_protobuf_and_raw_messages() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let coordinator = SteamGameCoordinator::new(connection, EventBus::new());
+
+        coordinator.send(CS2, PREVIEW_REQUEST, &body("inspect")).unwrap();
+        let message = to_gc(sent.recv().await.unwrap());
+        assert_eq!(message.app_id, CS2);
+        assert_eq!(message.msg_type, PREVIEW_REQUEST);
+        assert!(message.is_proto);
+        assert_eq!(message.source_job_id, NO_JOB);
+        assert_eq!(message.body, body("inspect").write_to_bytes().unwrap());
+
+        coordinator.send_raw(CS2, 4004, b"hello").unwrap();
+        let message = to_gc(sent.recv().await.unwrap());
+        assert_eq!(message.msg_type, 4004);
+        assert!(!message.is_proto);
+        assert_eq!(message.target_job_id, NO_JOB);
+        assert_eq!(&message.body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn messages_from_gcs_are_routed_by_app() {
+        let (connection, _sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let coordinator = SteamGameCoordinator::new(connection.clone(), events.clone());
+        let mut dota = coordinator.subscribe_app(570);
+        let mut previews = coordinator.subscribe::<CMsgClientRichPresenceUpload>(CS2, PREVIEW_REQUEST + 1);
+
+        let mut raw_payload = GC_HEADER_VERSION.to_le_bytes().to_vec();
+        raw_payload.extend_from_slice(&7u64.to_le_bytes());
+        raw_payload.extend_from_slice(&NO_JOB.to_le_bytes());
+        raw_payload.extend_from_slice(b"welcome");
+
+        // wrong type, not a protobuf, another app, and then the one we want
+        connection.inject(from_gc(CS2, 4004 | PROTOMASK, proto_payload(4004, "not a preview")));
+        connection.inject(from_gc(CS2, PREVIEW_REQUEST + 1, raw_payload.clone()));
+        connection.inject(from_gc(570, 4004, raw_payload));
+        connection.inject(from_gc(
+            CS2,
+            (PREVIEW_REQUEST + 1) | PROTOMASK,
+            proto_payload(PREVIEW_REQUEST + 1, "float 0.0123"),
+        ));
+
+        let welcome = dota.next().await.unwrap();
+        assert_eq!(welcome.msg_type, 4004);
+        assert_eq!(welcome.target_job_id, 7);
+        assert_eq!(&welcome.body[..], b"welcome");
+
+        let preview = previews.next().await.unwrap();
+        assert_eq!(preview.rich_presence_kv(), b"float 0.0123");
+
+        let mut all = events.subscribe::<GcMessage>();
+        connection.inject(from_gc(CS2, 4004 | PROTOMASK, proto_payload(4004, "job")));
+        assert_eq!(all.next().await.unwrap().target_job_id, 42);
+
+        let all = events.subscribe::<GcMessage>();
+        connection.inject(from_gc(CS2, PROTOMASK, vec![0; 3]));
+        connection.inject(from_gc(CS2, 1, vec![0; GC_HEADER_SIZE]));
+        let next: Vec<GcMessage> = all.take(1).collect().await;
+        assert_eq!(next[0].msg_type, 1, "malformed messages are dropped");
+    }
+}
@@ -0,0 +1,180 @@
+//! Calls to Steam services, such as `Player.GetGameBadgeLevels#1`, over the CM.
+//!
+//! Requests and responses are the `C*_Request` and `C*_Response` protobufs from steam-protobuf. The method being
+//! called travels on the `target_job_name` of the protobuf header. Steam calls methods on us the same way, as
+//! notifications such as `FriendMessagesClient.IncomingMessage#1`, that are never answered.
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Steam/Handlers/SteamUnifiedMessages/SteamUnifiedMessages.cs
+
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::enums::EResult;
+use steam_language_gen::MessageHeaderWrapper;
+use steam_protobuf::Message;
+use steam_protobuf::ProtobufSerialize;
+
+use crate::connection::ConnectionHandle;
+use crate::errors::ServiceMethodError;
+use crate::messages::message::ClientMessage;
+use crate::messages::packet::PacketMessage;
+use crate::utils::eresult_from_i32;
+
+/// Calls service methods and awaits their typed responses.
+#[derive(Debug, Clone)]
+pub struct SteamUnifiedMessages {
+    connection: ConnectionHandle,
+}
+
+impl SteamUnifiedMessages {
+    pub(crate) fn new(connection: ConnectionHandle) -> Self {
+        Self { connection }
+    }
+
+    /// Calls `method`, such as `"Player.GetGameBadgeLevels#1"`, and waits for its response.
+    ///
+    /// Methods that require a session are only available after logon, while others like `Authentication.*` may be
+    /// called right after connecting.
+    pub async fn call_service_method<Req, Resp>(&self, method: &str, request: Req) -> Result<Resp, ServiceMethodError>
+    where
+        Req: Message + ProtobufSerialize,
+        Resp: Message,
+    {
+        let emsg = if self.connection.session().is_logged_on() {
+            EMsg::ServiceMethodCallFromClient
+        } else {
+            EMsg::ServiceMethodCallFromClientNonAuthed
+        };
+
+        let mut message = ClientMessage::<Req>::new_proto(emsg);
+        if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+            header.set_target_job_name(method.to_string());
+        }
+        message.body = request;
+
+        let reply = self.connection.send_job(message).await?;
+
+        // Steam could not route our call at all
+        if reply.emsg() == EMsg::DestJobFailed {
+            return Err(ServiceMethodError::Failed {
+                method: method.to_string(),
+                result: EResult::Fail,
+                message: "destination job failed".to_string(),
+            });
+        }
+
+        if let MessageHeaderWrapper::Proto(header) = reply.header() {
+            let result = eresult_from_i32(header.eresult());
+            if result != EResult::OK {
+                return Err(ServiceMethodError::Failed {
+                    method: method.to_string(),
+                    result,
+                    message: header.error_message().to_string(),
+                });
+            }
+        }
+
+        ClientMessage::<Resp>::from_proto_packet(reply)
+            .map(|response| response.body)
+            .map_err(|_| ServiceMethodError::MalformedResponse(method.to_string()))
+    }
+}
+
+/// Decodes `packet_message` if it is a notification from Steam for `method`, such as
+/// `"FriendMessagesClient.IncomingMessage#1"`.
+pub(crate) fn notification<M: Message>(packet_message: &PacketMessage, method: &str) -> Option<M> {
+    match packet_message.emsg() {
+        EMsg::ServiceMethod | EMsg::ServiceMethodSendToClient => {}
+        _ => return None,
+    }
+
+    match packet_message.header() {
+        MessageHeaderWrapper::Proto(header) if header.target_job_name() == method => {}
+        _ => return None,
+    }
+
+    packet_message.decode().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use steam_language_gen::HasJobId;
+    use steam_language_gen::SerializableBytes;
+    use steam_protobuf::protobufs::steammessages_player_steamclient::CPlayer_GetGameBadgeLevels_Request;
+    use steam_protobuf::protobufs::steammessages_player_steamclient::CPlayer_GetGameBadgeLevels_Response;
+    use tokio::task::JoinHandle;
+
+    use super::*;
+    use crate::connection::testing::sent_packet;
+
+    type BadgeLevels = Result<CPlayer_GetGameBadgeLevels_Response, ServiceMethodError>;
+
+    fn call_badge_levels(unified_messages: &SteamUnifiedMessages) -> JoinHandle<BadgeLevels> {
+        let unified_messages = unified_messages.clone();
+        tokio::spawn(async move {
+            let mut request = CPlayer_GetGameBadgeLevels_Request::new();
+            request.set_appid(440);
+            unified_messages
+                .call_service_method("Player.GetGameBadgeLevels#1", request)
+                .await
+        })
+    }
+
+    fn response(job_id: u64, eresult: EResult) -> PacketMessage {
+        let mut message = ClientMessage::<CPlayer_GetGameBadgeLevels_Response>::new_proto(EMsg::ServiceMethodResponse);
+        if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+            header.set_target(job_id);
+            header.set_eresult(eresult as i32);
+            header.set_error_message("some error".to_string());
+        }
+        message.body.set_player_level(42);
+
+        PacketMessage::from_raw_bytes(&message.to_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn resolves_with_typed_response() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let unified_messages = SteamUnifiedMessages::new(connection.clone());
+
+        let call = call_badge_levels(&unified_messages);
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(request.emsg(), EMsg::ServiceMethodCallFromClientNonAuthed);
+        match request.header() {
+            MessageHeaderWrapper::Proto(header) => {
+                assert_eq!(header.target_job_name(), "Player.GetGameBadgeLevels#1")
+            }
+            header => panic!("expected a protobuf header, got {:?}", header),
+        }
+        let body = ClientMessage::<CPlayer_GetGameBadgeLevels_Request>::from_proto_packet(request.clone()).unwrap();
+        assert_eq!(body.body.appid(), 440);
+
+        connection.inject(response(request.jobs_ids().0, EResult::OK));
+
+        let response = call.await.unwrap().unwrap();
+        assert_eq!(response.player_level(), 42);
+    }
+
+    #[tokio::test]
+    async fn failed_eresult_is_an_error() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let unified_messages = SteamUnifiedMessages::new(connection.clone());
+
+        let call = call_badge_levels(&unified_messages);
+
+        let request = sent_packet(sent.recv().await.unwrap());
+        connection.inject(response(request.jobs_ids().0, EResult::AccessDenied));
+
+        match call.await.unwrap() {
+            Err(ServiceMethodError::Failed {
+                method,
+                result,
+                message,
+            }) => {
+                assert_eq!(method, "Player.GetGameBadgeLevels#1");
+                assert_eq!(result, EResult::AccessDenied);
+                assert_eq!(message, "some error");
+            }
+            result => panic!("expected a failed call, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,493 @@
+//! Logging on and off from Steam through the CM.
+//!
+//! Reference: https://github.com/SteamRE/SteamKit/blob/master/SteamKit2/SteamKit2/Steam/Handlers/SteamUser/SteamUser.cs
+
+use std::time::Duration;
+
+use steam_language_gen::generated::enums::EAccountType;
+use steam_language_gen::generated::enums::EMsg;
+use steam_language_gen::generated::enums::EOSType;
+use steam_language_gen::generated::enums::EResult;
+use steam_language_gen::MessageHeaderWrapper;
+use steam_protobuf::protobufs::steammessages_clientserver::cmsg_client_games_played;
+use steam_protobuf::protobufs::steammessages_clientserver::CMsgClientGamesPlayed;
+use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogOff;
+use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLoggedOff;
+use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogon;
+use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogonResponse;
+use steam_protobuf::UnknownValueRef;
+use steamid_parser::SteamID;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::connection::session::heartbeat_seconds;
+use crate::connection::ConnectionHandle;
+use crate::connection::PacketRx;
+use crate::errors::ConnectionError;
+use crate::errors::LogonError;
+use crate::events::Event;
+use crate::events::EventBus;
+use crate::handlers::forward_packets;
+use crate::messages::message::ClientMessage;
+use crate::messages::packet::PacketMessage;
+use crate::utils::eresult_from_i32;
+
+/// Protocol version we claim to speak, same as the official client.
+const PROTOCOL_VERSION: u32 = 65580;
+
+/// Game id Steam shows the name of, for games it knows nothing about.
+const NON_STEAM_GAME_ID: u64 = 15190414816125648896;
+
+/// `webapi_authenticate_user_nonce` was dropped from newer protobufs, but some CMs still send it.
+const WEBAPI_NONCE_FIELD: u32 = 11;
+
+/// Credentials used to log on.
+#[derive(Clone)]
+pub enum LogOnDetails {
+    /// Account name and password, plus a Steam Guard code if the account asks for one.
+    Password {
+        /// Login name, not the profile name.
+        account_name: String,
+        /// Account password.
+        password: String,
+        /// Code from the mobile authenticator.
+        two_factor_code: Option<String>,
+        /// Code sent by email.
+        auth_code: Option<String>,
+    },
+    /// Refresh token issued by a previous authentication session.
+    RefreshToken {
+        /// Login name the token was issued to.
+        account_name: String,
+        /// The token itself, a JWT.
+        refresh_token: String,
+    },
+    /// Anonymous user account, enough to access public data such as app info and depots.
+    Anonymous,
+}
+
+impl LogOnDetails {
+    /// Logs on with account name and password, without any Steam Guard code.
+    pub fn password<T: Into<String>>(account_name: T, password: T) -> Self {
+        Self::Password {
+            account_name: account_name.into(),
+            password: password.into(),
+            two_factor_code: None,
+            auth_code: None,
+        }
+    }
+
+    /// Logs on with a refresh token.
+    pub fn refresh_token<T: Into<String>>(account_name: T, refresh_token: T) -> Self {
+        Self::RefreshToken {
+            account_name: account_name.into(),
+            refresh_token: refresh_token.into(),
+        }
+    }
+}
+
+/// Never print credentials.
+impl std::fmt::Debug for LogOnDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogOnDetails::Password { account_name, .. } => f.debug_tuple("Password").field(account_name).finish(),
+            LogOnDetails::RefreshToken { account_name, .. } => {
+                f.debug_tuple("RefreshToken").field(account_name).finish()
+            }
+            LogOnDetails::Anonymous => f.write_str("Anonymous"),
+        }
+    }
+}
+
+/// A game we tell Steam we are playing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Game {
+    /// Steam app, by its id.
+    App(u32),
+    /// Game Steam knows nothing about, shown by its name.
+    NonSteam(String),
+}
+
+/// Answer to a logon attempt.
+#[derive(Debug, Clone)]
+pub struct LoggedOn {
+    /// Anything other than `OK` means the logon failed.
+    pub result: EResult,
+    /// SteamID we are logged on as.
+    pub steam_id: SteamID,
+    /// Cell id assigned to us, used to fetch nearby servers.
+    pub cell_id: u32,
+    /// How often Steam expects to hear from us.
+    pub heartbeat_interval: Duration,
+    /// Nonce used to authenticate against the Web API, if Steam sent one.
+    pub webapi_authenticate_user_nonce: Option<String>,
+}
+
+impl LoggedOn {
+    fn from_message(message: ClientMessage<CMsgClientLogonResponse>) -> Self {
+        let steam_id = match &message.wrapped_header {
+            MessageHeaderWrapper::Proto(header) => header.steamid(),
+            _ => 0,
+        };
+        let response = message.body;
+
+        let heartbeat_seconds = heartbeat_seconds(&response);
+
+        let webapi_authenticate_user_nonce = match response.special_fields.unknown_fields().get(WEBAPI_NONCE_FIELD) {
+            Some(UnknownValueRef::LengthDelimited(nonce)) => Some(String::from_utf8_lossy(nonce).into_owned()),
+            _ => None,
+        };
+
+        Self {
+            result: eresult_from_i32(response.eresult()),
+            steam_id: SteamID::from_steam64(steam_id),
+            cell_id: response.cell_id(),
+            heartbeat_interval: Duration::from_secs(heartbeat_seconds.max(0) as u64),
+            webapi_authenticate_user_nonce,
+        }
+    }
+}
+
+/// We are no longer logged on, either because Steam kicked us or the connection was lost.
+#[derive(Debug, Clone, Copy)]
+pub struct LoggedOff {
+    /// Why we were logged off. `NoConnection` if the connection itself was lost.
+    pub result: EResult,
+}
+
+impl Event for LoggedOn {}
+impl Event for LoggedOff {}
+
+/// Logs on and off from Steam.
+///
+/// Publishes [LoggedOn] and [LoggedOff] on the [EventBus].
+#[derive(Debug, Clone)]
+pub struct SteamUser {
+    connection: ConnectionHandle,
+    events: EventBus,
+}
+
+impl SteamUser {
+    pub(crate) fn new(connection: ConnectionHandle, events: EventBus) -> Self {
+        tokio::spawn(forward_events(
+            connection.clone(),
+            connection.subscribe(),
+            events.clone(),
+        ));
+        Self { connection, events }
+    }
+
+    /// Logs on and waits for Steam to answer.
+    ///
+    /// The answer is also published as a [LoggedOn] event, even if the logon failed.
+    pub async fn log_on(&self, details: LogOnDetails) -> Result<LoggedOn, LogonError> {
+        let mut logged_on = self.events.receiver::<LoggedOn>();
+        self.connection.send(logon_message(&details))?;
+
+        loop {
+            let event = tokio::select! {
+                event = logged_on.recv() => event,
+                _ = self.connection.closed() => return Err(ConnectionError::Dropped.into()),
+            };
+
+            match event {
+                Ok(logged_on) if logged_on.result == EResult::OK => return Ok(logged_on),
+                Ok(logged_on) => return Err(LogonError::Denied(logged_on.result)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Err(ConnectionError::Dropped.into()),
+            }
+        }
+    }
+
+    /// Asks Steam to log us off. Steam answers with a [LoggedOff] event.
+    pub fn log_off(&self) -> Result<(), ConnectionError> {
+        self.connection
+            .send(ClientMessage::<CMsgClientLogOff>::new_proto(EMsg::ClientLogOff))
+    }
+
+    /// Tells Steam we are playing `games`, all at once, or nothing if empty.
+    ///
+    /// Playing an app also shows its rich presence, set through
+    /// [SteamFriends](crate::handlers::steam_friends::SteamFriends).
+    pub fn games_played(&self, games: &[Game]) -> Result<(), ConnectionError> {
+        let mut message = ClientMessage::<CMsgClientGamesPlayed>::new_proto(EMsg::ClientGamesPlayedWithDataBlob);
+        message.body.set_client_os_type(EOSType::Windows10 as u32);
+        message.body.games_played = games
+            .iter()
+            .map(|game| {
+                let mut game_played = cmsg_client_games_played::GamePlayed::new();
+                match game {
+                    Game::App(app_id) => game_played.set_game_id(*app_id as u64),
+                    Game::NonSteam(name) => {
+                        game_played.set_game_id(NON_STEAM_GAME_ID);
+                        game_played.set_game_extra_info(name.clone());
+                    }
+                }
+                game_played
+            })
+            .collect();
+        self.connection.send(message)
+    }
+
+    /// SteamID we are currently logged on as, if any.
+    pub fn steam_id(&self) -> Option<SteamID> {
+        let session = self.connection.session();
+        if session.is_logged_on() {
+            Some(SteamID::from_steam64(session.steam_id()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Publishes logon related messages as events until the connection is gone.
+async fn forward_events(connection: ConnectionHandle, packets: PacketRx, events: EventBus) {
+    let mut logged_on = false;
+    forward_packets("SteamUser", connection, packets, |packet_message| {
+        handle_msg(&events, packet_message, &mut logged_on)
+    })
+    .await;
+
+    if logged_on {
+        events.publish(LoggedOff {
+            result: EResult::NoConnection,
+        });
+    }
+}
+
+fn handle_msg(events: &EventBus, packet_message: PacketMessage, logged_on: &mut bool) {
+    match packet_message.emsg() {
+        EMsg::ClientLogOnResponse => {
+            let message = match ClientMessage::<CMsgClientLogonResponse>::from_proto_packet(packet_message) {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+            let event = LoggedOn::from_message(message);
+            debug!("SteamUser event: {:?}", event);
+
+            *logged_on = event.result == EResult::OK;
+            events.publish(event);
+        }
+        EMsg::ClientLoggedOff => {
+            let message = match ClientMessage::<CMsgClientLoggedOff>::from_proto_packet(packet_message) {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+            let event = LoggedOff {
+                result: eresult_from_i32(message.body.eresult()),
+            };
+            debug!("SteamUser event: {:?}", event);
+
+            *logged_on = false;
+            events.publish(event);
+        }
+        _ => {}
+    }
+}
+
+fn logon_message(details: &LogOnDetails) -> ClientMessage<CMsgClientLogon> {
+    let mut message = ClientMessage::<CMsgClientLogon>::new_proto(EMsg::ClientLogon);
+
+    let account_type = match details {
+        LogOnDetails::Anonymous => EAccountType::AnonUser,
+        _ => EAccountType::Individual,
+    };
+    if let MessageHeaderWrapper::Proto(header) = &mut message.wrapped_header {
+        header.set_steamid(logon_steam_id(account_type));
+    }
+
+    let logon = &mut message.body;
+    logon.set_protocol_version(PROTOCOL_VERSION);
+    logon.set_client_os_type(EOSType::Windows10 as u32);
+    logon.set_client_language("english".to_string());
+    logon.set_supports_rate_limit_response(true);
+
+    match details {
+        LogOnDetails::Password {
+            account_name,
+            password,
+            two_factor_code,
+            auth_code,
+        } => {
+            logon.set_account_name(account_name.clone());
+            logon.set_password(password.clone());
+            if let Some(two_factor_code) = two_factor_code {
+                logon.set_two_factor_code(two_factor_code.clone());
+            }
+            if let Some(auth_code) = auth_code {
+                logon.set_auth_code(auth_code.clone());
+            }
+        }
+        LogOnDetails::RefreshToken {
+            account_name,
+            refresh_token,
+        } => {
+            logon.set_account_name(account_name.clone());
+            logon.set_access_token(refresh_token.clone());
+            logon.set_should_remember_password(true);
+        }
+        LogOnDetails::Anonymous => {}
+    }
+
+    message
+}
+
+/// SteamID on the logon header: public universe, desktop instance for users, and no account id, since Steam fills it.
+fn logon_steam_id(account_type: EAccountType) -> u64 {
+    const PUBLIC_UNIVERSE: u64 = 1 << 56;
+    const DESKTOP_INSTANCE: u64 = 1 << 32;
+
+    match account_type {
+        EAccountType::AnonUser => PUBLIC_UNIVERSE | (account_type as u64) << 52,
+        _ => PUBLIC_UNIVERSE | (account_type as u64) << 52 | DESKTOP_INSTANCE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use steam_language_gen::SerializableBytes;
+    use steam_protobuf::protobufs::steammessages_base::CMsgProtoBufHeader;
+
+    use super::*;
+    use crate::connection::testing::sent_packet;
+    use crate::connection::DynBytes;
+
+    fn logon_response(eresult: EResult) -> PacketMessage {
+        let mut header = CMsgProtoBufHeader::new();
+        header.set_steamid(76561197960287930);
+        header.set_client_sessionid(1234567);
+
+        let mut response = CMsgClientLogonResponse::new();
+        response.set_eresult(eresult as i32);
+        response.set_heartbeat_seconds(9);
+        response.set_cell_id(4);
+
+        PacketMessage::new(
+            EMsg::ClientLogOnResponse,
+            MessageHeaderWrapper::Proto(header),
+            SerializableBytes::to_bytes(&response),
+        )
+    }
+
+    fn sent_logon(sent: DynBytes) -> ClientMessage<CMsgClientLogon> {
+        let packet_message = sent_packet(sent);
+        assert_eq!(packet_message.emsg(), EMsg::ClientLogon);
+        ClientMessage::from_proto_packet(packet_message).unwrap()
+    }
+
+    #[test]
+    fn logon_steam_ids() {
+        assert_eq!(logon_steam_id(EAccountType::Individual), 0x0110_0001_0000_0000);
+        assert_eq!(logon_steam_id(EAccountType::AnonUser), 0x01A0_0000_0000_0000);
+    }
+
+    #[test]
+    fn refresh_token_goes_into_access_token() {
+        let message = logon_message(&LogOnDetails::refresh_token("bot", "eyJ0eXAi"));
+
+        assert_eq!(message.body.account_name(), "bot");
+        assert_eq!(message.body.access_token(), "eyJ0eXAi");
+        assert!(!message.body.has_password());
+    }
+
+    #[test]
+    fn debug_hides_credentials() {
+        let details = format!("{:?}", LogOnDetails::password("bot", "hunter2"));
+        assert!(!details.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn logs_on_with_password() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let steam_user = SteamUser::new(connection.clone(), events.clone());
+        let mut logged_on_events = events.subscribe::<LoggedOn>();
+
+        let logon = tokio::spawn({
+            let steam_user = steam_user.clone();
+            async move { steam_user.log_on(LogOnDetails::password("bot", "hunter2")).await }
+        });
+
+        let message = sent_logon(sent.recv().await.unwrap());
+        assert_eq!(message.body.account_name(), "bot");
+        assert_eq!(message.body.password(), "hunter2");
+        assert_eq!(message.body.protocol_version(), PROTOCOL_VERSION);
+
+        connection.inject(logon_response(EResult::OK));
+
+        let logged_on = logon.await.unwrap().unwrap();
+        assert_eq!(logged_on.steam_id.to_steam64(), 76561197960287930);
+        assert_eq!(logged_on.cell_id, 4);
+        assert_eq!(logged_on.heartbeat_interval, Duration::from_secs(9));
+        assert_eq!(steam_user.steam_id(), Some(SteamID::from_steam64(76561197960287930)));
+        assert_eq!(logged_on_events.next().await.unwrap().result, EResult::OK);
+
+        // from now on, everything we send carries our session
+        steam_user.log_off().unwrap();
+        let packet_message = sent_packet(sent.recv().await.unwrap());
+        match packet_message.header() {
+            MessageHeaderWrapper::Proto(header) => assert_eq!(header.client_sessionid(), 1234567),
+            header => panic!("expected a protobuf header, got {:?}", header),
+        }
+    }
+
+    #[tokio::test]
+    async fn denied_logon_is_an_error() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let steam_user = SteamUser::new(connection.clone(), EventBus::new());
+
+        let logon = tokio::spawn({
+            let steam_user = steam_user.clone();
+            async move { steam_user.log_on(LogOnDetails::Anonymous).await }
+        });
+
+        let message = sent_logon(sent.recv().await.unwrap());
+        match message.wrapped_header {
+            MessageHeaderWrapper::Proto(header) => assert_eq!(header.steamid(), 0x01A0_0000_0000_0000),
+            header => panic!("expected a protobuf header, got {:?}", header),
+        }
+
+        connection.inject(logon_response(EResult::InvalidPassword));
+
+        let result = logon.await.unwrap();
+        assert!(matches!(result, Err(LogonError::Denied(EResult::InvalidPassword))));
+        assert_eq!(steam_user.steam_id(), None);
+    }
+
+    #[tokio::test]
+    async fn plays_apps_and_non_steam_games() {
+        let (connection, mut sent) = ConnectionHandle::detached();
+        let steam_user = SteamUser::new(connection, EventBus::new());
+
+        steam_user
+            .games_played(&[Game::App(440), Game::NonSteam("Idling".to_string())])
+            .unwrap();
+
+        let packet_message = sent_packet(sent.recv().await.unwrap());
+        assert_eq!(packet_message.emsg(), EMsg::ClientGamesPlayedWithDataBlob);
+        let games_played = packet_message.decode::<CMsgClientGamesPlayed>().unwrap().games_played;
+        assert_eq!(games_played.len(), 2);
+        assert_eq!(games_played[0].game_id(), 440);
+        assert_eq!(games_played[1].game_id(), NON_STEAM_GAME_ID);
+        assert_eq!(games_played[1].game_extra_info(), "Idling");
+    }
+
+    #[tokio::test]
+    async fn logged_off_is_emitted() {
+        let (connection, _sent) = ConnectionHandle::detached();
+        let events = EventBus::new();
+        let _steam_user = SteamUser::new(connection.clone(), events.clone());
+        let mut logged_off_events = events.subscribe::<LoggedOff>();
+
+        let mut logged_off = CMsgClientLoggedOff::new();
+        logged_off.set_eresult(EResult::LoggedInElsewhere as i32);
+        connection.inject(PacketMessage::new(
+            EMsg::ClientLoggedOff,
+            MessageHeaderWrapper::Proto(CMsgProtoBufHeader::new()),
+            SerializableBytes::to_bytes(&logged_off),
+        ));
+
+        let logged_off = logged_off_events.next().await.unwrap();
+        assert_eq!(logged_off.result, EResult::LoggedInElsewhere);
+    }
+}
@@ -21,10 +21,15 @@ use tappet::SteamAPI;
 pub mod client;
 pub mod config;
 pub mod connection;
-mod content_manager;
+pub mod content;
 pub mod errors;
+pub mod events;
+#[cfg(any(test, feature = "fake-cm"))]
+pub mod fake_cm;
 pub mod handlers;
 pub mod messages;
+pub mod server_list;
+pub mod transport;
 pub(crate) mod utils;
 
 lazy_static! {
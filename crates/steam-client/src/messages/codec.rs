@@ -1,14 +1,20 @@
-use std::io::Write;
-
-use bytes::{Buf, BufMut, BytesMut};
-use tokio_util::codec::{Decoder, Encoder};
+use bytes::Buf;
+use bytes::BytesMut;
+use steam_crypto::symm::symmetric_decrypt;
+use steam_crypto::symm::symmetric_encrypt_hmac_iv;
+use tokio_util::codec::Decoder;
+use tokio_util::codec::Encoder;
 
 use crate::connection::EncryptionState;
 use crate::errors::PacketError;
 use crate::messages::packet::PacketMessage;
 
-const PACKET_MAGIC_BYTES: &[u8] = br#"VT01"#;
+pub(crate) const PACKET_MAGIC_BYTES: &[u8] = br#"VT01"#;
 const PACKET_MAGIC_SIZE: usize = 4;
+/// Frame length (u32) followed by the magic bytes.
+const PACKET_HEADER_SIZE: usize = 4 + PACKET_MAGIC_SIZE;
+/// Steam never sends frames close to this size, so anything bigger means the stream is corrupted.
+pub(crate) const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
 
 /// Used to encode and decode messages coming directly from the socket.
 ///
@@ -18,14 +24,17 @@ const PACKET_MAGIC_SIZE: usize = 4;
 /// The sole responsibility of the codec, is to ensure that when the connection is encrypted,
 /// it encrypts outgoing and decrypts incoming messages correctly.
 ///
-/// It does not hold state by itself, and it doesn't know anything outside of encrypting and wrapping messages
-/// with Steam magic bytes.
+/// The only state it holds is the session key, handed over by [SteamConnection] once the handshake succeeds. It
+/// doesn't know anything outside of encrypting and wrapping messages with Steam magic bytes.
 ///
 /// [SteamConnection] should know how to react to changes on the connection.
 #[derive(Debug)]
 pub(crate) struct PacketMessageCodec {
+    /// Bytes still missing from the buffer before the pending frame can be decoded.
     remaining_msg_bytes: usize,
     encryption_state: EncryptionState,
+    /// Plain session key, only present after the channel is `Encrypted`.
+    session_key: Option<Vec<u8>>,
 }
 
 impl Default for PacketMessageCodec {
@@ -33,6 +42,22 @@ impl Default for PacketMessageCodec {
         Self {
             remaining_msg_bytes: 0,
             encryption_state: EncryptionState::Disconnected,
+            session_key: None,
+        }
+    }
+}
+
+impl PacketMessageCodec {
+    /// Every frame encoded or decoded from now on is encrypted with `session_key`.
+    pub(crate) fn enable_encryption(&mut self, session_key: &[u8]) {
+        self.session_key = Some(session_key.to_vec());
+        self.encryption_state = EncryptionState::Encrypted;
+    }
+
+    fn active_session_key(&self) -> Option<&[u8]> {
+        match self.encryption_state {
+            EncryptionState::Encrypted => self.session_key.as_deref(),
+            _ => None,
         }
     }
 }
@@ -41,10 +66,12 @@ impl Encoder<Vec<u8>> for PacketMessageCodec {
     type Error = PacketError;
 
     fn encode(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        dst.reserve(1024);
+        let item = match self.active_session_key() {
+            Some(key) => symmetric_encrypt_hmac_iv(&item, key)?,
+            None => item,
+        };
 
-        println!("This is writing something");
-        println!("{:?}", item);
+        dst.reserve(item.len() + PACKET_MAGIC_SIZE + 4);
 
         let message_size = item.len() as u32;
         dst.extend_from_slice(&(message_size).to_le_bytes());
@@ -60,25 +87,200 @@ impl Decoder for PacketMessageCodec {
     type Error = PacketError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let encryption_state = self.encryption_state;
+        loop {
+            if self.remaining_msg_bytes > src.len() || src.len() < PACKET_HEADER_SIZE {
+                return Ok(None);
+            }
+
+            let data_len = u32::from_le_bytes([src[0], src[1], src[2], src[3]]) as usize;
+
+            // there is no reliable way to find the next frame after this, so we give up on the stream
+            if &src[4..PACKET_HEADER_SIZE] != PACKET_MAGIC_BYTES {
+                return Err(PacketError::InvalidMagic);
+            }
+            if data_len > MAX_FRAME_SIZE {
+                return Err(PacketError::FrameTooLarge(data_len));
+            }
 
-        println!("ehlo!");
+            let frame_len = PACKET_HEADER_SIZE + data_len;
+            if src.len() < frame_len {
+                self.remaining_msg_bytes = frame_len;
+                src.reserve(frame_len - src.len());
+                return Ok(None);
+            }
 
-        if self.remaining_msg_bytes > src.len() || src.is_empty() {
-            return Ok(None);
+            self.remaining_msg_bytes = 0;
+            src.advance(PACKET_HEADER_SIZE);
+            let message_bytes = src.split_to(data_len);
+
+            let packet_message = match self.active_session_key() {
+                Some(key) => PacketMessage::from_raw_bytes(&symmetric_decrypt(&message_bytes, key, true)?),
+                None => PacketMessage::from_raw_bytes(&message_bytes),
+            };
+
+            match packet_message {
+                Ok(packet_message) => return Ok(Some(packet_message)),
+                // the frame itself was fine, so we can still move on to the next one
+                Err(err) => warn!("Skipping undecodable message: {}", err),
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use steam_language_gen::generated::enums::EMsg;
+    use steam_language_gen::generated::messages::MsgClientChatEnter;
+    use steam_language_gen::SerializableBytes;
+    use tokio_util::codec::FramedRead;
+
+    use super::*;
+    use crate::messages::message::ClientMessage;
+
+    const SESSION_KEY: [u8; 32] = [7; 32];
+
+    /// ChannelEncryptRequest, ChannelEncryptResult and ClientChatEnter messages, as sent by a CM.
+    fn recorded_messages() -> Vec<Vec<u8>> {
+        vec![
+            vec![
+                23, 5, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1, 0, 0,
+                0, 1, 0, 0, 0, 66, 126, 251, 245, 88, 122, 243, 123, 102, 163, 11, 54, 151, 145, 31, 54,
+            ],
+            vec![
+                25, 5, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 1, 0, 0, 0,
+            ],
+            ClientMessage::<MsgClientChatEnter>::new().to_bytes(),
+        ]
+    }
 
-        let data_len = src.get_u32_le();
-        let magic_bytes = src.copy_to_bytes(PACKET_MAGIC_SIZE);
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = (message.len() as u32).to_le_bytes().to_vec();
+        frame.extend_from_slice(PACKET_MAGIC_BYTES);
+        frame.extend_from_slice(message);
+        frame
+    }
 
-        if magic_bytes != PACKET_MAGIC_BYTES {
-            return Err(PacketError::Malformed);
+    /// Feeds `stream` through a [FramedRead], split into reads of `chunk_sizes` bytes (cycling over them).
+    fn decode_in_chunks(stream: &[u8], chunk_sizes: &[usize]) -> Vec<Result<PacketMessage, PacketError>> {
+        let mut reader = tokio_test::io::Builder::new();
+        let mut remaining = stream;
+        for chunk_size in chunk_sizes.iter().cycle() {
+            if remaining.is_empty() {
+                break;
+            }
+            let (chunk, rest) = remaining.split_at((*chunk_size).min(remaining.len()));
+            reader.read(chunk);
+            remaining = rest;
         }
 
-        let message_bytes = src.copy_to_bytes(data_len as usize);
-        let packet_message = PacketMessage::from_raw_bytes(&message_bytes);
-        src.reserve(data_len as usize);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(FramedRead::new(reader.build(), PacketMessageCodec::default()).collect())
+    }
+
+    proptest! {
+        #[test]
+        fn decodes_frames_split_at_any_point(chunk_sizes in vec(1usize..96, 1..16), repeat in 1usize..4) {
+            let messages: Vec<Vec<u8>> = recorded_messages().into_iter().cycle().take(3 * repeat).collect();
+            let stream: Vec<u8> = messages.iter().flat_map(|message| frame(message)).collect();
+
+            let decoded: Vec<PacketMessage> = decode_in_chunks(&stream, &chunk_sizes)
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            let expected: Vec<PacketMessage> = messages
+                .iter()
+                .map(|message| PacketMessage::from_raw_bytes(message).unwrap())
+                .collect();
+
+            prop_assert_eq!(decoded, expected);
+        }
+
+        #[test]
+        fn garbage_after_valid_frame_ends_stream(garbage in vec(any::<u8>(), 8..64)) {
+            prop_assume!(&garbage[4..8] != PACKET_MAGIC_BYTES);
+            let mut stream = frame(&recorded_messages()[0]);
+            stream.extend_from_slice(&garbage);
+
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let decoded: Vec<_> =
+                runtime.block_on(FramedRead::new(&stream[..], PacketMessageCodec::default()).collect());
+
+            prop_assert_eq!(decoded.len(), 2);
+            prop_assert!(decoded[0].is_ok());
+            prop_assert!(matches!(decoded[1], Err(PacketError::InvalidMagic)));
+        }
+    }
+
+    #[test]
+    fn partial_frame_is_kept_until_complete() {
+        let stream = frame(&recorded_messages()[2]);
+        let mut codec = PacketMessageCodec::default();
+
+        let mut buffer = BytesMut::from(&stream[..stream.len() - 1]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        assert_eq!(buffer.len(), stream.len() - 1);
+
+        buffer.extend_from_slice(&stream[stream.len() - 1..]);
+        assert!(codec.decode(&mut buffer).unwrap().is_some());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn absurd_frame_length_is_rejected() {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&(MAX_FRAME_SIZE as u32 + 1).to_le_bytes());
+        buffer.extend_from_slice(PACKET_MAGIC_BYTES);
+
+        let mut codec = PacketMessageCodec::default();
+        assert!(matches!(codec.decode(&mut buffer), Err(PacketError::FrameTooLarge(_))));
+    }
+
+    #[test]
+    fn unknown_emsg_is_skipped() {
+        let mut stream = frame(&[0xFF, 0xFF, 0xFF, 0x7F, 0, 0, 0, 0]);
+        stream.extend(frame(&recorded_messages()[1]));
+
+        let decoded = decode_in_chunks(&stream, &[stream.len()]);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].as_ref().unwrap().emsg(), EMsg::ChannelEncryptResult);
+    }
+
+    fn encrypted_codec() -> PacketMessageCodec {
+        let mut codec = PacketMessageCodec::default();
+        codec.enable_encryption(&SESSION_KEY);
+        codec
+    }
+
+    #[test]
+    fn encrypted_roundtrip() {
+        let message = ClientMessage::<MsgClientChatEnter>::new().to_bytes();
+        let mut codec = encrypted_codec();
+
+        let mut buffer = BytesMut::new();
+        codec.encode(message.clone(), &mut buffer).unwrap();
+        assert!(!buffer.windows(message.len()).any(|window| window == &message[..]));
+
+        let packet_message = codec.decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(packet_message, PacketMessage::from_raw_bytes(&message).unwrap());
+        assert_eq!(packet_message.emsg(), EMsg::ClientChatEnter);
+    }
+
+    #[test]
+    fn tampered_frame_is_rejected() {
+        let message = ClientMessage::<MsgClientChatEnter>::new().to_bytes();
+        let mut codec = encrypted_codec();
+
+        let mut buffer = BytesMut::new();
+        codec.encode(message, &mut buffer).unwrap();
+        let last = buffer.len() - 1;
+        buffer[last] ^= 0xFF;
 
-        Ok(Some(packet_message))
+        assert!(matches!(codec.decode(&mut buffer), Err(PacketError::Crypto(_))));
     }
 }
@@ -73,7 +73,7 @@ mod tests {
         let emsg = EMsg::from_raw_message(&packet).unwrap();
         assert_eq!(emsg, EMsg::ClientChatEnter);
 
-        let packet_message = PacketMessage::from_raw_bytes(&packet);
+        let packet_message = PacketMessage::from_raw_bytes(&packet).unwrap();
         let message: ClientMessage<MsgClientChatEnter> = ClientMessage::from_packet_message(packet_message);
         let chat_name = str_from_u8_nul_utf8(message.payload()).unwrap();
         assert_eq!("Saxton Hell", chat_name);
@@ -15,9 +15,10 @@ use steam_language_gen::generated::headers::{ExtendedMessageHeader, MessageHeade
 use steam_language_gen::generated::messages::HasEMsg;
 use steam_language_gen::{DeserializableBytes, MessageBodyExt, MessageHeaderWrapper, SerializableBytes};
 use steam_language_gen::{HasJobId, MessageHeaderExt};
-use steam_protobuf::steam::steammessages_base::CMsgProtoBufHeader;
+use steam_protobuf::protobufs::steammessages_base::CMsgProtoBufHeader;
 use steam_protobuf::Message;
 
+use crate::errors::PacketError;
 use crate::messages::packet::PacketMessage;
 use crate::messages::MessageKind;
 
@@ -57,6 +58,30 @@ where
             payload: vec![],
         }
     }
+
+    /// Used to decode incoming protobuf messages, whose body is `M`.
+    pub(crate) fn from_proto_packet(msg: PacketMessage) -> Result<Self, PacketError> {
+        let body = M::parse_from_bytes(msg.payload()).map_err(|_| PacketError::Malformed)?;
+
+        Ok(Self {
+            emsg: msg.emsg(),
+            wrapped_header: msg.header(),
+            body,
+            payload: vec![],
+        })
+    }
+}
+
+impl<M> ClientMessage<M> {
+    pub(crate) fn set_target(mut self, target: u64) -> Self {
+        self.wrapped_header.set_target(target);
+        self
+    }
+
+    pub(crate) fn set_source(mut self, source: u64) -> Self {
+        self.wrapped_header.set_source(source);
+        self
+    }
 }
 
 impl<M: std::fmt::Debug + HasEMsg> std::fmt::Display for ClientMessage<M> {
@@ -75,7 +100,10 @@ impl<M: std::fmt::Debug + HasEMsg> std::fmt::Display for ClientMessage<M> {
 impl<M: SerializableBytes> SerializableBytes for ClientMessage<M> {
     fn to_bytes(&self) -> Vec<u8> {
         let mut output_buffer = BytesMut::with_capacity(1024);
-        let emsg = self.emsg as u32;
+        let emsg = match self.wrapped_header {
+            MessageHeaderWrapper::Proto(_) => self.emsg.with_protobuf_flag(),
+            _ => self.emsg as u32,
+        };
 
         output_buffer.extend(&emsg.to_le_bytes());
         output_buffer.extend(self.wrapped_header.to_bytes());
@@ -135,10 +163,6 @@ impl<T: MessageBodyExt + HasEMsg + SerializableBytes + DeserializableBytes> Clie
         }
     }
 
-    pub(crate) fn set_target(mut self, target: u64) -> Self {
-        self.wrapped_header.set_target(target);
-        self
-    }
     pub(crate) fn set_payload(mut self, payload: &[u8]) -> Self {
         self.payload = payload.to_vec();
         self
@@ -159,8 +183,16 @@ mod tests {
             headers::{ExtendedMessageHeader, StandardMessageHeader},
             messages::{MsgChannelEncryptRequest, MsgClientChatEnter},
         },
-        DeserializableBytes, MessageHeaderExt, SerializableBytes,
+        DeserializableBytes, HasJobId, MessageHeaderExt, MessageHeaderWrapper, SerializableBytes,
     };
+    use steam_protobuf::protobufs::steammessages_base::CMsgMulti;
+    use steam_protobuf::protobufs::steammessages_clientserver_login::CMsgClientLogonResponse;
+    use steam_protobuf::Message;
+
+    use super::ClientMessage;
+    use crate::connection::multi::unpack_multi;
+    use crate::errors::PacketError;
+    use crate::messages::packet::PacketMessage;
 
     /// ChannelEncryptRequest
     /// This has standard header
@@ -199,6 +231,95 @@ mod tests {
         struct_msg_data
     }
 
+    /// ClientLogOnResponse, EMsg(751) with the protobuf flag.
+    /// Protobuf header with steamid and client_sessionid, body with eresult, heartbeats and cell id.
+    fn get_client_logon_response() -> Vec<u8> {
+        vec![
+            0xEF, 0x02, 0x00, 0x80, 0x0D, 0x00, 0x00, 0x00, 0x09, 0xBA, 0x56, 0x00, 0x00, 0x01, 0x00, 0x10, 0x01, 0x10,
+            0x87, 0xAD, 0x4B, 0x08, 0x01, 0x10, 0x09, 0x18, 0x09, 0x38, 0x04,
+        ]
+    }
+
+    /// Multi, EMsg(1) with the protobuf flag and an empty protobuf header, carrying a single ClientLogOnResponse.
+    fn get_multi() -> Vec<u8> {
+        let logon_response = get_client_logon_response();
+        let mut message_body = (logon_response.len() as u32).to_le_bytes().to_vec();
+        message_body.extend(logon_response);
+
+        let mut multi = CMsgMulti::new();
+        multi.set_size_unzipped(0);
+        multi.set_message_body(message_body.into());
+
+        let mut frame = vec![0x01, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00];
+        frame.extend(multi.write_to_bytes().unwrap());
+        frame
+    }
+
+    #[test]
+    fn proto_message_roundtrip() {
+        let frame = get_client_logon_response();
+
+        let packet_message = PacketMessage::from_raw_bytes(&frame).unwrap();
+        assert_eq!(packet_message.emsg(), EMsg::ClientLogOnResponse);
+
+        let message: ClientMessage<CMsgClientLogonResponse> = ClientMessage::from_proto_packet(packet_message).unwrap();
+        match &message.wrapped_header {
+            MessageHeaderWrapper::Proto(header) => {
+                assert_eq!(header.steamid(), 76561197960287930);
+                assert_eq!(header.client_sessionid(), 1234567);
+            }
+            header => panic!("expected a protobuf header, got {:?}", header),
+        }
+        assert_eq!(message.body.eresult(), 1);
+        assert_eq!(message.body.heartbeat_seconds(), 9);
+        assert_eq!(message.body.cell_id(), 4);
+
+        assert_eq!(message.to_bytes(), frame);
+    }
+
+    #[test]
+    fn new_proto_sets_flag_and_header_length() {
+        let mut message: ClientMessage<CMsgClientLogonResponse> = ClientMessage::new_proto(EMsg::ClientLogOnResponse)
+            .set_target(10)
+            .set_source(20);
+        message.body.set_eresult(1);
+
+        let frame = message.to_bytes();
+        assert!(EMsg::is_protobuf(&frame));
+
+        let packet_message = PacketMessage::from_raw_bytes(&frame).unwrap();
+        assert_eq!(packet_message.emsg(), EMsg::ClientLogOnResponse);
+        assert_eq!(packet_message.header().target(), 10);
+        assert_eq!(packet_message.header().source(), 20);
+
+        let decoded: ClientMessage<CMsgClientLogonResponse> = ClientMessage::from_proto_packet(packet_message).unwrap();
+        assert_eq!(decoded.body, message.body);
+    }
+
+    #[test]
+    fn proto_header_length_past_the_end_is_malformed() {
+        let mut frame = get_client_logon_response();
+        frame.truncate(12);
+
+        assert!(matches!(
+            PacketMessage::from_raw_bytes(&frame),
+            Err(PacketError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn multi_frame_roundtrip() {
+        let packet_message = PacketMessage::from_raw_bytes(&get_multi()).unwrap();
+        assert_eq!(packet_message.emsg(), EMsg::Multi);
+
+        let messages = unpack_multi(&packet_message).unwrap();
+        assert_eq!(messages.len(), 1);
+
+        let message: ClientMessage<CMsgClientLogonResponse> =
+            ClientMessage::from_proto_packet(messages[0].clone()).unwrap();
+        assert_eq!(message.to_bytes(), get_client_logon_response());
+    }
+
     #[test]
     fn deserialize_client_chat_enter() {
         let message = get_client_chat_enter();
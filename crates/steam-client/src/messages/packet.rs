@@ -1,22 +1,27 @@
-use bytes::{Buf, BytesMut};
 use steam_language_gen::{
     generated::{
         enums::EMsg,
         headers::{ExtendedMessageHeader, StandardMessageHeader},
     },
-    DeserializableBytes, HasJobId, MessageHeaderExt, MessageHeaderWrapper, SerializableBytes,
+    DeserializableBytes, HasJobId, MessageHeaderExt, MessageHeaderWrapper,
 };
-use steam_protobuf::{steam::steammessages_base::CMsgProtoBufHeader, Message};
+use steam_protobuf::{protobufs::steammessages_base::CMsgProtoBufHeader, Message};
 
+use crate::errors::PacketError;
 use crate::messages::MessageKind;
 
+const EMSG_SIZE: usize = 4;
+const STANDARD_HEADER_SIZE: usize = 16;
+const EXTENDED_HEADER_SIZE: usize = 32;
+const PROTO_HEADER_LEN_SIZE: usize = 4;
+
 /// Represents a simple unified interface into client messages received directly from the socket.
 /// This is contrasted with [IClientMsg] in that this interface is packet body agnostic
 /// and allows simple access into its header and underlying data.
 ///
-/// Messages built by `PacketMessage` should be abstracted from the user.
+/// Handlers turn them into typed events, but they are also published as they are for user defined handlers.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct PacketMessage {
+pub struct PacketMessage {
     emsg: EMsg,
     header: MessageHeaderWrapper,
     data: Vec<u8>,
@@ -30,6 +35,10 @@ impl MessageKind for PacketMessage {
 }
 
 impl PacketMessage {
+    pub(crate) fn new(emsg: EMsg, header: MessageHeaderWrapper, data: Vec<u8>) -> Self {
+        Self { emsg, header, data }
+    }
+
     /// Returns (source_job_id, target_job_id)
     pub fn jobs_ids(&self) -> (u64, u64) {
         (self.header.source(), self.header.target())
@@ -47,6 +56,16 @@ impl PacketMessage {
         self.header.clone()
     }
 
+    /// Returns the message body, without its header.
+    pub fn body(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decodes the body of a protobuf message.
+    pub fn decode<M: Message>(&self) -> Result<M, PacketError> {
+        M::parse_from_bytes(&self.data).map_err(|_| PacketError::Malformed)
+    }
+
     /// This classify the socket message as:
     /// - Standard message (EncryptRequest, EncryptResponse, EncryptResult)
     /// - Protobuf message
@@ -56,39 +75,51 @@ impl PacketMessage {
     /// [raw_message_bytes] are the raw message bytes coming after Steam's identifier bytes.
     ///
     /// Reference: https://github.com/SteamRE/SteamKit/blob/58562fcc6f6972181615a6d1ff98103b06f0e33f/SteamKit2/SteamKit2/Steam/CMClient.cs#L448
-    pub fn from_raw_bytes(raw_message_bytes: &[u8]) -> PacketMessage {
-        let emsg = EMsg::from_raw_message(raw_message_bytes).unwrap();
+    pub fn from_raw_bytes(raw_message_bytes: &[u8]) -> Result<PacketMessage, PacketError> {
+        if raw_message_bytes.len() < EMSG_SIZE {
+            return Err(PacketError::Malformed);
+        }
+
+        let emsg = EMsg::from_raw_message(raw_message_bytes)
+            .map_err(|_| PacketError::UnknownEMsg(EMsg::strip_protobuf_flag(EMsg::extract_varint(raw_message_bytes))))?;
         let raw_data = EMsg::strip_message(raw_message_bytes);
 
         let (header, body) = match emsg {
             EMsg::ChannelEncryptRequest | EMsg::ChannelEncryptResponse | EMsg::ChannelEncryptResult => {
+                if raw_data.len() < STANDARD_HEADER_SIZE {
+                    return Err(PacketError::Malformed);
+                }
                 let (header, body) = StandardMessageHeader::split_from_bytes(raw_data);
                 let header = StandardMessageHeader::from_bytes(header);
-                println!("Found a Standard Header.");
-                println!("Header bytes: {:?} Body bytes: {:?}", header, body);
                 (MessageHeaderWrapper::Std(header), body)
             }
             // We can only check with the raw bytes, with the EMsg still inside
             _ if EMsg::is_protobuf(raw_message_bytes) => {
-                println!("Found a Protobuf Header.");
+                if raw_data.len() < PROTO_HEADER_LEN_SIZE
+                    || raw_data.len() - PROTO_HEADER_LEN_SIZE < EMsg::extract_varint(raw_data) as usize
+                {
+                    return Err(PacketError::Malformed);
+                }
                 let (header, body) = CMsgProtoBufHeader::split_from_bytes(raw_data);
-                let header = CMsgProtoBufHeader::parse_from_bytes(header).unwrap();
+                let header = CMsgProtoBufHeader::parse_from_bytes(header).map_err(|_| PacketError::Malformed)?;
                 (MessageHeaderWrapper::Proto(header), body)
             }
             _ => {
+                if raw_data.len() < EXTENDED_HEADER_SIZE {
+                    return Err(PacketError::Malformed);
+                }
                 let (header, body) = ExtendedMessageHeader::split_from_bytes(raw_data);
                 let header = ExtendedMessageHeader::from_bytes(header);
-                println!("Found a Extended Header.");
                 (MessageHeaderWrapper::Ext(header), body)
             }
         };
 
-        println!("Packet Message is: {:?}, {:?}, {:?}", &emsg, &header, body);
+        trace!("Packet Message is: {:?}, {:?}, {} bytes", &emsg, &header, body.len());
 
-        PacketMessage {
+        Ok(PacketMessage {
             emsg,
             header,
             data: body.to_vec(),
-        }
+        })
     }
 }
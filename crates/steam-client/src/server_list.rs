@@ -0,0 +1,429 @@
+//! CM servers we may connect to, and how well each of them served us so far.
+//!
+//! Servers come from a [DirectorySource], by default the Steam Directory Web API (`ISteamDirectory/GetCMList`). The
+//! last fetched list can be persisted to disk so cold starts do not depend on the Web API, and a small bundled list is
+//! used as a last resort.
+//!
+//! Servers that failed recently go to the back of the list, and among the rest the ones that accepted our connection
+//! the fastest come first.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde::Serialize;
+use tappet::response_types::GetCMListResponseBase;
+use tappet::ExecutorResponse;
+use tokio::time::Instant;
+
+use crate::errors::ServerListError;
+use crate::API_CLIENT;
+
+/// Servers shipped with the crate, in case neither the directory nor the cache are available.
+const BUNDLED_SERVERS: &str = include_str!("../assets/cm_servers.json");
+
+/// How many servers we ask the Steam Directory for.
+const DIRECTORY_MAX_COUNT: u32 = 200;
+
+/// How long a failure keeps a server at the back of the list.
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+/// How a CM server is talked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// Plain tcp, with VT01 framing.
+    Tcp,
+    /// Secure websockets, on `wss://<endpoint>/cmsocket/`.
+    WebSocket,
+}
+
+/// A CM server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CmServer {
+    /// Address of the server, as `host:port`.
+    pub endpoint: String,
+    /// How the server is talked to.
+    pub protocol: Protocol,
+}
+
+impl CmServer {
+    /// Server talked to through plain tcp.
+    pub fn tcp<T: Into<String>>(endpoint: T) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            protocol: Protocol::Tcp,
+        }
+    }
+
+    /// Server talked to through websockets.
+    pub fn websocket<T: Into<String>>(endpoint: T) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            protocol: Protocol::WebSocket,
+        }
+    }
+}
+
+impl fmt::Display for CmServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.endpoint, self.protocol)
+    }
+}
+
+/// Somewhere to fetch CM servers from.
+#[async_trait]
+pub trait DirectorySource: fmt::Debug + Send + Sync {
+    /// Fetches servers close to the given cell id, or to wherever the request comes from.
+    async fn fetch(&self, cell_id: Option<u32>) -> Result<Vec<CmServer>, ServerListError>;
+}
+
+/// The Steam Directory Web API.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SteamDirectory;
+
+#[async_trait]
+impl DirectorySource for SteamDirectory {
+    async fn fetch(&self, cell_id: Option<u32>) -> Result<Vec<CmServer>, ServerListError> {
+        let cm_list: GetCMListResponseBase = API_CLIENT
+            .get()
+            .ISteamDirectory()
+            .GetCMList(cell_id, Some(DIRECTORY_MAX_COUNT))
+            .execute_with_response()
+            .await?;
+
+        parse_cm_list(cm_list)
+    }
+}
+
+fn parse_cm_list(cm_list: GetCMListResponseBase) -> Result<Vec<CmServer>, ServerListError> {
+    let response = cm_list.response;
+    if response.result != 1 {
+        return Err(ServerListError::Directory(response.message));
+    }
+
+    let tcp_servers = response.serverlist.into_iter().map(CmServer::tcp);
+    let websocket_servers = response.serverlist_websockets.into_iter().map(CmServer::websocket);
+    Ok(tcp_servers.chain(websocket_servers).collect())
+}
+
+/// How a server served us recently.
+#[derive(Debug, Clone, Copy, Default)]
+struct Health {
+    latency: Option<Duration>,
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl Health {
+    /// Failures that still count against the server.
+    fn recent_failures(&self) -> u32 {
+        match self.last_failure {
+            Some(last_failure) if last_failure.elapsed() < FAILURE_COOLDOWN => self.failures,
+            _ => 0,
+        }
+    }
+}
+
+/// CM servers, ranked by how well they served us.
+#[derive(Debug)]
+pub struct ServerList {
+    directory: Box<dyn DirectorySource>,
+    cache_path: Option<PathBuf>,
+    /// Kept in the order the directory returned them, ranking only happens when picking.
+    servers: Mutex<Vec<CmServer>>,
+    health: Mutex<HashMap<CmServer, Health>>,
+}
+
+impl Default for ServerList {
+    fn default() -> Self {
+        Self::new(SteamDirectory)
+    }
+}
+
+impl ServerList {
+    /// Empty list, filled from `directory` on [ServerList::refresh].
+    pub fn new<D: DirectorySource + 'static>(directory: D) -> Self {
+        Self {
+            directory: Box::new(directory),
+            cache_path: None,
+            servers: Mutex::new(vec![]),
+            health: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// List starting with the given servers. The Steam Directory is only asked for more if none of them can be
+    /// talked to with the transport in use.
+    pub fn with_servers<I: IntoIterator<Item = CmServer>>(servers: I) -> Self {
+        let server_list = Self::default();
+        server_list.replace(servers.into_iter().collect());
+        server_list
+    }
+
+    /// Persists every fetched list to `path`, and reads it back when the directory cannot be reached.
+    pub fn with_cache<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.cache_path = Some(path.into());
+        self
+    }
+
+    /// Fetches servers from the directory.
+    ///
+    /// If it fails, servers are read from the cache, or from the bundled list as a last resort. Only fails if there
+    /// is no server to be found anywhere.
+    pub async fn refresh(&self, cell_id: Option<u32>) -> Result<(), ServerListError> {
+        let servers = match self.directory.fetch(cell_id).await {
+            Ok(servers) if !servers.is_empty() => {
+                if let Err(err) = self.write_cache(&servers).await {
+                    warn!("Could not persist CM servers: {}", err);
+                }
+                servers
+            }
+            fetched => {
+                let reason = fetched.err().map(|err| err.to_string()).unwrap_or_default();
+                warn!("Could not fetch CM servers from the directory: {}", reason);
+
+                match self.read_cache().await {
+                    Ok(servers) if !servers.is_empty() => servers,
+                    _ => bundled_servers(),
+                }
+            }
+        };
+
+        if servers.is_empty() {
+            return Err(ServerListError::NoServers);
+        }
+        self.replace(servers);
+        Ok(())
+    }
+
+    fn replace(&self, servers: Vec<CmServer>) {
+        debug!("Using {} CM servers.", servers.len());
+        *self.servers.lock().unwrap() = servers;
+    }
+
+    async fn read_cache(&self) -> Result<Vec<CmServer>, ServerListError> {
+        match self.cache_path.clone() {
+            Some(path) => Ok(serde_json::from_slice(&blocking(move || std::fs::read(path)).await?)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    async fn write_cache(&self, servers: &[CmServer]) -> Result<(), ServerListError> {
+        if let Some(path) = self.cache_path.clone() {
+            let contents = serde_json::to_vec_pretty(servers)?;
+            blocking(move || std::fs::write(path, contents)).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if there is no server for the given protocol.
+    pub fn is_empty(&self, protocol: Protocol) -> bool {
+        !self
+            .servers
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|server| server.protocol == protocol)
+    }
+
+    /// Every server for the given protocol, best first.
+    pub fn ranked(&self, protocol: Protocol) -> Vec<CmServer> {
+        let health = self.health.lock().unwrap();
+        let mut servers: Vec<(CmServer, Health)> = self
+            .servers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|server| server.protocol == protocol)
+            .map(|server| (server.clone(), health.get(server).copied().unwrap_or_default()))
+            .collect();
+
+        // stable, so servers we know nothing about keep the directory order, which already favours nearby ones
+        servers.sort_by_key(|(_, health)| (health.recent_failures(), health.latency.is_none(), health.latency));
+        servers.into_iter().map(|(server, _)| server).collect()
+    }
+
+    /// Best server for the given protocol, if any.
+    pub fn pick(&self, protocol: Protocol) -> Option<CmServer> {
+        self.ranked(protocol).into_iter().next()
+    }
+
+    /// Best server for the given protocol other than `previous`, unless it is the only one.
+    ///
+    /// Moves on from a server whose connection dropped without holding the drop against it.
+    pub fn pick_after(&self, protocol: Protocol, previous: Option<&CmServer>) -> Option<CmServer> {
+        let ranked = self.ranked(protocol);
+        let next = ranked.iter().find(|server| Some(*server) != previous);
+        next.or_else(|| ranked.first()).cloned()
+    }
+
+    /// Records that `server` accepted our connection, and how long it took.
+    pub fn mark_good(&self, server: &CmServer, latency: Duration) {
+        let mut health = self.health.lock().unwrap();
+        let health = health.entry(server.clone()).or_default();
+        health.latency = Some(latency);
+        health.failures = 0;
+        health.last_failure = None;
+    }
+
+    /// Records that `server` failed us, so others are tried first for a while.
+    pub fn mark_bad(&self, server: &CmServer) {
+        let mut health = self.health.lock().unwrap();
+        let health = health.entry(server.clone()).or_default();
+        health.failures = health.recent_failures() + 1;
+        health.last_failure = Some(Instant::now());
+    }
+}
+
+/// Runs disk work away from the runtime, so it does not hold up the connection to Steam.
+async fn blocking<T, F>(work: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work).await.map_err(io::Error::other)?
+}
+
+fn bundled_servers() -> Vec<CmServer> {
+    serde_json::from_str(BUNDLED_SERVERS).expect("bundled CM servers are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use tappet::response_types::GetCMListServerLists;
+
+    use super::*;
+
+    /// Directory that can be switched off.
+    #[derive(Debug, Clone, Default)]
+    struct FakeDirectory {
+        down: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl DirectorySource for FakeDirectory {
+        async fn fetch(&self, _cell_id: Option<u32>) -> Result<Vec<CmServer>, ServerListError> {
+            if self.down.load(Ordering::Acquire) {
+                return Err(ServerListError::Directory("down".to_string()));
+            }
+            Ok(vec![
+                CmServer::tcp("10.0.0.1:27017"),
+                CmServer::tcp("10.0.0.2:27017"),
+                CmServer::websocket("cm.example.com:443"),
+            ])
+        }
+    }
+
+    #[test]
+    fn cm_list_is_split_by_protocol() {
+        let cm_list = GetCMListResponseBase {
+            response: GetCMListServerLists {
+                serverlist: vec!["10.0.0.1:27017".to_string()],
+                serverlist_websockets: vec!["cm.example.com:443".to_string()],
+                result: 1,
+                message: String::new(),
+            },
+        };
+
+        let servers = parse_cm_list(cm_list).unwrap();
+        assert_eq!(
+            servers,
+            vec![
+                CmServer::tcp("10.0.0.1:27017"),
+                CmServer::websocket("cm.example.com:443")
+            ]
+        );
+    }
+
+    #[test]
+    fn bundled_servers_parse() {
+        let servers = bundled_servers();
+        assert!(servers.iter().any(|server| server.protocol == Protocol::Tcp));
+        assert!(servers.iter().any(|server| server.protocol == Protocol::WebSocket));
+    }
+
+    #[tokio::test]
+    async fn cache_is_used_when_the_directory_is_down() {
+        let cache_path = std::env::temp_dir().join(format!("cm_servers_{}.json", std::process::id()));
+        let directory = FakeDirectory::default();
+
+        let server_list = ServerList::new(directory.clone()).with_cache(&cache_path);
+        server_list.refresh(None).await.unwrap();
+        assert_eq!(
+            server_list.ranked(Protocol::WebSocket),
+            vec![CmServer::websocket("cm.example.com:443")]
+        );
+
+        directory.down.store(true, Ordering::Release);
+        let cold_start = ServerList::new(directory).with_cache(&cache_path);
+        cold_start.refresh(None).await.unwrap();
+        assert_eq!(cold_start.ranked(Protocol::Tcp), server_list.ranked(Protocol::Tcp));
+
+        std::fs::remove_file(cache_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bundled_servers_are_the_last_resort() {
+        let directory = FakeDirectory::default();
+        directory.down.store(true, Ordering::Release);
+
+        let server_list = ServerList::new(directory).with_cache("/nonexistent/cm_servers.json");
+        server_list.refresh(None).await.unwrap();
+        assert_eq!(server_list.ranked(Protocol::Tcp).len(), 3);
+        // hosts that only allow 443 out must be able to start from nothing too
+        assert!(!server_list.ranked(Protocol::WebSocket).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_servers_go_last_until_forgiven() {
+        let server_list = ServerList::new(FakeDirectory::default());
+        server_list.refresh(None).await.unwrap();
+        let first = CmServer::tcp("10.0.0.1:27017");
+        let second = CmServer::tcp("10.0.0.2:27017");
+
+        assert_eq!(server_list.pick(Protocol::Tcp), Some(first.clone()));
+        server_list.mark_bad(&first);
+        assert_eq!(server_list.pick(Protocol::Tcp), Some(second.clone()));
+
+        tokio::time::advance(FAILURE_COOLDOWN).await;
+        assert_eq!(server_list.pick(Protocol::Tcp), Some(first));
+    }
+
+    #[tokio::test]
+    async fn previous_server_is_skipped_once() {
+        let server_list = ServerList::new(FakeDirectory::default());
+        server_list.refresh(None).await.unwrap();
+        let first = CmServer::tcp("10.0.0.1:27017");
+        let second = CmServer::tcp("10.0.0.2:27017");
+        let websocket = CmServer::websocket("cm.example.com:443");
+
+        server_list.mark_good(&first, Duration::from_millis(20));
+        assert_eq!(server_list.pick_after(Protocol::Tcp, Some(&first)), Some(second));
+        assert_eq!(server_list.pick(Protocol::Tcp), Some(first));
+        assert_eq!(
+            server_list.pick_after(Protocol::WebSocket, Some(&websocket)),
+            Some(websocket)
+        );
+    }
+
+    #[tokio::test]
+    async fn faster_servers_come_first() {
+        let server_list = ServerList::new(FakeDirectory::default());
+        server_list.refresh(None).await.unwrap();
+        let first = CmServer::tcp("10.0.0.1:27017");
+        let second = CmServer::tcp("10.0.0.2:27017");
+
+        server_list.mark_good(&first, Duration::from_millis(300));
+        server_list.mark_good(&second, Duration::from_millis(20));
+        assert_eq!(server_list.ranked(Protocol::Tcp), vec![second, first]);
+    }
+}
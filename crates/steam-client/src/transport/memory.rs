@@ -0,0 +1,130 @@
+use std::io;
+
+use async_trait::async_trait;
+use tokio::io::duplex;
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+
+use crate::errors::ConnectionError;
+use crate::messages::codec::PacketMessageCodec;
+use crate::server_list::Protocol;
+use crate::transport::BoxMessageStream;
+use crate::transport::Transport;
+
+/// How many bytes may be in flight on each side of an in-memory connection.
+const DUPLEX_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Connections that never leave the process, framed like tcp over an in-memory duplex.
+///
+/// Every connection it opens comes out of the paired [MemoryListener], where whoever plays the CM picks it up. It
+/// takes the place of tcp CM servers, so the server list should hold tcp ones.
+#[derive(Debug, Clone)]
+pub struct MemoryTransport {
+    connections: mpsc::UnboundedSender<(String, BoxMessageStream)>,
+    secure: bool,
+    public_key: Option<Vec<u8>>,
+}
+
+/// The CM side of a [MemoryTransport].
+#[derive(Debug)]
+pub struct MemoryListener {
+    connections: mpsc::UnboundedReceiver<(String, BoxMessageStream)>,
+}
+
+impl MemoryTransport {
+    /// Transport whose connections go through the encryption handshake, like tcp ones.
+    pub fn new() -> (Self, MemoryListener) {
+        Self::with_security(false)
+    }
+
+    /// Transport whose connections skip the encryption handshake, like websocket ones, so both sides read each other
+    /// in plain.
+    pub fn without_encryption() -> (Self, MemoryListener) {
+        Self::with_security(true)
+    }
+
+    fn with_security(secure: bool) -> (Self, MemoryListener) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let transport = Self {
+            connections: sender,
+            secure,
+            public_key: None,
+        };
+        (transport, MemoryListener { connections: receiver })
+    }
+
+    /// The CM on the other side encrypts with its own keypair, whose public key, in PEM, is `public_key`.
+    pub fn with_public_key(mut self, public_key: &[u8]) -> Self {
+        self.public_key = Some(public_key.to_vec());
+        self
+    }
+}
+
+impl MemoryListener {
+    /// Waits for the next connection, returning the endpoint it was meant for and the CM side of it.
+    ///
+    /// Returns `None` once every [MemoryTransport] paired with it is gone.
+    pub async fn accept(&mut self) -> Option<(String, BoxMessageStream)> {
+        self.connections.recv().await
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    fn protocol(&self) -> Protocol {
+        Protocol::Tcp
+    }
+
+    fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    fn public_key(&self) -> Option<&[u8]> {
+        self.public_key.as_deref()
+    }
+
+    async fn connect(&self, endpoint: &str) -> Result<BoxMessageStream, ConnectionError> {
+        let (client, server) = duplex(DUPLEX_BUFFER_SIZE);
+        let server: BoxMessageStream = Box::new(Framed::new(server, PacketMessageCodec::default()));
+
+        self.connections
+            .send((endpoint.to_string(), server))
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(Box::new(Framed::new(client, PacketMessageCodec::default())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use futures::StreamExt;
+    use steam_language_gen::generated::enums::EMsg;
+    use steam_language_gen::generated::messages::MsgClientChatEnter;
+    use steam_language_gen::SerializableBytes;
+
+    use super::*;
+    use crate::messages::message::ClientMessage;
+
+    #[tokio::test]
+    async fn both_sides_exchange_whole_messages() {
+        let (transport, mut listener) = MemoryTransport::new();
+
+        let mut client = transport.connect("cm1:27017").await.unwrap();
+        let (endpoint, mut server) = listener.accept().await.unwrap();
+        assert_eq!(endpoint, "cm1:27017");
+
+        let message = ClientMessage::<MsgClientChatEnter>::new().to_bytes();
+        client.send(message.clone()).await.unwrap();
+        assert_eq!(server.next().await.unwrap().unwrap().emsg(), EMsg::ClientChatEnter);
+        server.send(message).await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap().emsg(), EMsg::ClientChatEnter);
+    }
+
+    #[tokio::test]
+    async fn refused_once_the_listener_is_gone() {
+        let (transport, listener) = MemoryTransport::new();
+        drop(listener);
+
+        assert!(transport.connect("cm1:27017").await.is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! How messages get to and from CM servers.
+//!
+//! A [Transport] opens connections to CMs, and every connection it opens is a [MessageStream], that reads and writes
+//! whole messages. Everything above it, from the encryption handshake to heartbeats and handlers, is shared by all
+//! transports, so which one the client uses is picked at runtime:
+//!
+//! - [TcpTransport], frames messages with the VT01 magic bytes over plain tcp.
+//! - [WebSocketTransport], sends one message per binary frame over secure websockets. Requires the `websockets`
+//!   feature.
+//! - [MemoryTransport], frames messages like tcp over an in-memory duplex, so a fake CM can be scripted without a
+//!   network.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use futures::Sink;
+use futures::Stream;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio_util::codec::Framed;
+
+pub use self::memory::MemoryListener;
+pub use self::memory::MemoryTransport;
+pub use self::tcp::TcpTransport;
+#[cfg(feature = "websockets")]
+pub use self::websocket::WebSocketTransport;
+use crate::errors::ConnectionError;
+use crate::errors::PacketError;
+use crate::messages::codec::PacketMessageCodec;
+use crate::messages::packet::PacketMessage;
+use crate::server_list::Protocol;
+
+mod memory;
+mod tcp;
+#[cfg(feature = "websockets")]
+mod websocket;
+
+/// Whole messages read from and written to a CM, whatever carries them.
+///
+/// Reads decoded [PacketMessage]s, and writes serialized messages.
+pub trait MessageStream:
+    Stream<Item = Result<PacketMessage, PacketError>>
+    + Sink<Vec<u8>, Error = PacketError>
+    + fmt::Debug
+    + Unpin
+    + Send
+    + 'static
+{
+    /// Every message read or written from now on is encrypted with `session_key`.
+    fn enable_encryption(&mut self, session_key: &[u8]);
+}
+
+/// A connection opened by a [Transport].
+pub type BoxMessageStream = Box<dyn MessageStream>;
+
+impl<S> MessageStream for Framed<S, PacketMessageCodec>
+where
+    S: AsyncRead + AsyncWrite + fmt::Debug + Unpin + Send + 'static,
+{
+    fn enable_encryption(&mut self, session_key: &[u8]) {
+        self.codec_mut().enable_encryption(session_key);
+    }
+}
+
+/// Opens connections to CM servers.
+#[async_trait]
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Which of the CM servers this transport can talk to.
+    fn protocol(&self) -> Protocol;
+
+    /// Whether connections are secure already, in which case Steam skips the encryption handshake.
+    fn is_secure(&self) -> bool {
+        false
+    }
+
+    /// Public key of the CMs this transport reaches, in PEM, or `None` if they are Steam's.
+    fn public_key(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Opens a connection to the CM at `endpoint`, given as `host:port`.
+    async fn connect(&self, endpoint: &str) -> Result<BoxMessageStream, ConnectionError>;
+}
@@ -0,0 +1,29 @@
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use crate::errors::ConnectionError;
+use crate::messages::codec::PacketMessageCodec;
+use crate::server_list::Protocol;
+use crate::transport::BoxMessageStream;
+use crate::transport::Transport;
+
+/// Plain tcp, with every message framed by its length and the VT01 magic bytes.
+///
+/// Steam asks to encrypt the channel right after connecting.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    fn protocol(&self) -> Protocol {
+        Protocol::Tcp
+    }
+
+    async fn connect(&self, endpoint: &str) -> Result<BoxMessageStream, ConnectionError> {
+        trace!("Connecting to ip: {}", endpoint);
+
+        let stream = TcpStream::connect(endpoint).await?;
+        Ok(Box::new(Framed::new(stream, PacketMessageCodec::default())))
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use async_trait::async_trait;
+use futures::ready;
+use futures::Sink;
+use futures::SinkExt;
+use futures::Stream;
+use futures::StreamExt;
+use steam_crypto::symm::symmetric_decrypt;
+use steam_crypto::symm::symmetric_encrypt_hmac_iv;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::errors::ConnectionError;
+use crate::errors::PacketError;
+use crate::messages::packet::PacketMessage;
+use crate::server_list::Protocol;
+use crate::transport::BoxMessageStream;
+use crate::transport::MessageStream;
+use crate::transport::Transport;
+
+/// Secure websockets on `wss://<endpoint>/cmsocket/`, for networks where only port 443 gets through.
+///
+/// Every binary frame carries exactly one message, without the VT01 framing used over tcp. TLS already protects the
+/// channel, so Steam skips the encryption handshake.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WebSocketTransport;
+
+/// Turns binary websocket frames into messages, and messages into binary frames.
+struct WebSocketMessages<S> {
+    inner: WebSocketStream<S>,
+    /// Only present if a CM ever asks to encrypt the channel on top of TLS.
+    session_key: Option<Vec<u8>>,
+}
+
+impl<S> fmt::Debug for WebSocketMessages<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketMessages")
+            .field("encrypted", &self.session_key.is_some())
+            .finish()
+    }
+}
+
+impl<S> WebSocketMessages<S> {
+    fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            session_key: None,
+        }
+    }
+}
+
+impl<S> Stream for WebSocketMessages<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<PacketMessage, PacketError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let data = match ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(data))) => data,
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(None),
+                // pings are answered by tungstenite itself, and Steam never sends text
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+            };
+
+            let data = match &self.session_key {
+                Some(key) => match symmetric_decrypt(&data, key, true) {
+                    Ok(data) => data,
+                    Err(err) => return Poll::Ready(Some(Err(err.into()))),
+                },
+                None => data,
+            };
+
+            match PacketMessage::from_raw_bytes(&data) {
+                Ok(packet_message) => return Poll::Ready(Some(Ok(packet_message))),
+                // the frame itself was fine, so we can still move on to the next one
+                Err(err) => warn!("Skipping undecodable message: {}", err),
+            }
+        }
+    }
+}
+
+impl<S> Sink<Vec<u8>> for WebSocketMessages<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Error = PacketError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready_unpin(cx).map_err(Into::into)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
+        let item = match &self.session_key {
+            Some(key) => symmetric_encrypt_hmac_iv(&item, key)?,
+            None => item,
+        };
+        self.inner.start_send_unpin(Message::Binary(item)).map_err(Into::into)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_flush_unpin(cx).map_err(Into::into)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx).map_err(Into::into)
+    }
+}
+
+impl<S> MessageStream for WebSocketMessages<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn enable_encryption(&mut self, session_key: &[u8]) {
+        self.session_key = Some(session_key.to_vec());
+    }
+}
+
+#[async_trait]
+impl Transport for WebSocketTransport {
+    fn protocol(&self) -> Protocol {
+        Protocol::WebSocket
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+
+    async fn connect(&self, endpoint: &str) -> Result<BoxMessageStream, ConnectionError> {
+        let url = format!("wss://{}/cmsocket/", endpoint);
+        trace!("Connecting to addr: {}", url);
+
+        let (stream, _) = connect_async(url.as_str()).await.map_err(PacketError::from)?;
+        Ok(Box::new(WebSocketMessages::new(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use steam_language_gen::generated::enums::EMsg;
+    use steam_language_gen::generated::messages::MsgClientChatEnter;
+    use steam_language_gen::SerializableBytes;
+    use tokio::io::duplex;
+    use tokio::io::DuplexStream;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    use super::*;
+    use crate::connection::EncryptionState;
+    use crate::connection::SteamConnection;
+    use crate::messages::message::ClientMessage;
+    use crate::server_list::DirectorySource;
+    use crate::server_list::Protocol;
+    use crate::server_list::SteamDirectory;
+
+    async fn in_memory_connection() -> (SteamConnection, WebSocketStream<DuplexStream>) {
+        let (client, server) = duplex(64 * 1024);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+
+        let messages = Box::new(WebSocketMessages::new(client));
+        (SteamConnection::new(messages, "in-memory".to_string(), true), server)
+    }
+
+    #[tokio::test]
+    async fn every_binary_frame_is_one_message() {
+        let (connection, mut server) = in_memory_connection().await;
+        let (handle, _task) = connection.spawn();
+        let mut packets = handle.subscribe();
+
+        // nothing to negotiate, so this goes out right away
+        let message = ClientMessage::<MsgClientChatEnter>::new();
+        let plain_bytes = message.to_bytes();
+        handle.send(message).unwrap();
+        match server.next().await.unwrap().unwrap() {
+            Message::Binary(data) => assert_eq!(data, plain_bytes),
+            frame => panic!("expected a binary frame, got {:?}", frame),
+        }
+
+        server.send(Message::Ping(vec![1, 2, 3])).await.unwrap();
+        server.send(Message::Binary(plain_bytes)).await.unwrap();
+        assert_eq!(packets.recv().await.unwrap().emsg(), EMsg::ClientChatEnter);
+    }
+
+    #[tokio::test]
+    async fn close_frame_ends_the_connection() {
+        let (connection, mut server) = in_memory_connection().await;
+        let (handle, task) = connection.spawn();
+
+        server.close(None).await.unwrap();
+
+        assert!(matches!(task.await.unwrap(), Err(ConnectionError::Dropped)));
+        assert_eq!(handle.encryption_state(), EncryptionState::Disconnected);
+    }
+
+    #[tokio::test]
+    #[ignore = "talks to live Steam"]
+    async fn connect_to_ws_server() {
+        let servers = SteamDirectory.fetch(None).await.unwrap();
+        let server = servers
+            .into_iter()
+            .find(|server| server.protocol == Protocol::WebSocket)
+            .unwrap();
+
+        let steam_connection = WebSocketTransport.connect(&server.endpoint).await;
+        assert!(steam_connection.is_ok())
+    }
+}
@@ -1,5 +1,13 @@
+use num::FromPrimitive;
+use steam_language_gen::generated::enums::EResult;
+
 /// Read a valid utf8 string until the null terminator.
 pub fn str_from_u8_nul_utf8(utf8_src: &[u8]) -> Result<&str, std::str::Utf8Error> {
     let nul_range_end = utf8_src.iter().position(|&c| c == b'\0').unwrap_or(utf8_src.len()); // default to length if no `\0` present
     ::std::str::from_utf8(&utf8_src[0..nul_range_end])
 }
+
+/// Converts the raw `eresult` found on protobuf messages, falling back to `Invalid` for values we don't know.
+pub fn eresult_from_i32(value: i32) -> EResult {
+    EResult::from_i32(value).unwrap_or(EResult::Invalid)
+}
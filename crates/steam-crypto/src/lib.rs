@@ -23,12 +23,18 @@ extern crate lazy_static;
 #[macro_use]
 extern crate lazy_static_include;
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::BufMut;
+use bytes::Bytes;
+use bytes::BytesMut;
 use crc32fast::Hasher;
-use openssl::{error::ErrorStack, hash::MessageDigest, rsa::Padding, sign::Verifier};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::rsa::Padding;
+use openssl::sign::Signer;
+use openssl::sign::Verifier;
 use rand::prelude::*;
 
-mod symm;
+pub mod symm;
 lazy_static_include_bytes!(STEAM_KEY, "assets/steam_public.pem");
 
 #[derive(Debug)]
@@ -42,17 +48,42 @@ pub struct SessionKeys {
     pub encrypted: Vec<u8>,
 }
 
+impl SessionKeys {
+    /// The plain 32 bytes AES key negotiated with Steam, without the appended nonce.
+    pub fn session_key(&self) -> &[u8] {
+        &self.plain_text[..SESSION_KEY_SIZE]
+    }
+}
+
 pub fn verify_signature(data: &[u8], signature: &[u8]) -> Result<bool, ErrorStack> {
+    let steam_key_bytes: &'static [u8] = *STEAM_KEY;
+    verify_signature_with(steam_key_bytes, data, signature)
+}
+
+/// Same as [verify_signature], but checks the RSA-SHA1 `signature` against `public_key`, in PEM, instead of Steam's.
+pub fn verify_signature_with(public_key: &[u8], data: &[u8], signature: &[u8]) -> Result<bool, ErrorStack> {
     // standard algorithm is RSA-SHA1
     // but this should be selectable
-    let steam_key_bytes: &'static [u8] = *STEAM_KEY;
-    let steam_pkey = openssl::pkey::PKey::public_key_from_pem(&steam_key_bytes)?;
+    let public_key = openssl::pkey::PKey::public_key_from_pem(public_key)?;
 
-    let mut verifier = Verifier::new(MessageDigest::sha1(), &steam_pkey)?;
-    verifier.update(&data)?;
-    verifier.verify(&signature)
+    let mut verifier = Verifier::new(MessageDigest::sha1(), &public_key)?;
+    verifier.update(data)?;
+    verifier.verify(signature)
 }
 
+/// Signs `data` with RSA-SHA1, given a private key in PEM. Reversed by [verify_signature_with].
+///
+/// Used to act as Steam, such as when building manifests in tests.
+pub fn sign_with(private_key: &[u8], data: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let private_key = openssl::pkey::PKey::private_key_from_pem(private_key)?;
+
+    let mut signer = Signer::new(MessageDigest::sha1(), &private_key)?;
+    signer.update(data)?;
+    signer.sign_to_vec()
+}
+
+const SESSION_KEY_SIZE: usize = 32;
+
 /// Returns SessionsKeys struct.
 ///
 /// Generates a 32 byte random blob of data and encrypts it with RSA 1024
@@ -60,8 +91,15 @@ pub fn verify_signature(data: &[u8], signature: &[u8]) -> Result<bool, ErrorStac
 ///
 /// If there is a nonce, it gets concatenated after the generated 32 bytes
 pub fn generate_session_key(nonce: Option<&[u8]>) -> Result<SessionKeys, ErrorStack> {
-    let mut random_bytes_array = vec![0u8; 32];
-    let mut encrypted_array = vec![0u8; 256];
+    let steam_key: &'static [u8] = *STEAM_KEY;
+    generate_session_key_with(steam_key, nonce)
+}
+
+/// Same as [generate_session_key], but encrypts the blob with `public_key`, in PEM, instead of Steam's.
+///
+/// Used to talk to servers that are not Steam, such as a fake CM in tests.
+pub fn generate_session_key_with(public_key: &[u8], nonce: Option<&[u8]>) -> Result<SessionKeys, ErrorStack> {
+    let mut random_bytes_array = vec![0u8; SESSION_KEY_SIZE];
 
     thread_rng().fill_bytes(&mut random_bytes_array);
 
@@ -69,9 +107,10 @@ pub fn generate_session_key(nonce: Option<&[u8]>) -> Result<SessionKeys, ErrorSt
         random_bytes_array.extend(nonce);
     }
 
-    let steam_key: &'static [u8] = *STEAM_KEY;
-    let public_key = openssl::rsa::Rsa::public_key_from_pem(&steam_key)?;
-    public_key.public_encrypt(&random_bytes_array, &mut encrypted_array, Padding::PKCS1_OAEP)?;
+    let public_key = openssl::rsa::Rsa::public_key_from_pem(public_key)?;
+    let mut encrypted_array = vec![0u8; public_key.size() as usize];
+    let encrypted_len = public_key.public_encrypt(&random_bytes_array, &mut encrypted_array, Padding::PKCS1_OAEP)?;
+    encrypted_array.truncate(encrypted_len);
 
     Ok(SessionKeys {
         plain_text: random_bytes_array,
@@ -79,6 +118,34 @@ pub fn generate_session_key(nonce: Option<&[u8]>) -> Result<SessionKeys, ErrorSt
     })
 }
 
+/// Reverses [generate_session_key_with], given the private key matching the public one, in PEM.
+///
+/// Returns the plain blob, that is the session key followed by the nonce.
+pub fn decrypt_session_key(private_key: &[u8], encrypted: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let private_key = openssl::rsa::Rsa::private_key_from_pem(private_key)?;
+
+    let mut decrypted = vec![0u8; private_key.size() as usize];
+    let decrypted_len = private_key.private_decrypt(encrypted, &mut decrypted, Padding::PKCS1_OAEP)?;
+    decrypted.truncate(decrypted_len);
+    Ok(decrypted)
+}
+
+/// Encrypts an account password for the `Authentication` service, as Steam expects it on
+/// `BeginAuthSessionViaCredentials`.
+///
+/// `modulus` and `exponent` are the hex encoded parts of the key returned by
+/// `GetPasswordRSAPublicKey`. The password is encrypted with RSA PKCS#1 and returned as base64.
+pub fn encrypt_password(password: &str, modulus: &str, exponent: &str) -> Result<String, ErrorStack> {
+    let modulus = openssl::bn::BigNum::from_hex_str(modulus)?;
+    let exponent = openssl::bn::BigNum::from_hex_str(exponent)?;
+    let public_key = openssl::rsa::Rsa::from_public_components(modulus, exponent)?;
+
+    let mut encrypted = vec![0u8; public_key.size() as usize];
+    let encrypted_len = public_key.public_encrypt(password.as_bytes(), &mut encrypted, Padding::PKCS1)?;
+    encrypted.truncate(encrypted_len);
+    Ok(openssl::base64::encode_block(&encrypted))
+}
+
 /// Performs CRC32 on an input byte array
 pub fn crc_hash(input: &[u8]) -> Vec<u8> {
     let mut hasher = Hasher::new();
@@ -90,16 +157,75 @@ pub fn crc_hash(input: &[u8]) -> Vec<u8> {
     checksum_bytes.to_vec()
 }
 
-/// Returns both the `SessionKeys` and a ready to send payload for MsgEncryptRequest
-pub fn generate_encrypt_request_handshake(payload: &[u8]) -> (SessionKeys, Bytes) {
-    let session_keys = generate_session_key(Some(payload)).unwrap();
-    let temp_encrypted_sessionkey = &session_keys.encrypted[..128];
+/// Performs SHA-1 on an input byte array, as Steam does to name and check depot chunks
+pub fn sha1_hash(input: &[u8]) -> [u8; 20] {
+    openssl::sha::sha1(input)
+}
 
-    let mut response = BytesMut::with_capacity(1024);
-    let key_hash = crc_hash(temp_encrypted_sessionkey);
+/// Returns both the `SessionKeys` and a ready to send payload for MsgEncryptRequest
+pub fn generate_encrypt_request_handshake(payload: &[u8]) -> Result<(SessionKeys, Bytes), ErrorStack> {
+    let steam_key: &'static [u8] = *STEAM_KEY;
+    generate_encrypt_request_handshake_with(steam_key, payload)
+}
 
-    response.put(temp_encrypted_sessionkey);
+/// Same as [generate_encrypt_request_handshake], but for a server whose public key, in PEM, is not Steam's.
+///
+/// The encrypted session key is as long as the key is, so any key size works.
+pub fn generate_encrypt_request_handshake_with(
+    public_key: &[u8],
+    payload: &[u8],
+) -> Result<(SessionKeys, Bytes), ErrorStack> {
+    let session_keys = generate_session_key_with(public_key, Some(payload))?;
+    let encrypted_session_key = &session_keys.encrypted[..];
+
+    let mut response = BytesMut::with_capacity(encrypted_session_key.len() + 8);
+    let key_hash = crc_hash(encrypted_session_key);
+
+    response.put(encrypted_session_key);
     response.put(key_hash.as_ref());
     response.put_u32(0);
-    (session_keys, response.freeze())
+    Ok((session_keys, response.freeze()))
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::rsa::Rsa;
+
+    use super::*;
+
+    #[test]
+    fn handshake_keeps_the_whole_session_key_of_bigger_keys() {
+        let key = Rsa::generate(2048).unwrap();
+        let public_key = key.public_key_to_pem().unwrap();
+        let private_key = key.private_key_to_pem().unwrap();
+
+        let (session_keys, payload) = generate_encrypt_request_handshake_with(&public_key, b"challenge").unwrap();
+        let (encrypted_key, trailer) = payload.split_at(payload.len() - 8);
+        assert_eq!(encrypted_key.len(), 256);
+        assert_eq!(&trailer[..4], crc_hash(encrypted_key).as_slice());
+        assert_eq!(
+            decrypt_session_key(&private_key, encrypted_key).unwrap(),
+            session_keys.plain_text
+        );
+    }
+
+    #[test]
+    fn passwords_are_encrypted_with_the_given_components() {
+        let key = Rsa::generate(2048).unwrap();
+        let modulus = key.n().to_hex_str().unwrap();
+        let exponent = key.e().to_hex_str().unwrap();
+
+        let encrypted = encrypt_password("hunter2", &modulus, &exponent).unwrap();
+        let encrypted = openssl::base64::decode_block(&encrypted).unwrap();
+        let mut decrypted = vec![0; key.size() as usize];
+        let decrypted_len = key.private_decrypt(&encrypted, &mut decrypted, Padding::PKCS1).unwrap();
+        assert_eq!(&decrypted[..decrypted_len], b"hunter2");
+
+        assert!(encrypt_password("hunter2", "not hex", &exponent).is_err());
+    }
+
+    #[test]
+    fn handshake_fails_on_bad_keys() {
+        assert!(generate_encrypt_request_handshake_with(b"not a key", b"challenge").is_err());
+    }
 }
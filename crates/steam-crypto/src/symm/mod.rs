@@ -1,103 +1,198 @@
+//! Symmetric encryption used by Steam after the channel handshake.
+//!
+//! Every message is encrypted with AES 256 CBC, prefixed by its IV encrypted with AES 256 ECB.
+
+use std::fmt;
+
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::sign::Signer;
+use openssl::symm::Cipher;
+use openssl::symm::Crypter;
+use openssl::symm::Mode;
+use rand::thread_rng;
+use rand::RngCore;
+
+const IV_SIZE: usize = 16;
+const HMAC_RANDOM_SIZE: usize = 3;
+const HMAC_KEY_SIZE: usize = 16;
+
+type Result<T> = std::result::Result<T, SymmetricError>;
+
+#[derive(Debug)]
+/// Errors from encrypting or decrypting a message with a session key.
+pub enum SymmetricError {
+    /// The underlying cipher failed, usually because of a bad key or padding.
+    Cipher(ErrorStack),
+    /// The message is too short to even contain an IV.
+    TooShort,
+    /// The HMAC embedded on the IV did not match the decrypted message.
+    InvalidHmac,
+    /// The key is too short to sign the message with.
+    ShortKey,
+}
 
-use openssl::{
-    error::ErrorStack,
-    hash::MessageDigest,
-    sign::Signer,
-    symm::{Cipher, Crypter, Mode},
-};
-use rand::{thread_rng, RngCore};
+impl fmt::Display for SymmetricError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymmetricError::Cipher(err) => write!(f, "cipher error: {}", err),
+            SymmetricError::TooShort => write!(f, "message is too short to hold an IV"),
+            SymmetricError::InvalidHmac => write!(f, "received invalid HMAC from remote host"),
+            SymmetricError::ShortKey => write!(f, "key is too short to sign an HMAC with"),
+        }
+    }
+}
 
-type Result<T> = std::result::Result<T, ErrorStack>;
+impl std::error::Error for SymmetricError {}
+
+impl From<ErrorStack> for SymmetricError {
+    fn from(err: ErrorStack) -> Self {
+        SymmetricError::Cipher(err)
+    }
+}
 
 /// Encrypt or decrypt a message with AES 256 CBC.
-pub fn cipher_message(
-    message: &[u8],
-    key: &[u8],
-    plain_iv: Option<&[u8]>,
-    mode: Mode,
-) -> Result<Vec<u8>> {
-    let mut message_cipher = Crypter::new(Cipher::aes_256_cbc(), mode, key, plain_iv)?;
-
-    let mut output_buffer: Vec<u8> = Vec::new();
-
-    message_cipher.update(&message, &mut output_buffer)?;
-    message_cipher.finalize(&mut output_buffer)?;
+pub fn cipher_message(message: &[u8], key: &[u8], plain_iv: Option<&[u8]>, mode: Mode) -> Result<Vec<u8>> {
+    let cipher = Cipher::aes_256_cbc();
+    let mut message_cipher = Crypter::new(cipher, mode, key, plain_iv)?;
+
+    let mut output_buffer: Vec<u8> = vec![0; message.len() + cipher.block_size()];
+
+    let mut count = message_cipher.update(message, &mut output_buffer)?;
+    count += message_cipher.finalize(&mut output_buffer[count..])?;
+    output_buffer.truncate(count);
     Ok(output_buffer)
 }
 
 /// Encrypt or decrypt an Initialization Vector with AES 256 ECB.
-fn cipher_iv_ecb(key: &[u8], plain_iv: Option<&[u8]>, mode: Mode) -> Result<Vec<u8>> {
-    let mut iv_cipher = Crypter::new(Cipher::aes_256_ecb(), mode, key, plain_iv).unwrap();
+fn cipher_iv_ecb(key: &[u8], iv: &[u8], mode: Mode) -> Result<Vec<u8>> {
+    let cipher = Cipher::aes_256_ecb();
+    let mut iv_cipher = Crypter::new(cipher, mode, key, None)?;
     iv_cipher.pad(false);
 
-    let mut output_buffer: Vec<u8> = Vec::new();
+    let mut output_buffer: Vec<u8> = vec![0; iv.len() + cipher.block_size()];
 
-    iv_cipher.update(plain_iv.unwrap(), &mut output_buffer)?;
-    iv_cipher.finalize(&mut output_buffer).unwrap();
+    let mut count = iv_cipher.update(iv, &mut output_buffer)?;
+    count += iv_cipher.finalize(&mut output_buffer[count..])?;
+    output_buffer.truncate(count);
     Ok(output_buffer)
 }
 
-pub fn symmetric_encrypt(input: &[u8], key: &[u8]) -> Vec<u8> {
-    let mut iv: [u8; 16] = [0; 16];
+/// Encrypt input with key, using a random IV.
+pub fn symmetric_encrypt(input: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+    let mut iv: [u8; IV_SIZE] = [0; IV_SIZE];
     thread_rng().fill_bytes(&mut iv);
 
-    symmetric_encrypt_with_iv(input, key, Option::from(&iv[..])).unwrap()
+    symmetric_encrypt_with_iv(input, key, &iv)
 }
 
-pub fn symmetric_encrypt_with_iv(
-    message: &[u8],
-    key: &[u8],
-    plain_iv: Option<&[u8]>,
-) -> Result<Vec<u8>> {
+/// Encrypt input with key and the supplied IV. The IV is itself encrypted and prepended to the output.
+pub fn symmetric_encrypt_with_iv(message: &[u8], key: &[u8], plain_iv: &[u8]) -> Result<Vec<u8>> {
     let encrypted_iv = cipher_iv_ecb(key, plain_iv, Mode::Encrypt)?;
-    let encrypted_message = cipher_message(message, key, plain_iv, Mode::Encrypt)?;
+    let encrypted_message = cipher_message(message, key, Some(plain_iv), Mode::Encrypt)?;
 
     let mut output = encrypted_iv;
     output.extend(encrypted_message.into_iter());
     Ok(output)
 }
 
+/// Decrypt input with key.
+///
+/// If `is_hmac` is set, the IV is checked against the HMAC-SHA1 of the decrypted message, as produced by
+/// [symmetric_encrypt_hmac_iv].
 pub fn symmetric_decrypt(input: &[u8], key: &[u8], is_hmac: bool) -> Result<Vec<u8>> {
-    let encrypted_iv = &input[0..16];
-    let plain_iv = cipher_iv_ecb(key, Some(encrypted_iv), Mode::Decrypt)?;
+    if input.len() < IV_SIZE {
+        return Err(SymmetricError::TooShort);
+    }
 
-    let encrypted_message = &input[16..];
+    let (encrypted_iv, encrypted_message) = input.split_at(IV_SIZE);
+    let plain_iv = cipher_iv_ecb(key, encrypted_iv, Mode::Decrypt)?;
+    let plain_message = cipher_message(encrypted_message, key, Some(&plain_iv), Mode::Decrypt)?;
 
-    if !is_hmac {
-        cipher_message(encrypted_message, key, Some(encrypted_iv), Mode::Decrypt)?;
-    }
-    let iv_len = plain_iv.len();
-    let hmac_partial = &plain_iv[..iv_len - 3];
-    let hmac_random_bytes = &plain_iv[iv_len - 3..];
+    if is_hmac {
+        let (hmac_partial, hmac_random_bytes) = plain_iv.split_at(IV_SIZE - HMAC_RANDOM_SIZE);
 
-    let signed_data = sign_hmac_sha1(&hmac_random_bytes, &plain_iv, &key[..16]).unwrap();
-    if &signed_data[..iv_len] != hmac_partial {
-        panic!("Received invalid HMAC from remote host.");
+        let signed_data = sign_hmac_sha1(hmac_random_bytes, &plain_message, hmac_key(key)?)?;
+        if &signed_data[..hmac_partial.len()] != hmac_partial {
+            return Err(SymmetricError::InvalidHmac);
+        }
     }
-    Ok(plain_iv)
+    Ok(plain_message)
 }
 
 /// Encrypt input with key. Returns HMAC
 /// IV is HMAC-SHA1(Random(3) + Plaintext) + Random(3). (Same random values for both)
-pub fn symmetric_encrypt_hmac_iv(input: &[u8], key: &[u8]) -> Vec<u8> {
-    const RAND_VEC_SIZE: usize = 3;
-    let mut random_vec: [u8; RAND_VEC_SIZE] = [0; RAND_VEC_SIZE];
+pub fn symmetric_encrypt_hmac_iv(input: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+    let mut random_vec: [u8; HMAC_RANDOM_SIZE] = [0; HMAC_RANDOM_SIZE];
     thread_rng().fill_bytes(&mut random_vec);
 
-    let signed_data = sign_hmac_sha1(&mut random_vec, &input, &key[..16]).unwrap();
+    let signed_data = sign_hmac_sha1(&random_vec, input, hmac_key(key)?)?;
 
     // the resulting IV must be 16 bytes long, so truncate the hmac to make room for the random
-    let mut signed_data_slice = signed_data[..16 - RAND_VEC_SIZE].to_vec();
+    let mut signed_data_slice = signed_data[..IV_SIZE - HMAC_RANDOM_SIZE].to_vec();
     signed_data_slice.extend(random_vec.iter());
 
-    symmetric_encrypt_with_iv(input, key, Some(signed_data_slice.as_ref())).unwrap()
+    symmetric_encrypt_with_iv(input, key, &signed_data_slice)
+}
+
+/// Only the start of the session key is used to sign messages.
+fn hmac_key(key: &[u8]) -> Result<&[u8]> {
+    key.get(..HMAC_KEY_SIZE).ok_or(SymmetricError::ShortKey)
 }
 
 fn sign_hmac_sha1(random_bytes: &[u8], input: &[u8], key: &[u8]) -> Result<Vec<u8>> {
-    let pkey = openssl::pkey::PKey::hmac(&key)?;
+    let pkey = openssl::pkey::PKey::hmac(key)?;
     let mut signer = Signer::new(MessageDigest::sha1(), &pkey)?;
     signer.update(random_bytes)?;
-    signer.update(&input)?;
+    signer.update(input)?;
     let signed_data = signer.sign_to_vec()?;
     Ok(signed_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn messages_round_trip() {
+        let encrypted = symmetric_encrypt(b"hello steam", &KEY).unwrap();
+        assert_eq!(symmetric_decrypt(&encrypted, &KEY, false).unwrap(), b"hello steam");
+    }
+
+    #[test]
+    fn hmac_messages_round_trip() {
+        let encrypted = symmetric_encrypt_hmac_iv(b"hello steam", &KEY).unwrap();
+        assert_eq!(symmetric_decrypt(&encrypted, &KEY, true).unwrap(), b"hello steam");
+    }
+
+    #[test]
+    fn tampered_messages_fail_the_hmac() {
+        let mut encrypted = symmetric_encrypt_hmac_iv(b"hello steam, how are you", &KEY).unwrap();
+        // the first block of the message only garbles itself, so the padding stays valid
+        encrypted[IV_SIZE] ^= 1;
+
+        assert!(matches!(
+            symmetric_decrypt(&encrypted, &KEY, true),
+            Err(SymmetricError::InvalidHmac)
+        ));
+    }
+
+    #[test]
+    fn short_input_is_an_error() {
+        assert!(matches!(
+            symmetric_decrypt(&[0; IV_SIZE - 1], &KEY, false),
+            Err(SymmetricError::TooShort)
+        ));
+    }
+
+    #[test]
+    fn short_keys_are_an_error() {
+        assert!(matches!(
+            symmetric_encrypt_hmac_iv(b"hello steam", &KEY[..8]),
+            Err(SymmetricError::ShortKey)
+        ));
+        assert!(symmetric_encrypt(b"hello steam", &KEY[..8]).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Binary KeyValues, the form Steam uses on the wire, such as for package info, rich presence and chat room members.
+//!
+//! Every value is prefixed with its type, and every section, the document included, ends with an end marker.
+
+use std::convert::TryInto;
+
+use crate::error::Error;
+use crate::value::KeyValue;
+use crate::MAX_DEPTH;
+
+const TYPE_NONE: u8 = 0;
+const TYPE_STRING: u8 = 1;
+const TYPE_INT32: u8 = 2;
+const TYPE_FLOAT32: u8 = 3;
+const TYPE_POINTER: u8 = 4;
+const TYPE_COLOR: u8 = 6;
+const TYPE_UINT64: u8 = 7;
+const TYPE_END: u8 = 8;
+const TYPE_INT64: u8 = 10;
+const TYPE_ALTERNATE_END: u8 = 11;
+
+/// Reads a binary KeyValues document, returning the name of its root and the root itself.
+///
+/// Anything after the document is ignored, use [read_binary] to go on reading past it.
+pub fn from_binary(input: &[u8]) -> Result<(String, KeyValue), Error> {
+    let mut input = input;
+    read_binary(&mut input)
+}
+
+/// Reads a binary KeyValues document from the start of `input`, advancing it past the document.
+///
+/// Meant for payloads holding many documents in a row, or more data after one.
+pub fn read_binary(input: &mut &[u8]) -> Result<(String, KeyValue), Error> {
+    let mut reader = BinaryReader {
+        input,
+        position: 0,
+    };
+
+    if reader.byte()? != TYPE_NONE {
+        return Err(Error::Malformed(0));
+    }
+    let root = reader.string()?;
+    let value = binary_section(&mut reader, 0)?;
+
+    // the end of the document itself, which some writers leave out
+    if matches!(
+        reader.input.get(reader.position),
+        Some(&TYPE_END) | Some(&TYPE_ALTERNATE_END)
+    ) {
+        reader.position += 1;
+    }
+
+    *input = &reader.input[reader.position..];
+    Ok((root, value))
+}
+
+/// Writes `value` under `root` as a binary KeyValues document.
+///
+/// Fails if `value` is not a section, since binary documents can only hold sections at their root.
+pub fn to_binary(root: &str, value: &KeyValue) -> Result<Vec<u8>, Error> {
+    let children = match value {
+        KeyValue::Section(children) => children,
+        _ => return Err(Error::Unsupported("a single value at the root of a binary document")),
+    };
+
+    let mut binary = vec![TYPE_NONE];
+    push_str(&mut binary, root);
+    write_section(&mut binary, children);
+    binary.push(TYPE_END);
+    Ok(binary)
+}
+
+fn push_str(binary: &mut Vec<u8>, value: &str) {
+    binary.extend_from_slice(value.as_bytes());
+    binary.push(0);
+}
+
+fn write_section(binary: &mut Vec<u8>, children: &[(String, KeyValue)]) {
+    for (key, value) in children {
+        let value_type = match value {
+            KeyValue::Section(_) => TYPE_NONE,
+            KeyValue::String(_) => TYPE_STRING,
+            KeyValue::Int32(_) => TYPE_INT32,
+            KeyValue::Float32(_) => TYPE_FLOAT32,
+            KeyValue::UInt64(_) => TYPE_UINT64,
+            KeyValue::Int64(_) => TYPE_INT64,
+        };
+        binary.push(value_type);
+        push_str(binary, key);
+
+        match value {
+            KeyValue::Section(children) => write_section(binary, children),
+            KeyValue::String(value) => push_str(binary, value),
+            KeyValue::Int32(value) => binary.extend_from_slice(&value.to_le_bytes()),
+            KeyValue::Float32(value) => binary.extend_from_slice(&value.to_le_bytes()),
+            KeyValue::UInt64(value) => binary.extend_from_slice(&value.to_le_bytes()),
+            KeyValue::Int64(value) => binary.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+    binary.push(TYPE_END);
+}
+
+struct BinaryReader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl BinaryReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], Error> {
+        let bytes = self
+            .input
+            .get(self.position..self.position + len)
+            .ok_or(Error::UnexpectedEnd)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let rest = &self.input[self.position..];
+        let len = rest.iter().position(|&b| b == 0).ok_or(Error::UnexpectedEnd)?;
+        let value = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.position += len + 1;
+        Ok(value)
+    }
+}
+
+fn binary_section(reader: &mut BinaryReader, depth: usize) -> Result<KeyValue, Error> {
+    if depth >= MAX_DEPTH {
+        return Err(Error::TooDeep(MAX_DEPTH));
+    }
+
+    let mut children = Vec::new();
+    loop {
+        let value_type = reader.byte()?;
+        if value_type == TYPE_END || value_type == TYPE_ALTERNATE_END {
+            return Ok(KeyValue::Section(children));
+        }
+
+        let key = reader.string()?;
+        let value = match value_type {
+            TYPE_NONE => binary_section(reader, depth + 1)?,
+            TYPE_STRING => KeyValue::String(reader.string()?),
+            TYPE_INT32 | TYPE_POINTER | TYPE_COLOR => KeyValue::Int32(i32::from_le_bytes(reader.array()?)),
+            TYPE_FLOAT32 => KeyValue::Float32(f32::from_le_bytes(reader.array()?)),
+            TYPE_UINT64 => KeyValue::UInt64(u64::from_le_bytes(reader.array()?)),
+            TYPE_INT64 => KeyValue::Int64(i64::from_le_bytes(reader.array()?)),
+            unknown => return Err(Error::UnknownType(unknown)),
+        };
+        children.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_binary() {
+        let mut binary = b"\x00123\x00".to_vec();
+        binary.extend_from_slice(b"\x02packageid\x00");
+        binary.extend_from_slice(&123i32.to_le_bytes());
+        binary.extend_from_slice(b"\x07big\x00");
+        binary.extend_from_slice(&u64::MAX.to_le_bytes());
+        binary.extend_from_slice(b"\x00appids\x00\x020\x00");
+        binary.extend_from_slice(&730i32.to_le_bytes());
+        binary.extend_from_slice(b"\x08\x01name\x00CS\x00\x08\x08");
+
+        let (root, package) = from_binary(&binary).unwrap();
+        assert_eq!(root, "123");
+        assert_eq!(package.get("packageid"), Some(&KeyValue::Int32(123)));
+        assert_eq!(package.get("big").and_then(KeyValue::as_u64), Some(u64::MAX));
+        assert_eq!(package.path(&["appids", "0"]).and_then(KeyValue::as_u32), Some(730));
+        assert_eq!(package.get("name").and_then(KeyValue::as_str), Some("CS"));
+    }
+
+    #[test]
+    fn truncated_binary_is_an_error() {
+        assert_eq!(
+            from_binary(b"\x00123\x00\x02packageid\x00\x01"),
+            Err(Error::UnexpectedEnd)
+        );
+        assert_eq!(from_binary(b"\x00123\x00\x09what\x00\x08"), Err(Error::UnknownType(9)));
+        assert_eq!(from_binary(b"\x01123\x00"), Err(Error::Malformed(0)));
+    }
+
+    #[test]
+    fn deeply_nested_binary_is_an_error() {
+        let mut binary = b"\x00root\x00".to_vec();
+        for _ in 0..100_000 {
+            binary.extend_from_slice(b"\x00a\x00");
+        }
+
+        assert_eq!(from_binary(&binary), Err(Error::TooDeep(MAX_DEPTH)));
+    }
+
+    #[test]
+    fn documents_are_read_one_after_the_other() {
+        let mut input: &[u8] = b"\x00first\x00\x08\x08\x00second\x00\x08\x08\xE8\x03\x00\x00";
+
+        assert_eq!(read_binary(&mut input).unwrap().0, "first");
+        assert_eq!(read_binary(&mut input).unwrap().0, "second");
+        assert_eq!(input, &1000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn writes_binary() {
+        let mut rich_presence = KeyValue::section();
+        rich_presence.push("status", "Idle".into());
+
+        assert_eq!(
+            to_binary("RP", &rich_presence).unwrap(),
+            b"\x00RP\x00\x01status\x00Idle\x00\x08\x08".to_vec()
+        );
+        assert_eq!(
+            to_binary("RP", &KeyValue::Int32(1)),
+            Err(Error::Unsupported("a single value at the root of a binary document"))
+        );
+    }
+
+    #[test]
+    fn binary_round_trips() {
+        let mut appids = KeyValue::section();
+        appids.push("0", KeyValue::Int32(730));
+        let mut package = KeyValue::section();
+        package.push("name", "CS".into());
+        package.push("appids", appids);
+        package.push("ratio", KeyValue::Float32(0.5));
+        package.push("steamid", KeyValue::UInt64(76561197960287930));
+        package.push("delta", KeyValue::Int64(-5));
+
+        let binary = to_binary("7", &package).unwrap();
+        assert_eq!(from_binary(&binary).unwrap(), ("7".to_string(), package));
+    }
+}
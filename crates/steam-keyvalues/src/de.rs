@@ -0,0 +1,370 @@
+//! Deserializing typed values out of a [KeyValue] tree.
+//!
+//! Since the text form only has strings, numbers and flags are parsed out of strings whenever a number is asked for.
+//! Sections are read as maps and structs, or as sequences of their values, ignoring keys, which is how Valve writes
+//! lists such as `"0" "730" "1" "440"`.
+
+use serde::de;
+use serde::de::DeserializeOwned;
+use serde::de::IntoDeserializer;
+use serde::de::Visitor;
+use serde::forward_to_deserialize_any;
+use serde::Deserializer;
+
+use crate::error::Error;
+use crate::value::KeyValue;
+
+/// Deserializes `T` out of a [KeyValue].
+pub fn from_value<T: DeserializeOwned>(value: &KeyValue) -> Result<T, Error> {
+    T::deserialize(value)
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match self {
+                    KeyValue::String(value) => visitor.$visit(value.trim().parse().map_err(de::Error::custom)?),
+                    _ => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for &'de KeyValue {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            KeyValue::String(value) => visitor.visit_borrowed_str(value),
+            KeyValue::Int32(value) => visitor.visit_i32(*value),
+            KeyValue::Float32(value) => visitor.visit_f32(*value),
+            KeyValue::UInt64(value) => visitor.visit_u64(*value),
+            KeyValue::Int64(value) => visitor.visit_i64(*value),
+            KeyValue::Section(children) => visitor.visit_map(SectionAccess {
+                children: children.iter(),
+                value: None,
+            }),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.as_bool() {
+            Some(value) => visitor.visit_bool(value),
+            None => Err(de::Error::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // absent keys are what None looks like, anything present is Some
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            KeyValue::Section(children) => visitor.visit_seq(ValuesAccess {
+                values: children.iter().map(|(_, value)| value),
+            }),
+            _ => Err(de::Error::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            KeyValue::String(variant) => visitor.visit_enum(variant.as_str().into_deserializer()),
+            KeyValue::Section(children) if children.len() == 1 => visitor.visit_enum(VariantAccess {
+                variant: &children[0].0,
+                value: &children[0].1,
+            }),
+            _ => Err(de::Error::invalid_type(unexpected(self), &visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf map struct identifier
+    }
+}
+
+fn unexpected(value: &KeyValue) -> de::Unexpected<'_> {
+    match value {
+        KeyValue::String(value) => de::Unexpected::Str(value),
+        KeyValue::Int32(value) => de::Unexpected::Signed((*value).into()),
+        KeyValue::Float32(value) => de::Unexpected::Float((*value).into()),
+        KeyValue::UInt64(value) => de::Unexpected::Unsigned(*value),
+        KeyValue::Int64(value) => de::Unexpected::Signed(*value),
+        KeyValue::Section(_) => de::Unexpected::Map,
+    }
+}
+
+struct SectionAccess<'de> {
+    children: std::slice::Iter<'de, (String, KeyValue)>,
+    value: Option<&'de KeyValue>,
+}
+
+impl<'de> de::MapAccess<'de> for SectionAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.children.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(KeyDeserializer(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value asked before its key"))?;
+        seed.deserialize(value)
+    }
+}
+
+struct ValuesAccess<I> {
+    values: I,
+}
+
+impl<'de, I: Iterator<Item = &'de KeyValue>> de::SeqAccess<'de> for ValuesAccess<I> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        self.values.next().map(|value| seed.deserialize(value)).transpose()
+    }
+}
+
+struct VariantAccess<'de> {
+    variant: &'de str,
+    value: &'de KeyValue,
+}
+
+impl<'de> de::EnumAccess<'de> for VariantAccess<'de> {
+    type Error = Error;
+    type Variant = &'de KeyValue;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Error> {
+        let variant = seed.deserialize(KeyDeserializer(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &'de KeyValue {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// Keys are always strings, but maps may be keyed by numbers, such as depots by their id.
+struct KeyDeserializer<'de>(&'de str);
+
+macro_rules! deserialize_parsed_key {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.0.trim().parse().map_err(de::Error::custom)?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for KeyDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    deserialize_parsed_key! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 char str string bytes byte_buf option unit unit_struct seq tuple tuple_struct map
+        struct enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::text::from_text;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct AppInfo {
+        appid: u32,
+        common: Common,
+        depots: BTreeMap<u32, Depot>,
+        #[serde(default)]
+        missing: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Common {
+        name: String,
+        #[serde(rename = "type")]
+        app_type: AppType,
+        releasestate: Option<String>,
+        free: bool,
+        languages: Vec<String>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum AppType {
+        Game,
+        Tool,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Depot {
+        maxsize: u64,
+    }
+
+    #[test]
+    fn strings_become_typed_values() {
+        let (_, app_info) = from_text(
+            r#""appinfo"
+            {
+                "appid"     "730"
+                "common"
+                {
+                    "name"          "Counter-Strike 2"
+                    "type"          "Game"
+                    "free"          "1"
+                    "languages"
+                    {
+                        "0"     "english"
+                        "1"     "brazilian"
+                    }
+                }
+                "depots"
+                {
+                    "731" { "maxsize" "34359738368" }
+                    "732" { "maxsize" "0" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let app_info: AppInfo = from_value(&app_info).unwrap();
+        assert_eq!(app_info.appid, 730);
+        assert_eq!(app_info.common.name, "Counter-Strike 2");
+        assert_eq!(app_info.common.app_type, AppType::Game);
+        assert_eq!(app_info.common.releasestate, None);
+        assert!(app_info.common.free);
+        assert_eq!(app_info.common.languages, vec!["english", "brazilian"]);
+        assert_eq!(app_info.depots[&731].maxsize, 34359738368);
+        assert_eq!(app_info.depots.len(), 2);
+        assert_eq!(app_info.missing, None);
+    }
+
+    #[test]
+    fn binary_values_keep_their_types() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Member {
+            steamid: u64,
+            permissions: i32,
+        }
+
+        let mut member = KeyValue::section();
+        member.push("steamid", KeyValue::UInt64(76561197960287930));
+        member.push("permissions", KeyValue::Int32(891));
+
+        assert_eq!(
+            from_value::<Member>(&member).unwrap(),
+            Member {
+                steamid: 76561197960287930,
+                permissions: 891,
+            }
+        );
+    }
+
+    #[test]
+    fn mismatched_types_are_errors() {
+        assert!(from_value::<u32>(&KeyValue::from("seven")).is_err());
+        assert!(from_value::<u8>(&KeyValue::Int32(300)).is_err());
+        assert!(from_value::<Vec<u32>>(&KeyValue::from("730")).is_err());
+        assert!(matches!(
+            from_value::<Depot>(&KeyValue::section()),
+            Err(Error::Message(message)) if message.contains("maxsize")
+        ));
+    }
+}
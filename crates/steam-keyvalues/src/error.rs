@@ -0,0 +1,45 @@
+//! Errors of reading and writing KeyValues.
+
+use std::fmt::Display;
+
+use thiserror::Error;
+
+/// Everything that can go wrong with KeyValues.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum Error {
+    /// Text that is not KeyValues, or a binary document that does not start with a section.
+    #[error("Malformed KeyValues near byte {0}.")]
+    Malformed(usize),
+
+    /// The input ended in the middle of a string or a section.
+    #[error("KeyValues ended before all of its sections were closed.")]
+    UnexpectedEnd,
+
+    /// Sections nested deeper than we are willing to read.
+    #[error("KeyValues nested deeper than {0} sections.")]
+    TooDeep(usize),
+
+    /// Binary value of a type we do not know how to read.
+    #[error("Unknown binary KeyValues type {0}.")]
+    UnknownType(u8),
+
+    /// A serde type with no KeyValues counterpart, such as bytes.
+    #[error("KeyValues can not hold {0}.")]
+    Unsupported(&'static str),
+
+    /// Raised from a serde implementation, such as a missing field.
+    #[error("{0}")]
+    Message(String),
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
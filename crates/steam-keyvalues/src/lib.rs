@@ -0,0 +1,133 @@
+//! Reader and writer for KeyValues, the nested key/value format Valve uses all over Steam.
+//!
+//! Both forms are supported: text, as found in `.vdf` files and PICS app info, and binary, as sent on the wire for
+//! package info, rich presence or chat room members. Either form is read into a [KeyValue] tree, which is then
+//! looked up by hand or deserialized with serde into typed structs.
+//!
+//! Keys are looked up ignoring case, the same as Steam does, but serde field names are matched exactly.
+//!
+//! # Example
+//!
+//! ```
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct AppInfo {
+//!     appid: u32,
+//!     common: Common,
+//! }
+//!
+//! #[derive(Deserialize)]
+//! struct Common {
+//!     name: String,
+//! }
+//!
+//! let text = r#""appinfo" { "appid" "730" "common" { "name" "Counter-Strike 2" } }"#;
+//! let app_info: AppInfo = steam_keyvalues::from_str(text).unwrap();
+//! assert_eq!(app_info.appid, 730);
+//! assert_eq!(app_info.common.name, "Counter-Strike 2");
+//! ```
+//!
+//! Reference: https://developer.valvesoftware.com/wiki/KeyValues
+
+#![warn(missing_docs)]
+#![deny(
+    missing_debug_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+pub use binary::from_binary;
+pub use binary::read_binary;
+pub use binary::to_binary;
+pub use de::from_value;
+pub use error::Error;
+pub use ser::to_value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+pub use text::from_text;
+pub use text::to_text;
+pub use value::KeyValue;
+
+mod binary;
+mod de;
+mod error;
+mod ser;
+mod text;
+mod value;
+
+/// How deep sections can be nested, past which documents are refused rather than risking the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Deserializes `T` out of the root of a text document.
+pub fn from_str<T: DeserializeOwned>(text: &str) -> Result<T, Error> {
+    let (_, value) = from_text(text)?;
+    from_value(&value)
+}
+
+/// Serializes `value` as a text document under `root`.
+pub fn to_string<T: Serialize + ?Sized>(root: &str, value: &T) -> Result<String, Error> {
+    Ok(to_text(root, &to_value(value)?))
+}
+
+/// Deserializes `T` out of the root of a binary document.
+pub fn from_slice<T: DeserializeOwned>(binary: &[u8]) -> Result<T, Error> {
+    let (_, value) = from_binary(binary)?;
+    from_value(&value)
+}
+
+/// Serializes `value` as a binary document under `root`.
+pub fn to_vec<T: Serialize + ?Sized>(root: &str, value: &T) -> Result<Vec<u8>, Error> {
+    to_binary(root, &to_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct LibraryFolders {
+        #[serde(rename = "0")]
+        steam: Folder,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Folder {
+        path: String,
+        label: String,
+        apps: BTreeMap<u32, u64>,
+    }
+
+    fn library_folders() -> LibraryFolders {
+        let mut apps = BTreeMap::new();
+        apps.insert(730, 34359738368);
+        apps.insert(440, 27000000000);
+        LibraryFolders {
+            steam: Folder {
+                path: "C:\\Program Files (x86)\\Steam".to_string(),
+                label: "".to_string(),
+                apps,
+            },
+        }
+    }
+
+    #[test]
+    fn text_documents_round_trip() {
+        let text = to_string("libraryfolders", &library_folders()).unwrap();
+        assert!(text.contains("\"path\"\t\t\"C:\\\\Program Files (x86)\\\\Steam\""));
+        assert_eq!(from_str::<LibraryFolders>(&text).unwrap(), library_folders());
+    }
+
+    #[test]
+    fn binary_documents_round_trip() {
+        let binary = to_vec("libraryfolders", &library_folders()).unwrap();
+        assert_eq!(from_slice::<LibraryFolders>(&binary).unwrap(), library_folders());
+    }
+}
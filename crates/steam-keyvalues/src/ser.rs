@@ -0,0 +1,436 @@
+//! Serializing typed values into a [KeyValue] tree.
+//!
+//! Numbers keep their binary types: up to 32 bits they become [KeyValue::Int32], except unsigned values too big for
+//! it, which become [KeyValue::UInt64] like any other `u64`. Sequences become sections keyed by their index, and
+//! `None` values are left out of their section.
+
+use std::convert::TryFrom;
+
+use serde::ser;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::value::KeyValue;
+
+/// Serializes `value` into a [KeyValue].
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<KeyValue, Error> {
+    value
+        .serialize(ValueSerializer)?
+        .ok_or(Error::Unsupported("None outside of a section"))
+}
+
+/// Serializes into `Some` value, or `None` for values to be left out.
+struct ValueSerializer;
+
+type Serialized = Option<KeyValue>;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+    type SerializeSeq = SectionSerializer;
+    type SerializeTuple = SectionSerializer;
+    type SerializeTupleStruct = SectionSerializer;
+    type SerializeTupleVariant = VariantSerializer;
+    type SerializeMap = SectionSerializer;
+    type SerializeStruct = SectionSerializer;
+    type SerializeStructVariant = VariantSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::Int32(v as i32)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Serialized, Error> {
+        self.serialize_i32(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Serialized, Error> {
+        self.serialize_i32(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::Int32(v)))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::Int64(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Serialized, Error> {
+        self.serialize_i32(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Serialized, Error> {
+        self.serialize_i32(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Serialized, Error> {
+        match i32::try_from(v) {
+            Ok(v) => self.serialize_i32(v),
+            Err(_) => self.serialize_u64(v.into()),
+        }
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::UInt64(v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::Float32(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Serialized, Error> {
+        self.serialize_f32(v as f32)
+    }
+
+    fn serialize_char(self, v: char) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::String(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::String(v.to_string())))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Serialized, Error> {
+        Err(Error::Unsupported("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<Serialized, Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Serialized, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Serialized, Error> {
+        Ok(Some(KeyValue::section()))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Serialized, Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Serialized, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Serialized, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Serialized, Error> {
+        let mut section = KeyValue::section();
+        if let Some(value) = value.serialize(ValueSerializer)? {
+            section.push(variant, value);
+        }
+        Ok(Some(section))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SectionSerializer, Error> {
+        Ok(SectionSerializer::default())
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SectionSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SectionSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<VariantSerializer, Error> {
+        Ok(VariantSerializer {
+            variant,
+            section: SectionSerializer::default(),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SectionSerializer, Error> {
+        Ok(SectionSerializer::default())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<SectionSerializer, Error> {
+        Ok(SectionSerializer::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<VariantSerializer, Error> {
+        Ok(VariantSerializer {
+            variant,
+            section: SectionSerializer::default(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct SectionSerializer {
+    children: Vec<(String, KeyValue)>,
+    next_key: Option<String>,
+    next_index: usize,
+}
+
+impl SectionSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), Error> {
+        if let Some(value) = value.serialize(ValueSerializer)? {
+            self.children.push((key, value));
+        }
+        Ok(())
+    }
+
+    fn push_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.next_index.to_string();
+        self.next_index += 1;
+        self.push(key, value)
+    }
+
+    fn finish(self) -> Serialized {
+        Some(KeyValue::Section(self.children))
+    }
+}
+
+impl ser::SerializeSeq for SectionSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<Serialized, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SectionSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<Serialized, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SectionSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<Serialized, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeMap for SectionSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let key = match key.serialize(ValueSerializer)? {
+            Some(KeyValue::String(key)) => key,
+            Some(KeyValue::Int32(key)) => key.to_string(),
+            Some(KeyValue::UInt64(key)) => key.to_string(),
+            Some(KeyValue::Int64(key)) => key.to_string(),
+            _ => return Err(Error::Unsupported("keys that are not strings nor integers")),
+        };
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| ser::Error::custom("value serialized before its key"))?;
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Serialized, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for SectionSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.push(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Serialized, Error> {
+        Ok(self.finish())
+    }
+}
+
+/// Enum variants holding data are a section with the variant name as its only key.
+struct VariantSerializer {
+    variant: &'static str,
+    section: SectionSerializer,
+}
+
+impl VariantSerializer {
+    fn finish(self) -> Serialized {
+        let mut wrapper = KeyValue::section();
+        wrapper.push(self.variant, KeyValue::Section(self.section.children));
+        Some(wrapper)
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.section.push_element(value)
+    }
+
+    fn end(self) -> Result<Serialized, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer {
+    type Ok = Serialized;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.section.push(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Serialized, Error> {
+        Ok(self.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::de::from_value;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Package {
+        packageid: u32,
+        name: Option<String>,
+        ratio: f32,
+        owner: u64,
+        appids: Vec<u32>,
+        depots: BTreeMap<u32, String>,
+        status: Status,
+        extended: Extended,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Status {
+        Available,
+        Unavailable { reason: String },
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Extended {
+        allowcrossregiontradingandgifting: bool,
+    }
+
+    fn package() -> Package {
+        let mut depots = BTreeMap::new();
+        depots.insert(731, "Content".to_string());
+        Package {
+            packageid: 7,
+            name: None,
+            ratio: 0.5,
+            owner: 76561197960287930,
+            appids: vec![730, 440],
+            depots,
+            status: Status::Unavailable {
+                reason: "region".to_string(),
+            },
+            extended: Extended {
+                allowcrossregiontradingandgifting: true,
+            },
+        }
+    }
+
+    #[test]
+    fn values_keep_their_types() {
+        let value = to_value(&package()).unwrap();
+
+        assert_eq!(value.get("packageid"), Some(&KeyValue::Int32(7)));
+        assert_eq!(value.get("name"), None);
+        assert_eq!(value.get("owner"), Some(&KeyValue::UInt64(76561197960287930)));
+        assert_eq!(value.path(&["appids", "1"]), Some(&KeyValue::Int32(440)));
+        assert_eq!(
+            value.path(&["depots", "731"]).and_then(KeyValue::as_str),
+            Some("Content")
+        );
+        assert_eq!(
+            value
+                .path(&["status", "Unavailable", "reason"])
+                .and_then(KeyValue::as_str),
+            Some("region")
+        );
+        assert_eq!(
+            value.path(&["extended", "allowcrossregiontradingandgifting"]),
+            Some(&KeyValue::Int32(1))
+        );
+        assert_eq!(to_value(&u32::MAX).unwrap(), KeyValue::UInt64(u32::MAX.into()));
+    }
+
+    #[test]
+    fn serialized_values_deserialize_back() {
+        let value = to_value(&package()).unwrap();
+        assert_eq!(from_value::<Package>(&value).unwrap(), package());
+    }
+
+    #[test]
+    fn bytes_are_unsupported() {
+        assert_eq!(to_value(&Bytes), Err(Error::Unsupported("bytes")));
+        assert_eq!(
+            to_value(&Option::<u32>::None),
+            Err(Error::Unsupported("None outside of a section"))
+        );
+    }
+
+    struct Bytes;
+
+    impl Serialize for Bytes {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(b"bytes")
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Text KeyValues, the form of `.vdf` files and of app info.
+
+use std::fmt::Write;
+
+use crate::error::Error;
+use crate::value::KeyValue;
+use crate::MAX_DEPTH;
+
+/// Reads a text KeyValues document, returning the name of its root and the root itself.
+///
+/// Conditionals such as `[$WIN32]` are skipped, as well as `//` comments. A trailing nul, which Steam leaves at the
+/// end of some buffers, is ignored.
+pub fn from_text(input: &str) -> Result<(String, KeyValue), Error> {
+    let mut tokens = TextTokens {
+        input: input.trim_end_matches('\0'),
+        position: 0,
+    };
+
+    let root = match tokens.next()? {
+        Some(Token::String(root)) => root,
+        _ => return Err(Error::Malformed(tokens.position)),
+    };
+    match tokens.next()? {
+        Some(Token::Open) => Ok((root, text_section(&mut tokens, 0)?)),
+        _ => Err(Error::Malformed(tokens.position)),
+    }
+}
+
+/// Writes `value` under `root` as a text KeyValues document, indented with tabs as Valve does.
+///
+/// Numbers are written as strings, since the text form has no types.
+pub fn to_text(root: &str, value: &KeyValue) -> String {
+    let mut text = String::new();
+    write_entry(&mut text, 0, root, value);
+    text
+}
+
+fn write_entry(text: &mut String, depth: usize, key: &str, value: &KeyValue) {
+    let indent = "\t".repeat(depth);
+    match value {
+        KeyValue::Section(children) => {
+            let _ = writeln!(text, "{}\"{}\"", indent, escape(key));
+            let _ = writeln!(text, "{}{{", indent);
+            for (key, value) in children {
+                write_entry(text, depth + 1, key, value);
+            }
+            let _ = writeln!(text, "{}}}", indent);
+        }
+        KeyValue::String(value) => {
+            let _ = writeln!(text, "{}\"{}\"\t\t\"{}\"", indent, escape(key), escape(value));
+        }
+        KeyValue::Int32(value) => {
+            let _ = writeln!(text, "{}\"{}\"\t\t\"{}\"", indent, escape(key), value);
+        }
+        KeyValue::Float32(value) => {
+            let _ = writeln!(text, "{}\"{}\"\t\t\"{}\"", indent, escape(key), value);
+        }
+        KeyValue::UInt64(value) => {
+            let _ = writeln!(text, "{}\"{}\"\t\t\"{}\"", indent, escape(key), value);
+        }
+        KeyValue::Int64(value) => {
+            let _ = writeln!(text, "{}\"{}\"\t\t\"{}\"", indent, escape(key), value);
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+enum Token {
+    String(String),
+    Open,
+    Close,
+}
+
+struct TextTokens<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> TextTokens<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn skip_blanks(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.position += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with('[') {
+                // conditional, which we take as always true
+                match trimmed.find(']') {
+                    Some(end) => self.position += end + 1,
+                    None => return,
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Token>, Error> {
+        self.skip_blanks();
+        let mut chars = self.rest().char_indices();
+
+        let token = match chars.next() {
+            None => return Ok(None),
+            Some((_, '{')) => {
+                self.position += 1;
+                Token::Open
+            }
+            Some((_, '}')) => {
+                self.position += 1;
+                Token::Close
+            }
+            Some((_, '"')) => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((end, '"')) => {
+                            self.position += end + 1;
+                            break;
+                        }
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => value.push('\n'),
+                            Some((_, 't')) => value.push('\t'),
+                            Some((_, escaped)) => value.push(escaped),
+                            None => return Err(Error::UnexpectedEnd),
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return Err(Error::UnexpectedEnd),
+                    }
+                }
+                Token::String(value)
+            }
+            Some(_) => {
+                let rest = self.rest();
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '{' | '}' | '"'))
+                    .unwrap_or(rest.len());
+                let value = rest[..end].to_string();
+                self.position += end;
+                Token::String(value)
+            }
+        };
+        Ok(Some(token))
+    }
+}
+
+/// Reads the children of a section whose opening brace was already read, up to its closing one.
+fn text_section(tokens: &mut TextTokens, depth: usize) -> Result<KeyValue, Error> {
+    if depth >= MAX_DEPTH {
+        return Err(Error::TooDeep(MAX_DEPTH));
+    }
+
+    let mut children = Vec::new();
+    loop {
+        let key = match tokens.next()? {
+            Some(Token::String(key)) => key,
+            Some(Token::Close) => return Ok(KeyValue::Section(children)),
+            None => return Err(Error::UnexpectedEnd),
+            Some(Token::Open) => return Err(Error::Malformed(tokens.position)),
+        };
+        let value = match tokens.next()? {
+            Some(Token::String(value)) => KeyValue::String(value),
+            Some(Token::Open) => text_section(tokens, depth + 1)?,
+            Some(Token::Close) => return Err(Error::Malformed(tokens.position)),
+            None => return Err(Error::UnexpectedEnd),
+        };
+        children.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_text() {
+        let text = r#"
+            "appinfo"
+            {
+                // comments are skipped
+                "appid"     "730"
+                "common"
+                {
+                    "name"  "Counter-Strike \"2\""
+                    "type"  "Game" [$WIN32]
+                }
+                unquoted    value
+            }
+        "#;
+
+        let (root, app_info) = from_text(&format!("{}\0", text)).unwrap();
+        assert_eq!(root, "appinfo");
+        assert_eq!(app_info.get("AppID").and_then(KeyValue::as_u32), Some(730));
+        assert_eq!(
+            app_info.path(&["common", "name"]).and_then(KeyValue::as_str),
+            Some("Counter-Strike \"2\"")
+        );
+        assert_eq!(
+            app_info.path(&["common", "type"]).and_then(KeyValue::as_str),
+            Some("Game")
+        );
+        assert_eq!(app_info.get("unquoted").and_then(KeyValue::as_str), Some("value"));
+    }
+
+    #[test]
+    fn unclosed_text_sections_are_errors() {
+        assert_eq!(
+            from_text(r#""appinfo" { "common" { "name" "CS" }"#),
+            Err(Error::UnexpectedEnd)
+        );
+        assert_eq!(from_text(r#""appinfo" "730""#), Err(Error::Malformed(15)));
+    }
+
+    #[test]
+    fn deeply_nested_text_is_an_error() {
+        let nested = |depth: usize| format!("root {{{}{}", "a {".repeat(depth), "}".repeat(depth + 1));
+
+        assert!(from_text(&nested(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(from_text(&nested(100_000)), Err(Error::TooDeep(MAX_DEPTH)));
+    }
+
+    #[test]
+    fn writes_text_as_valve_does() {
+        let mut common = KeyValue::section();
+        common.push("name", "Say \"hi\"".into());
+        let mut app = KeyValue::section();
+        app.push("appid", KeyValue::Int32(730));
+        app.push("common", common);
+
+        assert_eq!(
+            to_text("appinfo", &app),
+            "\"appinfo\"\n{\n\t\"appid\"\t\t\"730\"\n\t\"common\"\n\t{\n\t\t\"name\"\t\t\"Say \\\"hi\\\"\"\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let mut depots = KeyValue::section();
+        depots.push("731", "tab\there\nnew line \\ done".into());
+        depots.push("731", "keys may repeat".into());
+        let mut app = KeyValue::section();
+        app.push("depots", depots);
+        app.push("empty", KeyValue::section());
+
+        let (root, read) = from_text(&to_text("appinfo", &app)).unwrap();
+        assert_eq!(root, "appinfo");
+        assert_eq!(read, app);
+    }
+}
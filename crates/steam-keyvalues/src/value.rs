@@ -0,0 +1,180 @@
+//! The KeyValues tree, shared by both forms.
+
+use std::convert::TryInto;
+
+/// A KeyValues node, either a single value or a section holding more of them.
+///
+/// The text form only has strings and sections, the typed values come from the binary form.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyValue {
+    /// Text value.
+    String(String),
+    /// Signed 32 bits value, as well as pointers and colors.
+    Int32(i32),
+    /// 32 bits float value.
+    Float32(f32),
+    /// Unsigned 64 bits value.
+    UInt64(u64),
+    /// Signed 64 bits value.
+    Int64(i64),
+    /// Children, in the order they were read. Keys may repeat.
+    Section(Vec<(String, KeyValue)>),
+}
+
+impl KeyValue {
+    /// An empty section.
+    pub fn section() -> Self {
+        KeyValue::Section(Vec::new())
+    }
+
+    /// Appends `value` under `key`, if this is a section.
+    pub fn push<K: Into<String>>(&mut self, key: K, value: KeyValue) {
+        if let KeyValue::Section(children) = self {
+            children.push((key.into(), value));
+        }
+    }
+
+    /// First child under `key`, if this is a section that has it.
+    pub fn get(&self, key: &str) -> Option<&KeyValue> {
+        self.children()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    /// Follows `path` down the sections, like chaining [KeyValue::get].
+    pub fn path(&self, path: &[&str]) -> Option<&KeyValue> {
+        path.iter().try_fold(self, |node, key| node.get(key))
+    }
+
+    /// Children of this section, or nothing for single values.
+    pub fn children(&self) -> impl Iterator<Item = (&str, &KeyValue)> {
+        let children = match self {
+            KeyValue::Section(children) => children.as_slice(),
+            _ => &[],
+        };
+        children.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Whether this is a section, rather than a single value.
+    pub fn is_section(&self) -> bool {
+        matches!(self, KeyValue::Section(_))
+    }
+
+    /// The value, if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            KeyValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value as an unsigned number, either parsed from a string or converted from a number that fits.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            KeyValue::String(value) => value.trim().parse().ok(),
+            KeyValue::Int32(value) => (*value).try_into().ok(),
+            KeyValue::UInt64(value) => Some(*value),
+            KeyValue::Int64(value) => (*value).try_into().ok(),
+            KeyValue::Float32(_) | KeyValue::Section(_) => None,
+        }
+    }
+
+    /// Same as [KeyValue::as_u64], but for values that must fit in 32 bits.
+    pub fn as_u32(&self) -> Option<u32> {
+        self.as_u64().and_then(|value| value.try_into().ok())
+    }
+
+    /// The value as a signed number, either parsed from a string or converted from a number that fits.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            KeyValue::String(value) => value.trim().parse().ok(),
+            KeyValue::Int32(value) => Some((*value).into()),
+            KeyValue::UInt64(value) => (*value).try_into().ok(),
+            KeyValue::Int64(value) => Some(*value),
+            KeyValue::Float32(_) | KeyValue::Section(_) => None,
+        }
+    }
+
+    /// The value as a float, either parsed from a string or converted from a number.
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            KeyValue::String(value) => value.trim().parse().ok(),
+            KeyValue::Float32(value) => Some(*value),
+            KeyValue::Int32(value) => Some(*value as f32),
+            KeyValue::UInt64(value) => Some(*value as f32),
+            KeyValue::Int64(value) => Some(*value as f32),
+            KeyValue::Section(_) => None,
+        }
+    }
+
+    /// Flags are written as numbers, anything other than zero being true.
+    pub fn as_bool(&self) -> Option<bool> {
+        self.as_i64().map(|value| value != 0)
+    }
+}
+
+impl From<&str> for KeyValue {
+    fn from(value: &str) -> Self {
+        KeyValue::String(value.to_string())
+    }
+}
+
+impl From<String> for KeyValue {
+    fn from(value: String) -> Self {
+        KeyValue::String(value)
+    }
+}
+
+impl From<i32> for KeyValue {
+    fn from(value: i32) -> Self {
+        KeyValue::Int32(value)
+    }
+}
+
+impl From<u64> for KeyValue {
+    fn from(value: u64) -> Self {
+        KeyValue::UInt64(value)
+    }
+}
+
+impl From<i64> for KeyValue {
+    fn from(value: i64) -> Self {
+        KeyValue::Int64(value)
+    }
+}
+
+impl From<f32> for KeyValue {
+    fn from(value: f32) -> Self {
+        KeyValue::Float32(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_ignore_case() {
+        let mut common = KeyValue::section();
+        common.push("Name", "Counter-Strike 2".into());
+        let mut app = KeyValue::section();
+        app.push("common", common);
+
+        assert_eq!(
+            app.path(&["COMMON", "name"]).and_then(KeyValue::as_str),
+            Some("Counter-Strike 2")
+        );
+        assert_eq!(app.get("missing"), None);
+    }
+
+    #[test]
+    fn numbers_are_read_from_any_form() {
+        assert_eq!(KeyValue::from(" 730 ").as_u32(), Some(730));
+        assert_eq!(KeyValue::Int32(-1).as_u64(), None);
+        assert_eq!(KeyValue::Int32(-1).as_i64(), Some(-1));
+        assert_eq!(KeyValue::UInt64(u64::MAX).as_u32(), None);
+        assert_eq!(KeyValue::from("0").as_bool(), Some(false));
+        assert_eq!(KeyValue::Int32(2).as_bool(), Some(true));
+        assert_eq!(KeyValue::section().as_u64(), None);
+    }
+}
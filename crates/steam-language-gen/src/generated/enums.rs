@@ -1649,7 +1649,12 @@ pub enum EResult {
 pub enum EMsg {
     Invalid = 0,
     Multi = 1,
+    DestJobFailed = 113,
     RemoteSysID = 128,
+    ServiceMethod = 146,
+    ServiceMethodResponse = 147,
+    ServiceMethodCallFromClient = 151,
+    ServiceMethodSendToClient = 152,
     FileXferRequest = 1200,
     FileXferResponse = 1201,
     FileXferData = 1202,
@@ -1998,4 +2003,11 @@ pub enum EMsg {
     ClientRichPresenceUpload = 7501,
     ClientRichPresenceRequest = 7502,
     ClientRichPresenceInfo = 7503,
+    ClientPICSChangesSinceRequest = 8901,
+    ClientPICSChangesSinceResponse = 8902,
+    ClientPICSProductInfoRequest = 8903,
+    ClientPICSProductInfoResponse = 8904,
+    ClientPICSAccessTokenRequest = 8905,
+    ClientPICSAccessTokenResponse = 8906,
+    ServiceMethodCallFromClientNonAuthed = 9804,
 }
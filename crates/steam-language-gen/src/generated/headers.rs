@@ -1,3 +1,4 @@
+use arrayref::array_ref;
 use derive_new::new;
 use serde::Deserialize;
 use serde::Serialize;
@@ -10,6 +11,8 @@ use crate::HasJobId;
 use crate::MessageHeaderExt;
 use crate::SerializableBytes;
 
+const PROTO_HEADER_LEN_SIZE: usize = 4;
+
 // add protobuf
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum MessageHeaders {
@@ -42,9 +45,18 @@ impl MessageHeaderExt for CMsgProtoBufHeader {
         Self::new()
     }
 
+    /// Protobuf headers have no fixed size, so they are prefixed on the wire by their length as an `u32`.
+    ///
+    /// The length prefix is not part of the returned header. A length that points past the end of the data is
+    /// clamped, so the header bytes come out truncated and fail to parse instead of panicking here.
     fn split_from_bytes(data: &[u8]) -> (&[u8], &[u8]) {
-        let size = std::mem::size_of::<Self>();
-        (&data[..size], &data[size..])
+        if data.len() < PROTO_HEADER_LEN_SIZE {
+            return (&[], data);
+        }
+        let (header_len, rest) = data.split_at(PROTO_HEADER_LEN_SIZE);
+        let header_len = u32::from_le_bytes(*array_ref!(header_len, 0, PROTO_HEADER_LEN_SIZE)) as usize;
+
+        rest.split_at(header_len.min(rest.len()))
     }
 }
 
@@ -79,7 +91,7 @@ pub struct ExtendedMessageHeader {
     #[new(value = "239")]
     header_canary: u8,
     #[new(value = "0")]
-    steam_id: u64,
+    pub steam_id: u64,
     #[new(value = "0")]
-    session_id: i32,
+    pub session_id: i32,
 }
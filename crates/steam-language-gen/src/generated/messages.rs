@@ -325,19 +325,26 @@ pub struct MsgClientChatRoomInfo {
     type_: EChatInfoType,
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize, SteamMsg)]
+#[linked_emsg(EMsg::ClientSetIgnoreFriend)]
+#[derive(new, Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize, SteamMsg)]
 #[repr(packed)]
 pub struct MsgClientSetIgnoreFriend {
-    my_steam_id: u64,
-    steam_id_friend: u64,
-    ignore: u8,
+    #[new(default)]
+    pub my_steam_id: u64,
+    #[new(default)]
+    pub steam_id_friend: u64,
+    #[new(default)]
+    pub ignore: u8,
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize, SteamMsg)]
+#[linked_emsg(EMsg::ClientSetIgnoreFriendResponse)]
+#[derive(new, Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize, SteamMsg)]
 #[repr(packed)]
 pub struct MsgClientSetIgnoreFriendResponse {
-    friend_id: u64,
-    result: EResult,
+    #[new(default)]
+    pub friend_id: u64,
+    #[new(value = "EResult::Invalid")]
+    pub result: EResult,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize, SteamMsg)]
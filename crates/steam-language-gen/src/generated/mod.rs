@@ -7,7 +7,8 @@ pub mod enums;
 pub mod headers;
 pub mod messages;
 
-const PROTOMASK: u32 = 0x8000_0000;
+/// Flag set on the raw `EMsg` of messages carrying a protobuf header.
+pub const PROTOMASK: u32 = 0x8000_0000;
 const EMSGMASK: u32 = !PROTOMASK;
 
 impl EMsg {
@@ -22,7 +23,10 @@ impl EMsg {
     /// Creates an `EMsg` from a raw data packet.
     pub fn from_raw_message(message: &[u8]) -> Result<Self, EMsgError> {
         // an error should be throw if the message doesnt have 4 bytes of length
-        let extracted_varint: u32 = Self::extract_varint(message);
+        if message.len() < 4 {
+            return Err(EMsgError::MessageNotLongEnough("An EMsg needs at least 4 bytes."));
+        }
+        let extracted_varint: u32 = Self::strip_protobuf_flag(Self::extract_varint(message));
 
         match EMsg::from_u32(extracted_varint) {
             Some(value) => Ok(value),
@@ -30,9 +34,14 @@ impl EMsg {
         }
     }
 
-    /// Strips protobuf message flag out and and returns it
+    /// Strips protobuf message flag out and returns the bare EMsg value
     pub fn strip_protobuf_flag(message: u32) -> u32 {
-        message & PROTOMASK
+        message & EMSGMASK
+    }
+
+    /// Returns the raw value of this EMsg, with the protobuf flag set
+    pub fn with_protobuf_flag(self) -> u32 {
+        self as u32 | PROTOMASK
     }
 
     /// Strips the [EMsg] from data, and returns the data
@@ -43,7 +52,7 @@ impl EMsg {
     /// Checks if a message is flagged as a protobuf
     /// We can only check with the varint on it
     pub fn is_protobuf(message: &[u8]) -> bool {
-        Self::strip_protobuf_flag(Self::extract_varint(message)) > 0
+        Self::extract_varint(message) & PROTOMASK != 0
     }
 
     /// Extract varint from data
@@ -45,7 +45,13 @@ impl SerializableBytes for MessageHeaderWrapper {
         match self {
             MessageHeaderWrapper::Std(hdr) => hdr.to_bytes(),
             MessageHeaderWrapper::Ext(hdr) => hdr.to_bytes(),
-            MessageHeaderWrapper::Proto(hdr) => SerializableBytes::to_bytes(hdr),
+            MessageHeaderWrapper::Proto(hdr) => {
+                // protobuf headers are prefixed by their length, see `CMsgProtoBufHeader::split_from_bytes`
+                let header = SerializableBytes::to_bytes(hdr);
+                let mut output = (header.len() as u32).to_le_bytes().to_vec();
+                output.extend(header);
+                output
+            }
         }
     }
 }
@@ -47,6 +47,7 @@ use crate::web_handler::confirmation::Confirmation;
 use crate::web_handler::confirmation::Confirmations;
 use crate::web_handler::get_confirmations;
 use crate::web_handler::login::login_and_store_cookies;
+use crate::web_handler::login::login_with_tokens;
 use crate::web_handler::send_confirmations;
 use crate::web_handler::steam_guard_linker::account_has_phone;
 use crate::web_handler::steam_guard_linker::add_authenticator_to_account;
@@ -64,6 +65,7 @@ use crate::web_handler::steam_guard_linker::STEAM_ADD_PHONE_CATCHUP_SECS;
 use crate::CacheGuard;
 use crate::ConfirmationAction;
 use crate::MobileAuthFile;
+use crate::SteamCache;
 use crate::STEAM_COMMUNITY_HOST;
 
 /// Main authenticator. We use it to spawn and act as our "mobile" client.
@@ -163,20 +165,49 @@ where
         //     info!("Parental unlock successfully.");
         // }
 
-        let api_key = cache_api_key(&client, user_arc.clone(), cache.steamid.to_steam64()).await;
+        Ok(Self::authenticated(client, user, cache).await)
+    }
+
+    /// Log on into Steam website with an access token and refresh token pair, skipping the credentials
+    /// exchange, and populates the inner client with cookies the same way [login](Self::login) does.
+    ///
+    /// The tokens are the ones Steam mints through `IAuthenticationService`, so they can come from a
+    /// `steam-client` CM session that already logged on, or from a previous
+    /// [refresh_token](SteamAuthenticator::refresh_token) of this crate.
+    pub async fn login_with_tokens(
+        self,
+        access_token: String,
+        refresh_token: String,
+    ) -> Result<SteamAuthenticator<Authenticated, MaFileState>, AuthError> {
+        let user = self.inner.user;
+        let client = self.inner.client;
+
+        let cache = login_with_tokens(&client, access_token, refresh_token).await?;
+        info!("Login to Steam with tokens successfully.");
+
+        Ok(Self::authenticated(client, user, cache).await)
+    }
+
+    async fn authenticated(
+        client: MobileClient,
+        user: SteamUser<MaFileState>,
+        mut cache: SteamCache,
+    ) -> SteamAuthenticator<Authenticated, MaFileState> {
+        let user_arc: Arc<dyn IsUser> = Arc::new(user.clone());
+        let api_key = cache_api_key(&client, user_arc, cache.steamid.to_steam64()).await;
         if let Some(api_key) = api_key {
             cache.set_api_key(Some(api_key));
             info!("Cached API Key successfully.");
         }
 
-        Ok(SteamAuthenticator {
+        SteamAuthenticator {
             inner: InnerAuthenticator {
                 client,
                 user,
                 cache: Some(Arc::new(RwLock::new(cache))),
             },
             auth_level: PhantomData,
-        })
+        }
     }
 }
 
@@ -188,6 +219,14 @@ where
         self.inner.cache.as_ref().expect("Safe to unwrap.").clone()
     }
 
+    /// Returns the refresh token of this session.
+    ///
+    /// It can be handed to `steam-client` to log on to the CM with the same credentials, or to
+    /// [login_with_tokens](SteamAuthenticator::login_with_tokens) later to skip the credentials exchange.
+    pub fn refresh_token(&self) -> String {
+        self.cache().read().oauth_token().to_owned()
+    }
+
     /// Returns account's API Key, if authenticator managed to cache it.
     pub fn api_key(&self) -> Option<String> {
         self.inner
@@ -3,10 +3,11 @@ use std::time::Duration;
 
 use base64::Engine;
 use const_format::concatcp;
+use cookie::Cookie;
 use futures_timer::Delay;
 use futures_util::future::try_join_all;
 use rand::thread_rng;
-use reqwest::Client;
+use rand::RngCore;
 use reqwest::Method;
 use rsa::BigUint;
 use rsa::Pkcs1v15Encrypt;
@@ -34,7 +35,6 @@ use crate::types::FinalizeLoginResponseBase;
 use crate::user::IsUser;
 use crate::user::PresentMaFile;
 use crate::user::SteamUser;
-use crate::AuthResult;
 use crate::SteamCache;
 use crate::STEAM_API_BASE;
 use crate::STEAM_COMMUNITY_HOST;
@@ -76,25 +76,12 @@ where
     base64::engine::general_purpose::STANDARD.encode(encrypted)
 }
 
-/// Logs in Steam through Steam `ISteamAuthUser` interface.
-///
-/// `Webapi_nonce` is received by connecting to the Steam Network.
-///
-/// Currently not possible without the implementation of the [steam-client] crate.
-/// For website that currently works, check [login_and_store_cookies] method.
-async fn login_isteam_user_auth<U>(_client: &Client, _user: SteamUser<U>, _webapi_nonce: &[u8]) -> AuthResult<()> {
-    unimplemented!();
-}
-
 /// Logs in through the Steam website, caching the user's SteamID,
 /// and storing session cookies for steamcommunity and steampowered domains.
 ///
-/// Additionally, there is a method for logging in through an API interface called ISteamUserAuth.
-/// For more details, refer to [`login_isteam_user_auth`].
+/// If the tokens were already minted elsewhere, such as over a CM connection, use [`login_with_tokens`] instead.
 ///
 /// For the implementation details, you can check the original C# source code [here](https://github.com/Jessecar96/SteamBot/blob/e8e9e5fcd64ae35b201e2597068849c10a667b60/SteamTrade/SteamWeb.cs#L325).
-///
-/// [login_isteam_user_auth]: #method.login_isteam_user_auth
 #[allow(clippy::too_many_lines)]
 pub async fn login_and_store_cookies(client: &MobileClient, user: Arc<dyn IsUser>) -> Result<SteamCache, LoginError> {
     let mut rsa_payload = CAuthentication_GetPasswordRSAPublicKey_Request::new();
@@ -187,11 +174,20 @@ pub async fn login_and_store_cookies(client: &MobileClient, user: Arc<dyn IsUser
 
     let refresh_token = poll_session_response.refresh_token.expect("Safe to unwrap");
     let access_token = poll_session_response.access_token.expect("Safe to unwrap");
+    login_with_tokens(client, access_token, refresh_token).await
+}
 
-    // We should have the session_id cookie by now
-    let session_id = client
-        .get_cookie_value(STEAM_COMMUNITY_HOST, SESSION_ID_COOKIE)
-        .unwrap();
+/// Logs in through the Steam website with an access token and refresh token pair obtained through
+/// `IAuthenticationService`, storing the same session cookies as [`login_and_store_cookies`].
+///
+/// The tokens do not need to come from this crate: a `steam-client` CM session can run the same
+/// `Authentication` calls and hand its tokens here, so both sessions share one credential flow.
+pub async fn login_with_tokens(
+    client: &MobileClient,
+    access_token: String,
+    refresh_token: String,
+) -> Result<SteamCache, LoginError> {
+    let session_id = session_id(client)?;
     let finalize_payload = FinalizeLoginRequest::new(refresh_token.clone(), session_id);
 
     let finalize_login_response = client
@@ -210,6 +206,32 @@ pub async fn login_and_store_cookies(client: &MobileClient, user: Arc<dyn IsUser
     Ok(SteamCache::with_login_data(&steam_id, access_token, refresh_token).expect("Safe to unwrap"))
 }
 
+/// Returns the `sessionid` cookie of Steam Community, making one up if we have none.
+///
+/// Steam sets it on the first requests of a credentials login, but a client only handed tokens never made those.
+/// Steam accepts any random value, as long as it is sent both as the cookie and in the form.
+fn session_id(client: &MobileClient) -> Result<String, LoginError> {
+    let existing = client
+        .get_cookie_value(STEAM_COMMUNITY_HOST, SESSION_ID_COOKIE)
+        .filter(|session_id| !session_id.is_empty());
+    if let Some(session_id) = existing {
+        return Ok(session_id);
+    }
+
+    let mut random_bytes = [0u8; 12];
+    thread_rng().fill_bytes(&mut random_bytes);
+    client.set_cookie_value(
+        Cookie::build(SESSION_ID_COOKIE, hex::encode(random_bytes))
+            .domain(STEAM_COMMUNITY_HOST)
+            .finish(),
+    );
+
+    client
+        .get_cookie_value(STEAM_COMMUNITY_HOST, SESSION_ID_COOKIE)
+        .filter(|session_id| !session_id.is_empty())
+        .ok_or_else(|| LoginError::GeneralFailure("Could not set a session id cookie.".to_string()))
+}
+
 /// Calls multiple Steam Domains and set cookies for them.
 pub async fn set_cookies_on_steam_domains(
     client: &MobileClient,
@@ -229,3 +251,23 @@ pub async fn set_cookies_on_steam_domains(
     debug!("Setting tokens for all Steam domains..");
     try_join_all(futures).await.map(|_| ())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_clients_get_a_session_id() {
+        let client = MobileClient::default();
+        let missing = client.get_cookie_value(STEAM_COMMUNITY_HOST, SESSION_ID_COOKIE);
+        assert!(missing.unwrap_or_default().is_empty());
+
+        let first = session_id(&client).unwrap();
+        assert_eq!(first.len(), 24);
+        assert_eq!(
+            client.get_cookie_value(STEAM_COMMUNITY_HOST, SESSION_ID_COOKIE),
+            Some(first.clone())
+        );
+        assert_eq!(session_id(&client).unwrap(), first);
+    }
+}
@@ -2,6 +2,8 @@ pub mod error;
 pub mod protobufs;
 
 use protobuf::*;
+pub use protobuf::Message;
+pub use protobuf::UnknownValueRef;
 pub use protobuf_json_mapping::ParseError;
 pub use protobuf_json_mapping::PrintError;
 pub use protobuf_message::ProtobufDeserialize;